@@ -0,0 +1,94 @@
+//! Benchmarks of parsing and compiling generated programs. Run them with
+//! `cargo bench --features bench`.
+
+use banana::{
+    compile::compile_diagnostics, db::Database, generate::generate_program, ir::SourceProgram,
+    parser::parse_statements,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// Parsing a program from scratch, by its number of functions, with as many
+/// statements.
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_statements");
+    for size in [100, 1_000] {
+        let source_text = generate_program(size, size);
+        group.throughput(Throughput::Bytes(source_text.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &source_text,
+            |b, text| {
+                b.iter(|| {
+                    let db = Database::default();
+                    let source_program = SourceProgram::new(&db, text.clone());
+                    parse_statements(&db, source_program);
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Re-parsing a program after editing the body of one of its functions.
+fn reparse_after_edit(c: &mut Criterion) {
+    let source_text = generate_program(1_000, 1_000);
+    let edited_text = source_text.replacen("x * 500 ", "x * 5000 ", 1);
+    assert_ne!(source_text, edited_text);
+
+    let mut db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.clone());
+    parse_statements(&db, source_program);
+    let mut edited = false;
+    c.bench_function("reparse_after_edit", |b| {
+        b.iter(|| {
+            // Alternate between the two versions, so each run is an edit.
+            edited = !edited;
+            let text = if edited { &edited_text } else { &source_text };
+            source_program.set_text(&mut db).to(text.clone());
+            parse_statements(&db, source_program);
+        })
+    });
+}
+
+/// Compiling (parsing and type checking) a program of 5,000 lines: from
+/// scratch, again after setting the same text, and after changing one
+/// character in the body of a function.
+fn recompile(c: &mut Criterion) {
+    let source_text = generate_program(2_500, 2_500);
+    let edited_text = source_text.replacen("x * 1250 ", "x * 1259 ", 1);
+    assert_eq!(source_text.lines().count(), 5_000);
+    assert_ne!(source_text, edited_text);
+
+    let mut group = c.benchmark_group("recompile");
+    group.bench_function("cold", |b| {
+        b.iter(|| {
+            let db = Database::default();
+            let source_program = SourceProgram::new(&db, source_text.clone());
+            compile_diagnostics(&db, source_program);
+        })
+    });
+
+    let mut db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.clone());
+    compile_diagnostics(&db, source_program);
+    group.bench_function("warm_no_op", |b| {
+        b.iter(|| {
+            source_program.set_text(&mut db).to(source_text.clone());
+            compile_diagnostics(&db, source_program);
+        })
+    });
+
+    let mut edited = false;
+    group.bench_function("single_char_edit", |b| {
+        b.iter(|| {
+            edited = !edited;
+            let text = if edited { &edited_text } else { &source_text };
+            source_program.set_text(&mut db).to(text.clone());
+            compile_diagnostics(&db, source_program);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse, reparse_after_edit, recompile);
+criterion_main!(benches);
@@ -0,0 +1,102 @@
+/// A function provided by the language rather than defined in the program.
+pub struct Builtin {
+    pub name: &'static str,
+    /// The names of the parameters, for signatures. Arguments can't be
+    /// passed by name.
+    pub params: &'static [&'static str],
+    /// Whether any number of arguments is accepted, in place of `params`.
+    pub variadic: bool,
+    /// Computes the result, or explains why the arguments are outside the
+    /// function's domain.
+    pub eval: fn(&[f64]) -> Result<f64, &'static str>,
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "sqrt",
+        params: &["x"],
+        variadic: false,
+        eval: |args| Ok(args[0].sqrt()),
+    },
+    Builtin {
+        name: "abs",
+        params: &["x"],
+        variadic: false,
+        eval: |args| Ok(args[0].abs()),
+    },
+    Builtin {
+        name: "min",
+        params: &["a", "b"],
+        variadic: false,
+        eval: |args| Ok(args[0].min(args[1])),
+    },
+    Builtin {
+        name: "max",
+        params: &["a", "b"],
+        variadic: false,
+        eval: |args| Ok(args[0].max(args[1])),
+    },
+    Builtin {
+        name: "pow",
+        params: &["base", "exponent"],
+        variadic: false,
+        eval: |args| Ok(args[0].powf(args[1])),
+    },
+    Builtin {
+        name: "log",
+        params: &["x"],
+        variadic: false,
+        eval: |args| {
+            if args[0] > 0.0 {
+                Ok(args[0].ln())
+            } else {
+                Err("the logarithm is only defined for positive numbers")
+            }
+        },
+    },
+    Builtin {
+        name: "sin",
+        params: &["x"],
+        variadic: false,
+        eval: |args| Ok(args[0].sin()),
+    },
+    Builtin {
+        name: "cos",
+        params: &["x"],
+        variadic: false,
+        eval: |args| Ok(args[0].cos()),
+    },
+    Builtin {
+        name: "sum",
+        params: &[],
+        variadic: true,
+        eval: |args| Ok(args.iter().sum()),
+    },
+];
+
+impl Builtin {
+    /// The number of arguments, or `None` if any number is accepted.
+    pub fn arity(&self) -> Option<usize> {
+        (!self.variadic).then_some(self.params.len())
+    }
+
+    /// Whether the function can be called with `given` arguments.
+    pub fn accepts(&self, given: usize) -> bool {
+        self.arity().is_none_or(|arity| arity == given)
+    }
+}
+
+pub fn find_builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+#[test]
+fn builtin_domains() {
+    let call = |name, args: &[f64]| (find_builtin(name).unwrap().eval)(args);
+    assert_eq!(call("pow", &[2.0, 3.0]), Ok(8.0));
+    assert_eq!(call("log", &[1.0]), Ok(0.0));
+    assert!(call("log", &[0.0]).is_err());
+    assert!(call("log", &[-1.0]).is_err());
+    assert_eq!(call("sum", &[]), Ok(0.0));
+    assert_eq!(call("sum", &[1.0, 2.0, 3.0]), Ok(6.0));
+}
@@ -0,0 +1,538 @@
+//! A bytecode backend: functions are compiled into chunks of stack machine
+//! instructions, which a VM runs. It must behave exactly like the
+//! tree-walking evaluator in `eval`, and shares its runtime error reporting.
+
+use std::fmt::Write;
+
+use crate::eval::{resolve_callee, Callee, EvalOptions, EvalOutput, Runtime};
+use crate::ir::{
+    Diagnostic, ExprArena, ExprId, ExpressionData, Function, FunctionId, Op, Program, Span,
+    StatementData, VariableId,
+};
+use crate::type_check::{argument_order, call_arguments, find_function};
+use crate::value::Value;
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Instr {
+    PushConst(Value),
+    /// Pushes the argument with the given index of the current function.
+    LoadArg(usize),
+    /// Fails with an error: the variable isn't a parameter of the function.
+    Undeclared(VariableId),
+    /// Pops the right operand, then the left one, and pushes the result.
+    Op(Op),
+    Neg,
+    Abs,
+    /// Pops the arguments, pushed first to last, and pushes the result.
+    Call(FunctionId, usize),
+    /// Calls a lambda with the given parameters and compiled body, like
+    /// `Call`.
+    CallLambda(Vec<VariableId>, Box<Chunk>, usize),
+    /// Fails with the message of `error(...)`.
+    Raise(String),
+    /// Prints the value on top of the stack, leaving it there.
+    Print,
+    /// Returns the top of the stack from the current function.
+    Return,
+}
+
+/// Compiled code, with the span of the expression each instruction came
+/// from.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct Chunk {
+    pub instrs: Vec<Instr>,
+    pub spans: Vec<Span>,
+}
+
+impl Chunk {
+    fn push(&mut self, instr: Instr, span: Span) {
+        self.instrs.push(instr);
+        self.spans.push(span);
+    }
+
+    /// Appends one line per instruction to `out`: its index, opcode and
+    /// operands, and the source span it came from.
+    fn disassemble(&self, db: &dyn crate::Db, program: Program, out: &mut String) {
+        for (index, (instr, span)) in self.instrs.iter().zip(&self.spans).enumerate() {
+            let (start, end) = span.absolute(db, program);
+            writeln!(out, "{index:>4}  {:<28} {start}..{end}", instr.display(db)).unwrap();
+            // The body of a lambda is listed under the call, indented.
+            if let Instr::CallLambda(_, body, _) = instr {
+                let mut listing = String::new();
+                body.disassemble(db, program, &mut listing);
+                for line in listing.lines() {
+                    writeln!(out, "      {line}").unwrap();
+                }
+            }
+        }
+    }
+}
+
+impl Instr {
+    /// The opcode followed by its operands, with names resolved.
+    fn display(&self, db: &dyn crate::Db) -> String {
+        match self {
+            Instr::PushConst(value) => format!("push_const {value}"),
+            Instr::LoadArg(index) => format!("load_arg {index}"),
+            Instr::Undeclared(v) => format!("undeclared {}", v.text(db)),
+            Instr::Op(op) => format!("op {op}"),
+            Instr::Neg => "neg".to_string(),
+            Instr::Abs => "abs".to_string(),
+            Instr::Call(f, argc) => format!("call {} {argc}", f.text(db)),
+            Instr::CallLambda(params, _, argc) => {
+                let params: Vec<_> = params.iter().map(|param| param.text(db).as_str()).collect();
+                format!("call_lambda ({}) {argc}", params.join(", "))
+            }
+            Instr::Raise(message) => format!("raise {message:?}"),
+            Instr::Print => "print".to_string(),
+            Instr::Return => "return".to_string(),
+        }
+    }
+}
+
+/// A human-readable listing of the program's bytecode (`--emit bytecode`):
+/// each function, then each top-level statement.
+pub fn disassemble_program(db: &dyn crate::Db, program: Program) -> String {
+    let mut out = String::new();
+    for function in program.functions(db) {
+        writeln!(out, "fn {}:", function.name(db).text(db)).unwrap();
+        compile_function_bytecode(db, *function, program).disassemble(db, program, &mut out);
+    }
+    for (index, chunk) in compile_program_bytecode(db, program).iter().enumerate() {
+        writeln!(out, "statement {index}:").unwrap();
+        chunk.disassemble(db, program, &mut out);
+    }
+    out
+}
+
+#[salsa::tracked(return_ref)]
+pub fn compile_function_bytecode(
+    db: &dyn crate::Db,
+    function: Function,
+    program: Program,
+) -> Chunk {
+    let data = function.data(db);
+    let mut chunk = Chunk::default();
+    let arena = data.arena(db);
+    compile_expression(db, program, &mut chunk, arena, &data.args, data.body);
+    chunk.push(Instr::Return, arena[data.body].span);
+    chunk
+}
+
+/// One chunk for each top-level statement, so that a statement failing at
+/// runtime doesn't prevent the next ones from running.
+#[salsa::tracked(return_ref)]
+pub fn compile_program_bytecode(db: &dyn crate::Db, program: Program) -> Vec<Chunk> {
+    program
+        .statements(db)
+        .iter()
+        .map(|statement| {
+            let mut chunk = Chunk::default();
+            match &statement.data {
+                StatementData::Print(expression) => {
+                    compile_expression(db, program, &mut chunk, &statement.arena, &[], *expression);
+                    chunk.push(Instr::Print, statement.span);
+                }
+            }
+            chunk
+        })
+        .collect()
+}
+
+/// Appends the instructions computing the expression `id` of `arena` to
+/// `chunk`, resolving calls in `program`.
+fn compile_expression(
+    db: &dyn crate::Db,
+    program: Program,
+    chunk: &mut Chunk,
+    arena: &ExprArena,
+    args: &[VariableId],
+    id: ExprId,
+) {
+    let span = arena[id].span;
+    match &arena[id].data {
+        ExpressionData::Op(left, op, right) => {
+            compile_expression(db, program, chunk, arena, args, *left);
+            compile_expression(db, program, chunk, arena, args, *right);
+            chunk.push(Instr::Op(*op), span);
+        }
+        ExpressionData::Neg(operand) => {
+            compile_expression(db, program, chunk, arena, args, *operand);
+            chunk.push(Instr::Neg, span);
+        }
+        ExpressionData::Abs(operand) => {
+            compile_expression(db, program, chunk, arena, args, *operand);
+            chunk.push(Instr::Abs, span);
+        }
+        ExpressionData::Number(n) => chunk.push(Instr::PushConst(Value::Number(*n)), span),
+        ExpressionData::Bool(b) => chunk.push(Instr::PushConst(Value::Bool(*b)), span),
+        ExpressionData::Variable(v) => match args.iter().position(|arg| arg == v) {
+            Some(index) => chunk.push(Instr::LoadArg(index), span),
+            None => chunk.push(Instr::Undeclared(*v), span),
+        },
+        ExpressionData::Call(f, call_args) => {
+            // A call which can't be resolved fails before its arguments are
+            // evaluated, as in the AST evaluator, so they aren't compiled:
+            // `Call` fails without popping them.
+            if resolve_callee(db, Some(program), *f, call_args.len()).is_err() {
+                chunk.push(Instr::Call(*f, call_args.len()), span);
+                return;
+            }
+            let order = match call_arguments(db, program, *f, arena, call_args) {
+                Ok(bound) => argument_order(&bound, call_args),
+                Err((span, message)) => {
+                    chunk.push(Instr::Raise(message), span);
+                    return;
+                }
+            };
+            // Arguments are evaluated in the order they're written.
+            for arg in call_args {
+                compile_expression(db, program, chunk, arena, args, arg.value);
+            }
+            if order
+                .iter()
+                .enumerate()
+                .all(|(param, &index)| param == index)
+            {
+                chunk.push(Instr::Call(*f, order.len()), span);
+                return;
+            }
+            // Named arguments out of the parameters' order are passed to a
+            // lambda, which calls the function with them in order: as
+            // `(\y x -> f(x, y))(y = ..., x = ...)`.
+            let params = &find_function(db, program, *f).unwrap().data(db).args;
+            let mut lambda_params = params.clone();
+            for (param, &index) in order.iter().enumerate() {
+                lambda_params[index] = params[param];
+            }
+            let mut lambda = Chunk::default();
+            for &index in &order {
+                lambda.push(Instr::LoadArg(index), span);
+            }
+            lambda.push(Instr::Call(*f, order.len()), span);
+            lambda.push(Instr::Return, span);
+            chunk.push(
+                Instr::CallLambda(lambda_params, Box::new(lambda), order.len()),
+                span,
+            );
+        }
+        ExpressionData::Lambda(params, body, call_args) => {
+            for arg in call_args {
+                compile_expression(db, program, chunk, arena, args, *arg);
+            }
+            let mut lambda = Chunk::default();
+            compile_expression(db, program, &mut lambda, arena, params, *body);
+            lambda.push(Instr::Return, arena[*body].span);
+            chunk.push(
+                Instr::CallLambda(params.clone(), Box::new(lambda), call_args.len()),
+                span,
+            );
+        }
+        ExpressionData::Let(name, value, body) => {
+            // The body runs like a lambda, called with the variables in scope
+            // and the value. A shadowed variable isn't passed on.
+            let mut scope = vec![];
+            for (index, arg) in args.iter().enumerate() {
+                if arg != name {
+                    chunk.push(Instr::LoadArg(index), span);
+                    scope.push(*arg);
+                }
+            }
+            compile_expression(db, program, chunk, arena, args, *value);
+            scope.push(*name);
+            let mut lambda = Chunk::default();
+            compile_expression(db, program, &mut lambda, arena, &scope, *body);
+            lambda.push(Instr::Return, arena[*body].span);
+            let argc = scope.len();
+            chunk.push(Instr::CallLambda(scope, Box::new(lambda), argc), span);
+        }
+        ExpressionData::Print(operand) => {
+            compile_expression(db, program, chunk, arena, args, *operand);
+            chunk.push(Instr::Print, span);
+        }
+        ExpressionData::Error(message) => chunk.push(Instr::Raise(message.clone()), span),
+    }
+}
+
+/// Runs the program's top-level statements on the VM. Produces the same
+/// output and diagnostics as `eval::eval_program`, except that the
+/// `memoize` and `cse` options are ignored.
+#[salsa::tracked(return_ref)]
+pub fn run_bytecode(db: &dyn crate::Db, program: Program, options: EvalOptions) -> EvalOutput {
+    let mut vm = Vm {
+        runtime: Runtime::new(db, Some(program), options),
+        stack: vec![],
+    };
+    for chunk in compile_program_bytecode(db, program) {
+        vm.runtime.stats.statements += 1;
+        if let Err(diagnostic) = vm.run(chunk) {
+            vm.runtime.report(Some(diagnostic));
+            if vm.runtime.math_failed {
+                break;
+            }
+        }
+    }
+    EvalOutput {
+        lines: vm.runtime.output,
+        stats: vm.runtime.stats,
+    }
+}
+
+struct Vm<'db> {
+    runtime: Runtime<'db>,
+    stack: Vec<Value>,
+}
+
+/// A user function call being executed.
+struct Frame<'db> {
+    chunk: &'db Chunk,
+    ip: usize,
+    /// The index of the first argument on the stack.
+    base: usize,
+    /// Whether this is a lambda's frame rather than a user function's,
+    /// which `Runtime` keeps track of.
+    lambda: bool,
+}
+
+impl<'db> Vm<'db> {
+    /// Runs a top-level chunk. Calls don't recurse on the Rust stack, so the
+    /// call depth is only limited by `EvalOptions::max_depth`.
+    fn run(&mut self, chunk: &'db Chunk) -> Result<(), Diagnostic> {
+        let db = self.runtime.db;
+        let mut frames = vec![Frame {
+            chunk,
+            ip: 0,
+            base: 0,
+            lambda: false,
+        }];
+        let result = loop {
+            let frame = frames.last_mut().unwrap();
+            let chunk = frame.chunk;
+            let Some(instr) = chunk.instrs.get(frame.ip) else {
+                break Ok(());
+            };
+            let span = chunk.spans[frame.ip];
+            frame.ip += 1;
+            match instr {
+                Instr::PushConst(value) => self.stack.push(value.clone()),
+                Instr::LoadArg(index) => self.stack.push(self.stack[frame.base + index].clone()),
+                Instr::Undeclared(v) => break Err(self.runtime.undeclared_variable(*v, span)),
+                Instr::Op(op) => {
+                    let right = self.stack.pop().unwrap();
+                    let left = self.stack.pop().unwrap();
+                    match self.runtime.apply_op(*op, &left, &right, span, None) {
+                        Ok(result) => self.stack.push(result),
+                        Err(diagnostic) => break Err(diagnostic),
+                    }
+                }
+                Instr::Neg => {
+                    let operand = self.stack.pop().unwrap();
+                    match self.runtime.expect_number(&operand, span) {
+                        Ok(n) => self.stack.push(Value::from(-n)),
+                        Err(diagnostic) => break Err(diagnostic),
+                    }
+                }
+                Instr::Abs => {
+                    let operand = self.stack.pop().unwrap();
+                    match self.runtime.expect_number(&operand, span) {
+                        Ok(n) => self.stack.push(Value::from(n.abs())),
+                        Err(diagnostic) => break Err(diagnostic),
+                    }
+                }
+                Instr::Call(f, argc) => {
+                    let callee = match self.runtime.resolve_call(*f, *argc, span) {
+                        Ok(callee) => callee,
+                        Err(diagnostic) => break Err(diagnostic),
+                    };
+                    let base = self.stack.len() - argc;
+                    match callee {
+                        Callee::User(function) => {
+                            if let Err(diagnostic) = self.runtime.enter_call(*f, span) {
+                                break Err(diagnostic);
+                            }
+                            // User functions are only found in a program.
+                            let program = self.runtime.program.unwrap();
+                            frames.push(Frame {
+                                chunk: compile_function_bytecode(db, function, program),
+                                ip: 0,
+                                base,
+                                lambda: false,
+                            });
+                        }
+                        Callee::Builtin(builtin) => {
+                            let args = self.stack.split_off(base);
+                            match self.runtime.call_builtin(builtin, *f, &args, span) {
+                                Ok(result) => self.stack.push(result),
+                                Err(diagnostic) => break Err(diagnostic),
+                            }
+                        }
+                    }
+                }
+                Instr::CallLambda(params, body, argc) => {
+                    if let Err(diagnostic) =
+                        self.runtime.check_lambda_arity(params.len(), *argc, span)
+                    {
+                        break Err(diagnostic);
+                    }
+                    frames.push(Frame {
+                        chunk: body,
+                        ip: 0,
+                        base: self.stack.len() - argc,
+                        lambda: true,
+                    });
+                }
+                Instr::Raise(message) => break Err(self.runtime.raise(message, span)),
+                Instr::Print => {
+                    let value = self.stack.last().unwrap().clone();
+                    self.runtime.print(value, span);
+                }
+                Instr::Return => {
+                    let result = self.stack.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    self.stack.push(result);
+                    if !frame.lambda {
+                        self.runtime.exit_call();
+                    }
+                    frames.pop();
+                }
+            }
+        };
+        // Unwind the calls left after an error.
+        for frame in &frames[1..] {
+            if !frame.lambda {
+                self.runtime.exit_call();
+            }
+        }
+        self.stack.clear();
+        result
+    }
+}
+
+/// Runs the program on both backends, returning the output and the
+/// diagnostics' locations and severities of each.
+#[cfg(test)]
+fn run_both(source_text: &str, options: EvalOptions) -> [(EvalOutput, Vec<String>); 2] {
+    use crate::{
+        db::Database, eval::eval_program, ir::Diagnostics, ir::SourceProgram,
+        parser::parse_statements,
+    };
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = parse_statements(&db, source_program);
+    let summarize = |diagnostics: Vec<Diagnostic>| {
+        diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let related: Vec<_> = diagnostic.related.iter().map(|(span, _)| *span).collect();
+                format!(
+                    "{:?} {}..{} {related:?}",
+                    diagnostic.severity, diagnostic.start, diagnostic.end
+                )
+            })
+            .collect()
+    };
+    [
+        (
+            eval_program(&db, program, options).clone(),
+            summarize(eval_program::accumulated::<Diagnostics>(
+                &db, program, options,
+            )),
+        ),
+        (
+            run_bytecode(&db, program, options).clone(),
+            summarize(run_bytecode::accumulated::<Diagnostics>(
+                &db, program, options,
+            )),
+        ),
+    ]
+}
+
+#[test]
+fn bytecode_matches_evaluator() {
+    let programs = [
+        "
+            fn area_rectangle(w, h) = w * h;
+            fn area_circle(r) = 3.14 * r * r;
+            print area_rectangle(3, 4);
+            print area_circle(1);
+            print 11 * 2;
+        ",
+        "
+            fn avg(x, n) = x / n;
+            fn half(x) = avg(x, 2);
+            print half(avg(10, 2));
+            print half(half(half(2)));
+            print -avg(1, 0) - sqrt(-1);
+        ",
+        "fn f(x) = y + f(x); print f(1); print g(1); print max(1); print 2; print log(0);",
+        "fn f(x) = 1 / x; print 1; print f(0); print 2;",
+        "fn f(x) = g(x) * 2; fn g(x) = -error(\"g\"); print 1; print f(2); print 3;",
+        r"fn f(x) = (\(a, b) -> a / b)(x, 0); print f(1); print (\(x) -> x)(1, 2); print 3;",
+        "fn f(x, y) = let x = x * y in 1 + (let y = x - y in x / y); print f(3, 2); print f(1, 1);",
+        "fn f(x) = print x / 0; print (print 1) + 1; print f(2) * (print f(0)); print 3;",
+        "fn f(x, y) = x / y; fn g(x) = f(y = x, x = 1); print g(2); print f(1, x = 2); print 3;",
+        "fn f(x, y) = x - y; fn g(x) = f(y = print x, x = print 2 * x); print g(1); print f(y = 1, x = 2);",
+        // The callee is resolved before the arguments are evaluated.
+        "print g(1 / 0); print sqrt(print 1, 0 / 0); print 2;",
+    ];
+    for program in programs {
+        let [evaluated, compiled] = run_both(program, EvalOptions::default());
+        assert_eq!(evaluated, compiled, "{program}");
+        let strict = EvalOptions {
+            strict_math: true,
+            max_depth: 3,
+            ..EvalOptions::default()
+        };
+        let [evaluated, compiled] = run_both(program, strict);
+        assert_eq!(evaluated, compiled, "{program}");
+    }
+}
+
+#[cfg(test)]
+fn disassemble_string(source_text: &str) -> String {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    disassemble_program(&db, parse_statements(&db, source_program))
+}
+
+#[test]
+fn disassemble_area_rectangle() {
+    let expected = expect_test::expect![[r#"
+        fn area_rectangle:
+           0  load_arg 0                   26..27
+           1  load_arg 1                   30..31
+           2  op *                         26..31
+           3  return                       26..31
+        statement 0:
+           0  push_const 3                 54..55
+           1  push_const 4                 57..58
+           2  call area_rectangle 2        39..59
+           3  print                        33..60
+    "#]];
+    expected.assert_eq(&disassemble_string(
+        "fn area_rectangle(w, h) = w * h;\nprint area_rectangle(3, 4);",
+    ));
+}
+
+#[test]
+fn disassemble_calls() {
+    let expected = expect_test::expect![[r#"
+        fn half:
+           0  load_arg 0                   13..14
+           1  push_const 2                 17..18
+           2  op /                         13..18
+           3  return                       13..18
+        statement 0:
+           0  undeclared y                 37..38
+           1  call sqrt 1                  32..39
+           2  call half 1                  27..40
+           3  neg                          26..40
+           4  print                        20..41
+    "#]];
+    expected.assert_eq(&disassemble_string(
+        "fn half(x) = x / 2;\nprint -half(sqrt(y));",
+    ));
+}
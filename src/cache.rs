@@ -0,0 +1,301 @@
+//! An on-disk cache of type checking results (`--cache` on the command
+//! line), so that a fresh run only type checks the functions which changed
+//! since the last one.
+//!
+//! A function's entry is keyed by a hash of its text together with the
+//! signatures of every function in the program, as checking a call depends
+//! on the callee's parameters. Diagnostics are stored with their spans
+//! relative to the function, so moving a function doesn't invalidate it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+
+use crate::ir::{DefIdData, Diagnostic, Diagnostics, Function, Program, Severity, Span};
+use crate::parser::{parse_item, split_into_items};
+use crate::raw::{escape, unescape};
+use crate::signature::{signature, FunctionSignature};
+use crate::type_check::type_check_function;
+
+/// The file the cache is kept in, in the current directory.
+pub const CACHE_FILE: &str = ".banana-cache";
+
+/// The first line of a cache file. Files with another header are ignored.
+const HEADER: &str = "banana-cache 1";
+
+/// What type checking each function of a program found.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct CheckCache {
+    /// By function name and hash.
+    functions: BTreeMap<(String, u64), CachedFunction>,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+struct CachedFunction {
+    signature: FunctionSignature,
+    diagnostics: Vec<CachedDiagnostic>,
+}
+
+/// A diagnostic of a function, with its related spans, all relative to the
+/// function's start.
+#[derive(Eq, PartialEq, Clone, Debug)]
+struct CachedDiagnostic {
+    severity: Severity,
+    start: usize,
+    end: usize,
+    message: String,
+    related: Vec<(usize, usize, String)>,
+}
+
+impl CheckCache {
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// Reads a cache written by `to_text`. Returns `None` if the text isn't
+    /// one, e.g. if it was written by another version.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next()? != HEADER {
+            return None;
+        }
+        let mut cache = CheckCache::default();
+        let mut current: Option<&mut CachedFunction> = None;
+        for line in lines {
+            let (kind, rest) = line.split_once(' ')?;
+            match kind {
+                "fn" => {
+                    let mut fields = rest.split(' ');
+                    let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+                    let name = fields.next()?.to_string();
+                    let params = fields.map(str::to_string).collect();
+                    let entry = cache.functions.entry((name.clone(), hash));
+                    current = Some(entry.or_insert(CachedFunction {
+                        signature: FunctionSignature {
+                            name,
+                            params,
+                            variadic: false,
+                        },
+                        diagnostics: vec![],
+                    }));
+                }
+                "diagnostic" => {
+                    let (severity, rest) = rest.split_once(' ')?;
+                    let (start, end, message) = parse_span_message(rest)?;
+                    current.as_mut()?.diagnostics.push(CachedDiagnostic {
+                        severity: parse_severity(severity)?,
+                        start,
+                        end,
+                        message,
+                        related: vec![],
+                    });
+                }
+                "related" => {
+                    let diagnostic = current.as_mut()?.diagnostics.last_mut()?;
+                    diagnostic.related.push(parse_span_message(rest)?);
+                }
+                _ => return None,
+            }
+        }
+        Some(cache)
+    }
+
+    /// The cache as text, one line per function, diagnostic and related
+    /// span, with the messages quoted.
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{HEADER}\n");
+        for ((name, hash), function) in &self.functions {
+            out += &format!("fn {hash:016x} {name}");
+            for param in &function.signature.params {
+                out += &format!(" {param}");
+            }
+            out += "\n";
+            for diagnostic in &function.diagnostics {
+                let severity = match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                    Severity::Note => "note",
+                };
+                out += &format!(
+                    "diagnostic {severity} {}..{} \"{}\"\n",
+                    diagnostic.start,
+                    diagnostic.end,
+                    escape(&diagnostic.message)
+                );
+                for (start, end, message) in &diagnostic.related {
+                    out += &format!("related {start}..{end} \"{}\"\n", escape(message));
+                }
+            }
+        }
+        out
+    }
+}
+
+fn parse_span_message(text: &str) -> Option<(usize, usize, String)> {
+    let (span, message) = text.split_once(' ')?;
+    let (start, end) = span.split_once("..")?;
+    let message = message.strip_prefix('"')?.strip_suffix('"')?;
+    Some((start.parse().ok()?, end.parse().ok()?, unescape(message)))
+}
+
+fn parse_severity(severity: &str) -> Option<Severity> {
+    match severity {
+        "error" => Some(Severity::Error),
+        "warning" => Some(Severity::Warning),
+        "note" => Some(Severity::Note),
+        _ => None,
+    }
+}
+
+/// Type checks the functions of a program, replaying the diagnostics of
+/// those unchanged since `previous` instead of checking them again, and
+/// recording every function's diagnostics for the next run.
+pub struct CachedCheck<'a> {
+    previous: &'a CheckCache,
+    /// The entries of the functions checked so far.
+    pub next: CheckCache,
+}
+
+impl<'a> CachedCheck<'a> {
+    pub fn new(previous: &'a CheckCache) -> Self {
+        Self {
+            previous,
+            next: CheckCache::default(),
+        }
+    }
+
+    /// The type errors of `function`, for `compile_diagnostics_with`.
+    pub fn check(
+        &mut self,
+        db: &dyn crate::Db,
+        function: Function,
+        program: Program,
+    ) -> Vec<Diagnostic> {
+        let Some(hash) = function_hash(db, function, program) else {
+            return type_check_function::accumulated::<Diagnostics>(db, function, program);
+        };
+        let key = (function.name(db).text(db).clone(), hash);
+        // The spans of a replayed diagnostic are in the function.
+        let id = function.data(db).name_span.id;
+        if let Some(cached) = self.previous.functions.get(&key) {
+            self.next.functions.insert(key, cached.clone());
+            return cached
+                .diagnostics
+                .iter()
+                .map(|cached| {
+                    let mut diagnostic =
+                        Diagnostic::new(cached.start, cached.end, cached.message.clone());
+                    diagnostic.severity = cached.severity;
+                    for (start, end, message) in &cached.related {
+                        diagnostic =
+                            diagnostic.with_related(Span::new(id, *start, *end), message.clone());
+                    }
+                    diagnostic
+                })
+                .collect();
+        }
+        let diagnostics = type_check_function::accumulated::<Diagnostics>(db, function, program);
+        // A related span in another function, e.g. a callee's definition,
+        // may move without this function changing, so isn't cached.
+        let cacheable = diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.related.iter().all(|(span, _)| span.id == id));
+        if cacheable {
+            let cached = CachedFunction {
+                signature: signature(db, function).clone(),
+                diagnostics: diagnostics
+                    .iter()
+                    .map(|diagnostic| CachedDiagnostic {
+                        severity: diagnostic.severity,
+                        start: diagnostic.start,
+                        end: diagnostic.end,
+                        message: diagnostic.message.clone(),
+                        related: diagnostic
+                            .related
+                            .iter()
+                            .map(|(span, message)| (span.start, span.end, message.clone()))
+                            .collect(),
+                    })
+                    .collect(),
+            };
+            self.next.functions.insert(key, cached);
+        }
+        diagnostics
+    }
+}
+
+/// A hash of the text of `function` and the signatures of all functions
+/// in `program`, or `None` if its text can't be found.
+fn function_hash(db: &dyn crate::Db, function: Function, program: Program) -> Option<u64> {
+    let DefIdData::Function(file, _) = function.data(db).name_span.id.data(db) else {
+        return None;
+    };
+    let item = split_into_items(db, file).iter().find(|item| {
+        parse_item(db, item.text)
+            .is_some_and(|parsed| item.start + parsed.start(db) == function.start(db))
+    })?;
+    let mut hasher = DefaultHasher::new();
+    item.text.text(db).hash(&mut hasher);
+    for function in program.functions(db) {
+        signature(db, *function).hash(&mut hasher);
+    }
+    Some(hasher.finish())
+}
+
+/// Compiles `source_text` in a new database, with the cache of a previous
+/// run. Returns the diagnostics' messages, the functions type checked, and
+/// the cache for the next run.
+#[cfg(test)]
+fn cached_run(source_text: &str, previous: &CheckCache) -> (Vec<String>, Vec<String>, CheckCache) {
+    use crate::{compile::compile_diagnostics_with, db::Database, ir::SourceProgram};
+
+    let mut db = Database::builder().logging(true).build();
+    let source = SourceProgram::new(&db, source_text.to_string());
+    let mut check = CachedCheck::new(previous);
+    let diagnostics = compile_diagnostics_with(&db, source, |function, program| {
+        check.check(&db, function, program)
+    });
+    let messages = diagnostics
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect();
+    let checked = db
+        .take_logs()
+        .executed_queries()
+        .into_iter()
+        .filter(|query| query.starts_with("type_check_function("))
+        .collect();
+    (messages, checked, check.next)
+}
+
+#[test]
+fn cache_round_trip() {
+    let (_, _, cache) = cached_run("fn f(x) = x + true;\nfn g(y) = 1;", &CheckCache::default());
+    assert_eq!(cache.len(), 2);
+    assert_eq!(CheckCache::parse(&cache.to_text()), Some(cache));
+    assert_eq!(CheckCache::parse("banana-cache 0\n"), None);
+}
+
+#[test]
+fn cache_checks_only_changed_functions() {
+    let source_text = "fn f(x) = x + true;\nfn g(y) = y * 2;\nfn h(z) = g(z);";
+    let (first, checked, cache) = cached_run(source_text, &CheckCache::default());
+    assert_eq!(checked.len(), 3);
+    let cache = CheckCache::parse(&cache.to_text()).unwrap();
+
+    // `g`'s body changes, and `f` moves: only `g` is checked again, and
+    // `f`'s error is replayed from the cache.
+    let edited = "\n\nfn f(x) = x + true;\nfn g(y) = y * 3;\nfn h(z) = g(z);";
+    let (second, checked, _) = cached_run(edited, &cache);
+    assert_eq!(second, first);
+    assert_eq!(checked.len(), 1);
+
+    // A new parameter of `g` changes the signatures, so every function is
+    // checked again.
+    let (_, checked, _) = cached_run(&source_text.replace("g(y)", "g(y, w)"), &cache);
+    assert_eq!(checked.len(), 3);
+}
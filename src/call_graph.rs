@@ -0,0 +1,204 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    ir::{Diagnostic, Diagnostics, ExprArena, ExprId, ExpressionData, Function, Program},
+    type_check::find_function,
+};
+
+/// The user functions called directly from the body of `function`, in order
+/// of their first call. Calls to builtins and undeclared functions are left
+/// out.
+#[salsa::tracked(return_ref)]
+pub fn callees(db: &dyn crate::Db, function: Function, program: Program) -> Vec<Function> {
+    let data = function.data(db);
+    let mut callees = vec![];
+    collect_callees(db, program, data.arena(db), data.body, &mut callees);
+    callees
+}
+
+fn collect_callees(
+    db: &dyn crate::Db,
+    program: Program,
+    arena: &ExprArena,
+    id: ExprId,
+    callees: &mut Vec<Function>,
+) {
+    match &arena[id].data {
+        ExpressionData::Op(left, _, right) => {
+            collect_callees(db, program, arena, *left, callees);
+            collect_callees(db, program, arena, *right, callees);
+        }
+        ExpressionData::Neg(operand)
+        | ExpressionData::Abs(operand)
+        | ExpressionData::Print(operand) => collect_callees(db, program, arena, *operand, callees),
+        ExpressionData::Number(_)
+        | ExpressionData::Bool(_)
+        | ExpressionData::Variable(_)
+        | ExpressionData::Error(_) => {}
+        ExpressionData::Call(f, args) => {
+            if let Some(callee) = find_function(db, program, *f) {
+                if !callees.contains(&callee) {
+                    callees.push(callee);
+                }
+            }
+            for arg in args {
+                collect_callees(db, program, arena, arg.value, callees);
+            }
+        }
+        ExpressionData::Lambda(_, body, args) => {
+            collect_callees(db, program, arena, *body, callees);
+            for arg in args {
+                collect_callees(db, program, arena, *arg, callees);
+            }
+        }
+        ExpressionData::Let(_, value, body) => {
+            collect_callees(db, program, arena, *value, callees);
+            collect_callees(db, program, arena, *body, callees);
+        }
+    }
+}
+
+/// The maximum number of nested user function frames a call can need,
+/// counting the called function itself.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Debug)]
+pub enum CallDepth {
+    Bounded(usize),
+    /// The function can reach a recursive call.
+    Unbounded,
+}
+
+impl CallDepth {
+    fn plus_one(self) -> Self {
+        match self {
+            CallDepth::Bounded(depth) => CallDepth::Bounded(depth + 1),
+            CallDepth::Unbounded => CallDepth::Unbounded,
+        }
+    }
+}
+
+impl fmt::Display for CallDepth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallDepth::Bounded(depth) => write!(f, "{depth}"),
+            CallDepth::Unbounded => write!(f, "unbounded"),
+        }
+    }
+}
+
+/// The call depth of every function in the program, computed as the longest
+/// path in the call graph. Functions on or reaching a cycle are unbounded.
+#[salsa::tracked(return_ref)]
+pub fn call_depths(db: &dyn crate::Db, program: Program) -> Vec<(Function, CallDepth)> {
+    let mut depths = HashMap::new();
+    for function in program.functions(db) {
+        call_depth(db, program, *function, &mut depths, &mut vec![]);
+    }
+    program
+        .functions(db)
+        .iter()
+        .map(|function| (*function, depths[function]))
+        .collect()
+}
+
+fn call_depth(
+    db: &dyn crate::Db,
+    program: Program,
+    function: Function,
+    depths: &mut HashMap<Function, CallDepth>,
+    stack: &mut Vec<Function>,
+) -> CallDepth {
+    if let Some(depth) = depths.get(&function) {
+        return *depth;
+    }
+    // A function already on the stack calls itself, possibly indirectly.
+    // Everything on the stack above it is part of the cycle, so it's fine to
+    // memoize them as unbounded on the way out.
+    if stack.contains(&function) {
+        return CallDepth::Unbounded;
+    }
+    stack.push(function);
+    let mut depth = CallDepth::Bounded(1);
+    for callee in callees(db, function, program) {
+        depth = depth.max(call_depth(db, program, *callee, depths, stack).plus_one());
+    }
+    stack.pop();
+    depths.insert(function, depth);
+    depth
+}
+
+/// The call depth above which `check_call_depth` warns by default.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 64;
+
+/// Warns about functions whose call depth exceeds `limit`.
+#[salsa::tracked]
+pub fn check_call_depth(db: &dyn crate::Db, program: Program, limit: usize) {
+    for (function, depth) in call_depths(db, program) {
+        if *depth > CallDepth::Bounded(limit) {
+            let span = function.data(db).name_span;
+            Diagnostics::push(
+                db,
+                Diagnostic::warning(
+                    span.start,
+                    span.end,
+                    format!(
+                        "the call depth of `{}` is {depth}, exceeding the limit of {limit}",
+                        function.name(db).text(db)
+                    ),
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+const CALL_GRAPH_PROGRAM: &str = "
+    fn a() = 1;
+    fn b() = a() + a();
+    fn c() = b() * a() + sqrt(2);
+    fn r(x) = r(x);
+    fn s() = r(1);
+";
+
+#[test]
+fn call_depths_of_program() {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, CALL_GRAPH_PROGRAM.to_string());
+    let program = parse_statements(&db, source_program);
+    let depths: Vec<_> = call_depths(&db, program)
+        .iter()
+        .map(|(function, depth)| format!("{}: {depth}", function.name(&db).text(&db)))
+        .collect();
+    let expected = expect_test::expect![[r#"
+        [
+            "a: 1",
+            "b: 2",
+            "c: 3",
+            "r: unbounded",
+            "s: unbounded",
+        ]
+    "#]];
+    expected.assert_debug_eq(&depths);
+}
+
+#[test]
+fn call_depth_limit() {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, CALL_GRAPH_PROGRAM.to_string());
+    let program = parse_statements(&db, source_program);
+    let messages: Vec<_> = check_call_depth::accumulated::<Diagnostics>(&db, program, 2)
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect();
+    let expected = expect_test::expect![[r#"
+        [
+            "the call depth of `c` is 3, exceeding the limit of 2",
+            "the call depth of `r` is unbounded, exceeding the limit of 2",
+            "the call depth of `s` is unbounded, exceeding the limit of 2",
+        ]
+    "#]];
+    expected.assert_debug_eq(&messages);
+}
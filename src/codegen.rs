@@ -0,0 +1,4 @@
+//! Compiling programs to source code in other languages.
+
+pub mod rust;
+pub mod wat;
@@ -0,0 +1,310 @@
+//! Compiles a program into a standalone Rust program (`--emit rust`).
+//!
+//! Each function becomes a Rust function on `f64`s and the `print`
+//! statements become `println!` calls in `main`. The program is expected to
+//! have type checked, and `--emit rust` refuses programs with errors: calls
+//! to undefined functions are emitted as is.
+
+use crate::ir::{ExprArena, ExprId, ExpressionData, FunctionId, Op, Program, StatementData};
+use crate::type_check::{argument_order, call_arguments, find_function};
+
+pub fn emit_program(db: &dyn crate::Db, program: Program) -> String {
+    let mut out = String::new();
+    for function in program.functions(db) {
+        let data = function.data(db);
+        let args: Vec<_> = data
+            .args
+            .iter()
+            .map(|arg| format!("{}: f64", mangle(arg.text(db))))
+            .collect();
+        out += &format!(
+            "fn {}({}) -> f64 {{\n    {}\n}}\n\n",
+            mangle(function.name(db).text(db)),
+            args.join(", "),
+            emit_expr(db, program, data.arena(db), data.body)
+        );
+    }
+    out += SHOW;
+    out += "fn main() {\n";
+    for statement in program.statements(db) {
+        match &statement.data {
+            StatementData::Print(expression) => {
+                out += &format!(
+                    "    println!(\"{{}}\", Show::show({}));\n",
+                    emit_expr(db, program, &statement.arena, *expression)
+                );
+            }
+        }
+    }
+    out += "}\n";
+    out
+}
+
+/// Formats values as `print` does, which for numbers isn't quite Rust's
+/// `Display`: zero is never `-0`, and NaN is `nan`.
+const SHOW: &str = "trait Show {
+    fn show(self) -> String;
+}
+
+impl Show for f64 {
+    fn show(self) -> String {
+        if self == 0.0 {
+            \"0\".to_string()
+        } else if self.is_nan() {
+            \"nan\".to_string()
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl Show for bool {
+    fn show(self) -> String {
+        self.to_string()
+    }
+}
+
+";
+
+/// Like `ir::display_expr`, only adds the parentheses needed to preserve
+/// the expression's structure. Rust's operators have the same precedence and
+/// associativity.
+fn emit_expr(db: &dyn crate::Db, program: Program, arena: &ExprArena, id: ExprId) -> String {
+    match &arena[id].data {
+        ExpressionData::Op(left, op, right) => {
+            let left = emit_operand(db, program, arena, *left, |inner| {
+                inner.precedence() < op.precedence()
+            });
+            let right = emit_operand(db, program, arena, *right, |inner| {
+                inner.precedence() <= op.precedence()
+            });
+            format!("{left} {op} {right}")
+        }
+        ExpressionData::Abs(operand) => {
+            format!("f64::abs({})", emit_expr(db, program, arena, *operand))
+        }
+        ExpressionData::Neg(operand) => {
+            format!("-{}", emit_operand(db, program, arena, *operand, |_| true))
+        }
+        // `Debug` always includes a decimal point or exponent, which makes
+        // the literal an `f64`.
+        ExpressionData::Number(n) => format!("{:?}", n.into_inner()),
+        ExpressionData::Bool(b) => b.to_string(),
+        ExpressionData::Variable(v) => mangle(v.text(db)),
+        ExpressionData::Call(f, args) => {
+            let order = match call_arguments(db, program, *f, arena, args) {
+                Ok(bound) => argument_order(&bound, args),
+                Err((_, message)) => return format!("panic!(\"{{}}\", {message:?})"),
+            };
+            let args: Vec<_> = args
+                .iter()
+                .map(|arg| emit_expr(db, program, arena, arg.value))
+                .collect();
+            if order
+                .iter()
+                .enumerate()
+                .any(|(param, &index)| param != index)
+            {
+                // Named arguments out of the parameters' order are evaluated
+                // in the order they're written, as the closure's arguments.
+                let params = &find_function(db, program, *f).unwrap().data(db).args;
+                let mut lambda_params = params.clone();
+                for (param, &index) in order.iter().enumerate() {
+                    lambda_params[index] = params[param];
+                }
+                let lambda_params: Vec<_> = lambda_params
+                    .iter()
+                    .map(|param| format!("{}: f64", mangle(param.text(db))))
+                    .collect();
+                let params: Vec<_> = params.iter().map(|param| mangle(param.text(db))).collect();
+                return format!(
+                    "(|{}| -> f64 {{ {}({}) }})({})",
+                    lambda_params.join(", "),
+                    callee(db, program, *f),
+                    params.join(", "),
+                    args.join(", ")
+                );
+            }
+            if f.text(db) == "sum" && find_function(db, program, *f).is_none() {
+                return if args.is_empty() {
+                    "0.0".to_string()
+                } else {
+                    format!("[{}].into_iter().sum::<f64>()", args.join(", "))
+                };
+            }
+            format!("{}({})", callee(db, program, *f), args.join(", "))
+        }
+        ExpressionData::Lambda(params, body, args) => {
+            let params: Vec<_> = params
+                .iter()
+                .map(|param| format!("{}: f64", mangle(param.text(db))))
+                .collect();
+            let args: Vec<_> = args
+                .iter()
+                .map(|&arg| emit_expr(db, program, arena, arg))
+                .collect();
+            format!(
+                "(|{}| -> f64 {{ {} }})({})",
+                params.join(", "),
+                emit_expr(db, program, arena, *body),
+                args.join(", ")
+            )
+        }
+        ExpressionData::Let(name, value, body) => format!(
+            "{{ let {} = {}; {} }}",
+            mangle(name.text(db)),
+            emit_expr(db, program, arena, *value),
+            emit_expr(db, program, arena, *body)
+        ),
+        ExpressionData::Print(operand) => format!(
+            "{{ let value = {}; println!(\"{{}}\", Show::show(value)); value }}",
+            emit_expr(db, program, arena, *operand)
+        ),
+        // `!` coerces to `f64`.
+        ExpressionData::Error(message) => format!("panic!(\"{{}}\", {message:?})"),
+    }
+}
+
+fn emit_operand(
+    db: &dyn crate::Db,
+    program: Program,
+    arena: &ExprArena,
+    operand: ExprId,
+    needs_parens: impl Fn(Op) -> bool,
+) -> String {
+    match &arena[operand].data {
+        ExpressionData::Op(_, op, _) if needs_parens(*op) => {
+            format!("({})", emit_expr(db, program, arena, operand))
+        }
+        _ => emit_expr(db, program, arena, operand),
+    }
+}
+
+/// The Rust function to call for `f`. Builtins map to the `f64` methods,
+/// except that `log` of a non-positive number gives NaN or an infinity
+/// instead of failing.
+fn callee(db: &dyn crate::Db, program: Program, f: FunctionId) -> String {
+    let name = f.text(db);
+    if find_function(db, program, f).is_some() {
+        return mangle(name);
+    }
+    match name.as_str() {
+        "sqrt" | "abs" | "min" | "max" | "sin" | "cos" => format!("f64::{name}"),
+        "pow" => "f64::powf".to_string(),
+        "log" => "f64::ln".to_string(),
+        _ => mangle(name),
+    }
+}
+
+/// Rust keywords, which are escaped as raw identifiers.
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where", "while",
+    "abstract", "become", "box", "do", "final", "macro", "override", "priv", "try", "typeof",
+    "unsized", "virtual", "yield",
+];
+
+/// Names which can't be raw identifiers, or would clash with the generated
+/// `main`.
+const RESERVED: &[&str] = &["", "crate", "self", "Self", "super", "main"];
+
+/// Turns a banana identifier into a Rust one. Reserved names, possibly
+/// followed by underscores, get one more underscore, so that `main` and
+/// `main_` stay distinct.
+fn mangle(name: &str) -> String {
+    if RESERVED.contains(&name.trim_end_matches('_')) {
+        format!("{name}_")
+    } else if KEYWORDS.contains(&name) {
+        format!("r#{name}")
+    } else {
+        name.to_string()
+    }
+}
+
+#[test]
+fn mangle_names() {
+    let names = [
+        "x", "fn", "loop", "self", "self_", "main", "_", "__", "selfie",
+    ]
+    .map(mangle);
+    assert_eq!(
+        names,
+        ["x", "r#fn", "r#loop", "self_", "self__", "main_", "__", "___", "selfie"]
+    );
+}
+
+#[test]
+fn emit_example() {
+    let db = crate::db::Database::default();
+    let source_program = crate::ir::SourceProgram::new(
+        &db,
+        "
+            fn area_rectangle(w, h) = w * h;
+            fn area_circle(r) = 3.14 * r * r;
+            fn main(loop) = -(loop - 1) / (2 * pow(loop, 2));
+            print area_rectangle(3, 4);
+            print area_circle(1) - (2 - sqrt(4));
+            print main(11 * 2);
+        "
+        .to_string(),
+    );
+    let program = crate::parser::parse_statements(&db, source_program);
+    let expected = expect_test::expect![[r#"
+        fn area_rectangle(w: f64, h: f64) -> f64 {
+            w * h
+        }
+
+        fn area_circle(r: f64) -> f64 {
+            3.14 * r * r
+        }
+
+        fn main_(r#loop: f64) -> f64 {
+            -(r#loop - 1.0) / (2.0 * f64::powf(r#loop, 2.0))
+        }
+
+        trait Show {
+            fn show(self) -> String;
+        }
+
+        impl Show for f64 {
+            fn show(self) -> String {
+                if self == 0.0 {
+                    "0".to_string()
+                } else if self.is_nan() {
+                    "nan".to_string()
+                } else {
+                    self.to_string()
+                }
+            }
+        }
+
+        impl Show for bool {
+            fn show(self) -> String {
+                self.to_string()
+            }
+        }
+
+        fn main() {
+            println!("{}", Show::show(area_rectangle(3.0, 4.0)));
+            println!("{}", Show::show(area_circle(1.0) - (2.0 - f64::sqrt(4.0))));
+            println!("{}", Show::show(main_(11.0 * 2.0)));
+        }
+    "#]];
+    expected.assert_eq(&emit_program(&db, program));
+}
+
+#[test]
+fn emit_named_arguments() {
+    let db = crate::db::Database::default();
+    let source_program = crate::ir::SourceProgram::new(
+        &db,
+        "fn f(x, y) = x - y; print f(y = print 1, x = 2);".to_string(),
+    );
+    let program = crate::parser::parse_statements(&db, source_program);
+    let out = emit_program(&db, program);
+    assert!(
+        out.contains("(|y: f64, x: f64| -> f64 { f(x, y) })({ let value = 1.0;"),
+        "{out}"
+    );
+}
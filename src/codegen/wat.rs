@@ -0,0 +1,265 @@
+//! Compiles a program into a WebAssembly text module (`--emit wat`).
+//!
+//! The module is translated from the bytecode (see `bytecode`), whose stack
+//! operations map directly onto WebAssembly instructions. Functions take and
+//! return `f64`s, and the `print` statements run in an exported `main`
+//! function. `print` calls the imported `env.print_f64` through `$print`,
+//! which returns the value printed. Builtins without a
+//! WebAssembly instruction are imported from the `math` module, and lambdas
+//! become functions of their own, `$lambda.<n>`. As with `--emit rust`, only
+//! programs without errors are compiled.
+
+use std::collections::BTreeSet;
+
+use crate::builtins::find_builtin;
+use crate::bytecode::{compile_function_bytecode, compile_program_bytecode, Chunk, Instr};
+use crate::ir::{Op, Program};
+use crate::type_check::find_function;
+use crate::value::Value;
+
+pub fn emit_program(db: &dyn crate::Db, program: Program) -> String {
+    let mut imports = BTreeSet::new();
+    let mut lambdas = vec![];
+    let mut funcs = String::new();
+    for function in program.functions(db) {
+        let data = function.data(db);
+        funcs += &format!("  (func ${}", function.name(db).text(db));
+        for arg in &data.args {
+            funcs += &format!(" (param ${} f64)", arg.text(db));
+        }
+        funcs += " (result f64)\n";
+        let args: Vec<_> = data.args.iter().map(|arg| arg.text(db).as_str()).collect();
+        emit_chunk(
+            db,
+            program,
+            &args,
+            compile_function_bytecode(db, *function, program),
+            &mut imports,
+            &mut lambdas,
+            &mut funcs,
+        );
+        funcs += "  )\n";
+    }
+    funcs += "  (func (export \"main\")\n";
+    for chunk in compile_program_bytecode(db, program) {
+        emit_chunk(
+            db,
+            program,
+            &[],
+            chunk,
+            &mut imports,
+            &mut lambdas,
+            &mut funcs,
+        );
+        // `print` leaves the printed value on the stack.
+        funcs += "    drop\n";
+    }
+    funcs += "  )\n";
+    funcs.extend(lambdas);
+
+    let mut out = "(module\n".to_string();
+    out += "  (import \"env\" \"print_f64\" (func $env.print_f64 (param f64)))\n";
+    for (name, arity) in imports {
+        let params = vec!["f64"; arity].join(" ");
+        out += &format!(
+            "  (import \"math\" \"{name}\" (func $math.{name} (param {params}) (result f64)))\n"
+        );
+    }
+    // Prints its argument and returns it, as `print` leaves the value on
+    // the stack.
+    out += "  (func $print (param $value f64) (result f64)\n";
+    out += "    local.get $value\n";
+    out += "    call $env.print_f64\n";
+    out += "    local.get $value\n";
+    out += "  )\n";
+    out + &funcs + ")\n"
+}
+
+/// Appends the instructions for `chunk` to `out`, adding the builtins it
+/// imports to `imports` and the functions for its lambdas to `lambdas`.
+fn emit_chunk(
+    db: &dyn crate::Db,
+    program: Program,
+    args: &[&str],
+    chunk: &Chunk,
+    imports: &mut BTreeSet<(&'static str, usize)>,
+    lambdas: &mut Vec<String>,
+    out: &mut String,
+) {
+    for instr in &chunk.instrs {
+        let wat = match instr {
+            Instr::PushConst(Value::Number(n)) => format!("f64.const {}", n.into_inner()),
+            // There are only `f64`s: booleans are 1 and 0, and print as such.
+            Instr::PushConst(Value::Bool(b)) => format!("f64.const {}", u8::from(*b)),
+            Instr::LoadArg(index) => format!("local.get ${}", args[*index]),
+            // Only checked programs are compiled; this traps like a runtime
+            // error would.
+            Instr::Undeclared(_) => "unreachable".to_string(),
+            Instr::Op(op) => match op {
+                Op::Add => "f64.add",
+                Op::Subtract => "f64.sub",
+                Op::Multiply => "f64.mul",
+                Op::Divide => "f64.div",
+            }
+            .to_string(),
+            Instr::Neg => "f64.neg".to_string(),
+            Instr::Abs => "f64.abs".to_string(),
+            Instr::Call(f, _) if find_function(db, program, *f).is_some() => {
+                format!("call ${}", f.text(db))
+            }
+            Instr::Call(f, argc) => match find_builtin(f.text(db)) {
+                Some(builtin) => match builtin.name {
+                    "sqrt" | "abs" | "min" | "max" => format!("f64.{}", builtin.name),
+                    // Adds up the arguments on the stack.
+                    "sum" if *argc == 0 => "f64.const 0".to_string(),
+                    // The single argument is already the result.
+                    "sum" if *argc == 1 => continue,
+                    "sum" => vec!["f64.add"; argc - 1].join("\n    "),
+                    _ => {
+                        imports.insert((builtin.name, *argc));
+                        format!("call $math.{}", builtin.name)
+                    }
+                },
+                None => "unreachable".to_string(),
+            },
+            Instr::CallLambda(params, body, _) => {
+                // Reserve the name before any lambdas nested in this one.
+                let index = lambdas.len();
+                lambdas.push(String::new());
+                let mut func = format!("  (func $lambda.{index}");
+                for param in params {
+                    func += &format!(" (param ${} f64)", param.text(db));
+                }
+                func += " (result f64)\n";
+                let params: Vec<_> = params.iter().map(|param| param.text(db).as_str()).collect();
+                emit_chunk(db, program, &params, body, imports, lambdas, &mut func);
+                func += "  )\n";
+                lambdas[index] = func;
+                format!("call $lambda.{index}")
+            }
+            // The message is lost: WebAssembly traps don't carry one.
+            Instr::Raise(_) => "unreachable".to_string(),
+            Instr::Print => "call $print".to_string(),
+            // The result is left on the stack at the end of the function.
+            Instr::Return => continue,
+        };
+        *out += &format!("    {wat}\n");
+    }
+}
+
+#[cfg(test)]
+fn emit_string(source_text: &str) -> String {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    emit_program(&db, parse_statements(&db, source_program))
+}
+
+#[test]
+fn emit_example() {
+    let expected = expect_test::expect![[r#"
+        (module
+          (import "env" "print_f64" (func $env.print_f64 (param f64)))
+          (func $print (param $value f64) (result f64)
+            local.get $value
+            call $env.print_f64
+            local.get $value
+          )
+          (func $area_rectangle (param $w f64) (param $h f64) (result f64)
+            local.get $w
+            local.get $h
+            f64.mul
+          )
+          (func $area_circle (param $r f64) (result f64)
+            f64.const 3.14
+            local.get $r
+            f64.mul
+            local.get $r
+            f64.mul
+          )
+          (func (export "main")
+            f64.const 3
+            f64.const 4
+            call $area_rectangle
+            call $print
+            drop
+            f64.const 1
+            call $area_circle
+            call $print
+            drop
+            f64.const 11
+            f64.const 2
+            f64.mul
+            call $print
+            drop
+          )
+        )
+    "#]];
+    expected.assert_eq(&emit_string(
+        "
+            fn area_rectangle(w, h) = w * h;
+            fn area_circle(r) = 3.14 * r * r;
+            print area_rectangle(3, 4);
+            print area_circle(1);
+            print 11 * 2;
+        ",
+    ));
+}
+
+#[test]
+fn emit_nested_calls() {
+    let expected = expect_test::expect![[r#"
+        (module
+          (import "env" "print_f64" (func $env.print_f64 (param f64)))
+          (import "math" "log" (func $math.log (param f64) (result f64)))
+          (import "math" "pow" (func $math.pow (param f64 f64) (result f64)))
+          (func $print (param $value f64) (result f64)
+            local.get $value
+            call $env.print_f64
+            local.get $value
+          )
+          (func $avg (param $x f64) (param $n f64) (result f64)
+            local.get $x
+            local.get $n
+            f64.div
+          )
+          (func $dist (param $x f64) (param $y f64) (result f64)
+            local.get $x
+            f64.const 2
+            call $math.pow
+            local.get $y
+            f64.const 2
+            call $math.pow
+            f64.add
+            f64.sqrt
+          )
+          (func (export "main")
+            f64.const 1
+            f64.const 10
+            f64.const 2
+            call $avg
+            f64.sub
+            f64.const 2.5
+            call $avg
+            f64.neg
+            call $print
+            drop
+            f64.const 3
+            f64.const 4
+            call $dist
+            call $math.log
+            call $print
+            drop
+          )
+        )
+    "#]];
+    expected.assert_eq(&emit_string(
+        "
+            fn avg(x, n) = x / n;
+            fn dist(x, y) = sqrt(pow(x, 2) + pow(y, 2));
+            print -avg(1 - avg(10, 2), 2.5);
+            print log(dist(3, 4));
+        ",
+    ));
+}
@@ -1,7 +1,682 @@
-use crate::{ir::SourceProgram, parser::parse_statements, type_check::type_check_program};
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
 
+use crate::{
+    bytecode::run_bytecode,
+    eval::{eval_program, EvalOptions, EvalOutput, OutputLine},
+    ir::{
+        Diagnostic, Diagnostics, ExprArena, ExprId, ExpressionData, Function, FunctionId, Op,
+        Program, Severity, SourceProgram, VariableId,
+    },
+    parser::{parse_statements, statement_items},
+    type_check::{
+        argument_order, call_arguments, check_duplicate_definitions, redefinitions,
+        type_check_function, type_check_program, type_check_statements,
+    },
+    value::Value,
+    workspace::{workspace_diagnostics_with, workspace_program, Workspace},
+};
+
+/// Parses and type checks the program. A program with parse errors isn't
+/// type checked: with parts of it missing, most type errors would be noise,
+/// e.g. calls to functions which failed to parse.
 #[salsa::tracked]
 pub fn compile(db: &dyn crate::Db, source_program: SourceProgram) {
     let program = parse_statements(db, source_program);
-    type_check_program(db, program);
+    let parse_diagnostics = parse_statements::accumulated::<Diagnostics>(db, source_program);
+    if !parse_diagnostics.iter().any(Diagnostic::is_error) {
+        type_check_program(db, program);
+    }
+}
+
+/// What compiling a source program, or a workspace, produced.
+#[derive(Clone, Debug)]
+pub struct CompileResult {
+    pub program: Program,
+    /// As returned by `compile_diagnostics`, or by `workspace_diagnostics`
+    /// for a workspace, with the file each is in.
+    pub diagnostics: Vec<(SourceProgram, Diagnostic)>,
+    /// Whether none of the diagnostics is an error, so that the program can
+    /// run.
+    pub success: bool,
+    /// What running the program printed, if it was run. Its runtime errors
+    /// are accumulated by the backend's query, see `Backend::diagnostics`.
+    pub output: Option<Vec<OutputLine>>,
+    /// How long running the program took, if it was run. It's timed here
+    /// rather than in the backend's query, whose result must be
+    /// deterministic.
+    pub run_time: Option<Duration>,
+}
+
+/// How far `compile_full` goes.
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub enum CompileMode {
+    /// Parse and type check only (`--check`), e.g. for editors and CI.
+    /// Problems only found by running the program, such as exceeding the
+    /// maximum call depth, aren't reported.
+    Check,
+    /// Also run the program on the backend with these options, if it
+    /// compiled without errors.
+    Run(EvalOptions, Backend),
+}
+
+/// How to run programs (`--backend <engine>`).
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug, Default)]
+pub enum Backend {
+    /// The tree-walking evaluator.
+    #[default]
+    Ast,
+    /// The bytecode VM.
+    Vm,
+}
+
+impl Backend {
+    /// Runs `program`, as `eval_program` or `run_bytecode`.
+    pub fn run(self, db: &dyn crate::Db, program: Program, options: EvalOptions) -> &EvalOutput {
+        match self {
+            Backend::Ast => eval_program(db, program, options),
+            Backend::Vm => run_bytecode(db, program, options),
+        }
+    }
+
+    /// The runtime errors and warnings of `run`.
+    pub fn diagnostics(
+        self,
+        db: &dyn crate::Db,
+        program: Program,
+        options: EvalOptions,
+    ) -> Vec<Diagnostic> {
+        match self {
+            Backend::Ast => eval_program::accumulated::<Diagnostics>(db, program, options),
+            Backend::Vm => run_bytecode::accumulated::<Diagnostics>(db, program, options),
+        }
+    }
+}
+
+/// Compiles the program and, in `CompileMode::Run`, runs it if it compiled
+/// without errors.
+pub fn compile_full(
+    db: &dyn crate::Db,
+    source_program: SourceProgram,
+    mode: CompileMode,
+) -> CompileResult {
+    let program = parse_statements(db, source_program);
+    let diagnostics = compile_diagnostics(db, source_program)
+        .into_iter()
+        .map(|diagnostic| (source_program, diagnostic))
+        .collect();
+    finish_compile(db, program, diagnostics, mode)
+}
+
+/// Compiles the files of `workspace` together, as `compile_full` compiles
+/// one, with the type errors of each function given by `check_function`,
+/// as for `compile_diagnostics_with`.
+pub fn compile_workspace(
+    db: &dyn crate::Db,
+    workspace: Workspace,
+    mode: CompileMode,
+    check_function: impl FnMut(Function, Program) -> Vec<Diagnostic>,
+) -> CompileResult {
+    let program = workspace_program(db, workspace);
+    let diagnostics = workspace_diagnostics_with(db, workspace, check_function);
+    finish_compile(db, program, diagnostics, mode)
+}
+
+fn finish_compile(
+    db: &dyn crate::Db,
+    program: Program,
+    diagnostics: Vec<(SourceProgram, Diagnostic)>,
+    mode: CompileMode,
+) -> CompileResult {
+    let success = diagnostics
+        .iter()
+        .all(|(_, diagnostic)| diagnostic.severity != Severity::Error);
+    let (output, run_time) = match mode {
+        CompileMode::Run(options, backend) if success => {
+            let start = Instant::now();
+            let output = backend.run(db, program, options).lines.clone();
+            (Some(output), Some(start.elapsed()))
+        }
+        _ => (None, None),
+    };
+    CompileResult {
+        program,
+        diagnostics,
+        success,
+        output,
+        run_time,
+    }
+}
+
+/// The pass of the compiler which reported a diagnostic.
+/// `compile_diagnostics` shows the diagnostics of each pass in this order.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Debug)]
+pub enum Pass {
+    Parse,
+    TypeCheck,
+}
+
+/// The diagnostics of `compile` in a stable order, independent of the order
+/// the queries ran in: parse errors first, then type errors, each by
+/// position in the source text. As with `compile`, there are no type errors
+/// if there are parse errors.
+pub fn compile_diagnostics(db: &dyn crate::Db, source_program: SourceProgram) -> Vec<Diagnostic> {
+    compile_diagnostics_with(db, source_program, |function, program| {
+        type_check_function::accumulated::<Diagnostics>(db, function, program)
+    })
+}
+
+/// `compile_diagnostics`, with the type errors of each function given by
+/// `check_function` instead of `type_check_function`, e.g. from a cache.
+pub fn compile_diagnostics_with(
+    db: &dyn crate::Db,
+    source_program: SourceProgram,
+    mut check_function: impl FnMut(Function, Program) -> Vec<Diagnostic>,
+) -> Vec<Diagnostic> {
+    let program = parse_statements(db, source_program);
+    let mut keyed: Vec<_> = parse_statements::accumulated::<Diagnostics>(db, source_program)
+        .into_iter()
+        .map(|diagnostic| ((Pass::Parse, diagnostic.start), diagnostic))
+        .collect();
+    if keyed.iter().any(|(_, diagnostic)| diagnostic.is_error()) {
+        keyed.sort_by_key(|(key, _)| *key);
+        return keyed
+            .into_iter()
+            .map(|(_, diagnostic)| diagnostic)
+            .collect();
+    }
+    // Type errors are positioned relative to the function they're in, and
+    // are moved to where it is in the file.
+    let duplicates = check_duplicate_definitions::accumulated::<Diagnostics>(db, program);
+    let redefined = redefinitions(db, program).into_iter().map(|(f, _)| f);
+    let by_function = redefined
+        .zip(duplicates)
+        .chain(program.functions(db).iter().flat_map(|function| {
+            check_function(*function, program)
+                .into_iter()
+                .map(|diagnostic| (*function, diagnostic))
+        }));
+    keyed.extend(by_function.map(|(function, diagnostic)| {
+        let start = function.start(db);
+        let diagnostic = Diagnostic {
+            start: start + diagnostic.start,
+            end: start + diagnostic.end,
+            ..diagnostic
+        };
+        ((Pass::TypeCheck, diagnostic.start), diagnostic)
+    }));
+    // Those of statements are relative to the item they're in.
+    keyed.extend(
+        statement_diagnostics(db, program, source_program)
+            .map(|diagnostic| ((Pass::TypeCheck, diagnostic.start), diagnostic)),
+    );
+    // The sort is stable, so diagnostics at the same position stay in the
+    // order they were reported.
+    keyed.sort_by_key(|(key, _)| *key);
+    keyed
+        .into_iter()
+        .map(|(_, diagnostic)| diagnostic)
+        .collect()
+}
+
+/// The type errors of the statements of `source`, a file of `program`,
+/// positioned in the file.
+pub(crate) fn statement_diagnostics<'db>(
+    db: &'db dyn crate::Db,
+    program: Program,
+    source: SourceProgram,
+) -> impl Iterator<Item = Diagnostic> + 'db {
+    statement_items(db, source)
+        .into_iter()
+        .flat_map(move |item| {
+            type_check_statements::accumulated::<Diagnostics>(db, program, item.text)
+                .into_iter()
+                .map(move |diagnostic| Diagnostic {
+                    start: item.start + diagnostic.start,
+                    end: item.start + diagnostic.end,
+                    ..diagnostic
+                })
+        })
+}
+
+/// The diagnostics of `compile`, grouped by the function they were found
+/// in, positioned in the file as by `compile_diagnostics`. Parse errors and
+/// other diagnostics not tied to one function, including those of the
+/// statements, are under `None`. Functions without diagnostics have no
+/// entry.
+pub fn diagnostics_by_function(
+    db: &dyn crate::Db,
+    source_program: SourceProgram,
+) -> HashMap<Option<FunctionId>, Vec<Diagnostic>> {
+    let program = parse_statements(db, source_program);
+    let mut global = parse_statements::accumulated::<Diagnostics>(db, source_program);
+    if global.iter().any(Diagnostic::is_error) {
+        return HashMap::from([(None, global)]);
+    }
+    let in_file = |function: Function, diagnostic: Diagnostic| {
+        let start = function.start(db);
+        Diagnostic {
+            start: start + diagnostic.start,
+            end: start + diagnostic.end,
+            ..diagnostic
+        }
+    };
+    let duplicates = check_duplicate_definitions::accumulated::<Diagnostics>(db, program);
+    let redefined = redefinitions(db, program).into_iter().map(|(f, _)| f);
+    global.extend(
+        redefined
+            .zip(duplicates)
+            .map(|(function, diagnostic)| in_file(function, diagnostic)),
+    );
+    global.extend(statement_diagnostics(db, program, source_program));
+    let mut grouped = HashMap::new();
+    for function in program.functions(db) {
+        let diagnostics = type_check_function::accumulated::<Diagnostics>(db, *function, program);
+        if !diagnostics.is_empty() {
+            grouped
+                .entry(Some(function.name(db)))
+                .or_insert_with(Vec::new)
+                .extend(
+                    diagnostics
+                        .into_iter()
+                        .map(|diagnostic| in_file(*function, diagnostic)),
+                );
+        }
+    }
+    if !global.is_empty() {
+        grouped.insert(None, global);
+    }
+    grouped
+}
+
+#[cfg(test)]
+fn compile_messages(db: &crate::db::Database, source_text: &str) -> Vec<String> {
+    let source_program = SourceProgram::new(db, source_text.to_string());
+    compile_diagnostics(db, source_program)
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect()
+}
+
+/// A temporary of the SSA form, assigned by exactly one instruction.
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub struct Temp(pub usize);
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t{}", self.0)
+    }
+}
+
+/// An operand of an SSA instruction.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Operand {
+    Temp(Temp),
+    Param(VariableId),
+    Const(Value),
+}
+
+/// An instruction of the SSA form of a function body: each operation
+/// assigns a new temporary, and its operands are temporaries, parameters or
+/// constants.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum SsaInstr {
+    /// `t<n> = <lhs> <op> <rhs>`
+    Op(Temp, Operand, Op, Operand),
+    /// `t<n> = -<operand>`
+    Neg(Temp, Operand),
+    /// `t<n> = |<operand>|`
+    Abs(Temp, Operand),
+    /// `t<n> = <f>(<args>)`, calling a user function or a builtin.
+    Call(Temp, FunctionId, Vec<Operand>),
+    /// Prints the operand, which is also the value of the `print`.
+    Print(Operand),
+    /// Fails with the message of `error(...)`.
+    Raise(String),
+    Return(Operand),
+}
+
+impl Operand {
+    pub fn display(&self, db: &dyn crate::Db) -> String {
+        match self {
+            Operand::Temp(temp) => temp.to_string(),
+            Operand::Param(v) => v.text(db).clone(),
+            Operand::Const(value) => value.to_string(),
+        }
+    }
+}
+
+impl SsaInstr {
+    /// The instruction as a line of the listing, e.g. `t0 = w * h`.
+    pub fn display(&self, db: &dyn crate::Db) -> String {
+        match self {
+            SsaInstr::Op(dest, lhs, op, rhs) => {
+                format!("{dest} = {} {op} {}", lhs.display(db), rhs.display(db))
+            }
+            SsaInstr::Neg(dest, operand) => format!("{dest} = -{}", operand.display(db)),
+            SsaInstr::Abs(dest, operand) => format!("{dest} = |{}|", operand.display(db)),
+            SsaInstr::Call(dest, f, args) => {
+                let args: Vec<_> = args.iter().map(|arg| arg.display(db)).collect();
+                format!("{dest} = {}({})", f.text(db), args.join(", "))
+            }
+            SsaInstr::Print(operand) => format!("print {}", operand.display(db)),
+            SsaInstr::Raise(message) => format!("raise {message:?}"),
+            SsaInstr::Return(operand) => format!("return {}", operand.display(db)),
+        }
+    }
+}
+
+/// Lowers the body of `function` to SSA form, ending with its `return`.
+/// `let`s and lambdas leave no instructions of their own: their variables
+/// stand for the operands of their values. The function should have type
+/// checked; a variable not in scope is read as a parameter. Calls are
+/// resolved in `program`.
+pub fn lower_function(db: &dyn crate::Db, function: Function, program: Program) -> Vec<SsaInstr> {
+    let data = function.data(db);
+    let env = data
+        .args
+        .iter()
+        .map(|arg| (*arg, Operand::Param(*arg)))
+        .collect();
+    let mut lower = LowerSsa {
+        db,
+        program,
+        instrs: vec![],
+        temps: 0,
+    };
+    let result = lower.lower(data.arena(db), data.body, &env);
+    lower.instrs.push(SsaInstr::Return(result));
+    lower.instrs
+}
+
+struct LowerSsa<'db> {
+    db: &'db dyn crate::Db,
+    program: Program,
+    instrs: Vec<SsaInstr>,
+    /// The number of temporaries assigned so far.
+    temps: usize,
+}
+
+impl LowerSsa<'_> {
+    /// Appends the instruction assigning a new temporary, and returns it.
+    fn assign(&mut self, instr: impl FnOnce(Temp) -> SsaInstr) -> Operand {
+        let temp = Temp(self.temps);
+        self.temps += 1;
+        self.instrs.push(instr(temp));
+        Operand::Temp(temp)
+    }
+
+    /// Appends the instructions computing the expression `id` of `arena`
+    /// and returns the operand holding its value.
+    fn lower(
+        &mut self,
+        arena: &ExprArena,
+        id: ExprId,
+        env: &HashMap<VariableId, Operand>,
+    ) -> Operand {
+        match &arena[id].data {
+            ExpressionData::Op(left, op, right) => {
+                let lhs = self.lower(arena, *left, env);
+                let rhs = self.lower(arena, *right, env);
+                self.assign(|dest| SsaInstr::Op(dest, lhs, *op, rhs))
+            }
+            ExpressionData::Neg(operand) => {
+                let operand = self.lower(arena, *operand, env);
+                self.assign(|dest| SsaInstr::Neg(dest, operand))
+            }
+            ExpressionData::Abs(operand) => {
+                let operand = self.lower(arena, *operand, env);
+                self.assign(|dest| SsaInstr::Abs(dest, operand))
+            }
+            ExpressionData::Number(n) => Operand::Const(Value::Number(*n)),
+            ExpressionData::Bool(b) => Operand::Const(Value::Bool(*b)),
+            ExpressionData::Variable(v) => env.get(v).cloned().unwrap_or(Operand::Param(*v)),
+            ExpressionData::Call(f, args) => {
+                let order = match call_arguments(self.db, self.program, *f, arena, args) {
+                    Ok(bound) => argument_order(&bound, args),
+                    Err((_, message)) => {
+                        self.instrs.push(SsaInstr::Raise(message));
+                        return Operand::Const(Value::Number(0.0.into()));
+                    }
+                };
+                // Arguments are evaluated in the order they're written.
+                let values: Vec<_> = args
+                    .iter()
+                    .map(|arg| self.lower(arena, arg.value, env))
+                    .collect();
+                let args = order.iter().map(|&index| values[index].clone()).collect();
+                self.assign(|dest| SsaInstr::Call(dest, *f, args))
+            }
+            ExpressionData::Lambda(params, body, args) => {
+                // The body only sees the parameters.
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|&arg| self.lower(arena, arg, env))
+                    .collect();
+                let env = params.iter().copied().zip(args).collect();
+                self.lower(arena, *body, &env)
+            }
+            ExpressionData::Let(name, value, body) => {
+                let value = self.lower(arena, *value, env);
+                let mut env = env.clone();
+                env.insert(*name, value);
+                self.lower(arena, *body, &env)
+            }
+            ExpressionData::Print(operand) => {
+                let operand = self.lower(arena, *operand, env);
+                self.instrs.push(SsaInstr::Print(operand.clone()));
+                operand
+            }
+            ExpressionData::Error(message) => {
+                self.instrs.push(SsaInstr::Raise(message.clone()));
+                // Never read: the function fails before.
+                Operand::Const(Value::Number(0.0.into()))
+            }
+        }
+    }
+}
+
+#[test]
+fn diagnostics_in_source_order() {
+    let db = crate::db::Database::default();
+    // The duplicate definition is reported before the functions are
+    // checked, but shown at the position of the second `f`.
+    let expected = expect_test::expect![[r#"
+        [
+            "the variable `y` is not declared",
+            "the function `h` is not declared",
+            "the function `f` is defined more than once",
+        ]
+    "#]];
+    expected.assert_debug_eq(&compile_messages(
+        &db,
+        "fn f() = y; fn g() = h(); fn f() = 1;",
+    ));
+}
+
+#[test]
+fn parse_errors_skip_type_check() {
+    let db = crate::db::Database::default();
+    // Otherwise `y`, `area` and `pi` would be reported as undeclared too.
+    let expected = expect_test::expect![[r#"
+        [
+            "cannot include `missing.bn`: the file hasn't been loaded",
+        ]
+    "#]];
+    expected.assert_debug_eq(&compile_messages(
+        &db,
+        "fn f() = y; include \"missing.bn\"; print area(pi());",
+    ));
+}
+
+#[test]
+fn compile_full_results() {
+    let db = crate::db::Database::default();
+    let source_program = SourceProgram::new(
+        &db,
+        "fn sqrt(x) = x; print sqrt(4); print 1 + 2;".to_string(),
+    );
+    let result = compile_full(
+        &db,
+        source_program,
+        CompileMode::Run(EvalOptions::default(), Backend::Ast),
+    );
+    assert!(result.success);
+    assert_eq!(result.program, parse_statements(&db, source_program));
+    // Warnings don't stop the program from running.
+    let messages: Vec<_> = result
+        .diagnostics
+        .iter()
+        .map(|(_, diagnostic)| diagnostic.message.as_str())
+        .collect();
+    assert_eq!(
+        messages,
+        ["definition of `sqrt` shadows a built-in function"]
+    );
+    let output: Vec<_> = result
+        .output
+        .unwrap()
+        .iter()
+        .map(|line| line.text.clone())
+        .collect();
+    assert_eq!(output, ["4", "3"]);
+    assert!(compile_full(&db, source_program, CompileMode::Check)
+        .output
+        .is_none());
+
+    let source_program = SourceProgram::new(&db, "fn f() = y; print f();".to_string());
+    let result = compile_full(
+        &db,
+        source_program,
+        CompileMode::Run(EvalOptions::default(), Backend::Vm),
+    );
+    assert!(!result.success);
+    assert_eq!(result.diagnostics.len(), 1);
+    assert_eq!(result.output, None);
+}
+
+#[test]
+fn compile_workspace_results() {
+    let db = crate::db::Database::default();
+    let main = SourceProgram::new(&db, "print double(21);".to_string());
+    let lib = SourceProgram::new(&db, "fn double(x) = x * 2; fn sqrt(x) = x;".to_string());
+    let files = vec![("main.bn".into(), main), ("lib.bn".into(), lib)];
+    let workspace = Workspace::new(&db, files);
+    let result = compile_workspace(
+        &db,
+        workspace,
+        CompileMode::Run(EvalOptions::default(), Backend::Vm),
+        |function, program| type_check_function::accumulated::<Diagnostics>(&db, function, program),
+    );
+    assert!(result.success);
+    // The warning is in the file which defines `sqrt`.
+    assert_eq!(result.diagnostics.len(), 1);
+    assert_eq!(result.diagnostics[0].0, lib);
+    let output: Vec<_> = result
+        .output
+        .unwrap()
+        .into_iter()
+        .map(|line| line.text)
+        .collect();
+    assert_eq!(output, ["42"]);
+}
+
+#[test]
+fn check_mode_does_not_run() {
+    let db = crate::db::Database::default();
+    let source_program = SourceProgram::new(&db, "fn f(x) = f(x) + 1;\nprint f(1);".to_string());
+    // Running would exceed the maximum depth; checking finds nothing.
+    let result = compile_full(&db, source_program, CompileMode::Check);
+    assert!(result.success);
+    assert!(result.diagnostics.is_empty());
+    assert_eq!(result.output, None);
+}
+
+#[test]
+fn group_diagnostics_by_function() {
+    let db = crate::db::Database::default();
+    let source_program = SourceProgram::new(
+        &db,
+        "fn f(x) = y; fn g() = h(1); fn ok() = 1; fn f() = 2; print x;".to_string(),
+    );
+    let mut grouped: Vec<_> = diagnostics_by_function(&db, source_program)
+        .into_iter()
+        .map(|(function, diagnostics)| {
+            let messages: Vec<_> = diagnostics
+                .into_iter()
+                .map(|diagnostic| {
+                    let (start, end) = (diagnostic.start, diagnostic.end);
+                    format!("{start}..{end}: {}", diagnostic.message)
+                })
+                .collect();
+            (function.map(|f| f.text(&db).clone()), messages)
+        })
+        .collect();
+    grouped.sort();
+    let expected = expect_test::expect![[r#"
+        [
+            (
+                None,
+                [
+                    "44..45: the function `f` is defined more than once",
+                    "59..60: the variable `x` is not declared",
+                ],
+            ),
+            (
+                Some(
+                    "f",
+                ),
+                [
+                    "10..11: the variable `y` is not declared",
+                ],
+            ),
+            (
+                Some(
+                    "g",
+                ),
+                [
+                    "22..26: the function `h` is not declared",
+                ],
+            ),
+        ]
+    "#]];
+    expected.assert_debug_eq(&grouped);
+}
+
+#[test]
+fn lower_function_to_ssa() {
+    let db = crate::db::Database::default();
+    let source_program = SourceProgram::new(
+        &db,
+        "
+            fn area(w, h) = w * h;
+            fn f(x) = let y = x + 1 in (1 + 2) * y - g(y, |-x|);
+        "
+        .to_string(),
+    );
+    let program = parse_statements(&db, source_program);
+    let listing = |function: Function| -> Vec<_> {
+        lower_function(&db, function, program)
+            .iter()
+            .map(|instr| instr.display(&db))
+            .collect()
+    };
+    let functions = program.functions(&db);
+    assert_eq!(listing(functions[0]), ["t0 = w * h", "return t0"]);
+    assert_eq!(
+        listing(functions[1]),
+        [
+            "t0 = x + 1",
+            "t1 = 1 + 2",
+            "t2 = t1 * t0",
+            "t3 = -x",
+            "t4 = |t3|",
+            "t5 = g(t0, t4)",
+            "t6 = t2 - t5",
+            "return t6",
+        ]
+    );
 }
@@ -1,50 +1,614 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt,
+    io::Read,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
 
 use salsa::DebugWithDb;
+pub use salsa::Durability;
+
+use crate::{
+    compile::{compile_full, CompileMode, CompileResult},
+    ir::{Files, SourceProgram},
+    parser::{parse_included, parse_statements},
+    Db,
+};
 
 // ANCHOR: db_struct
-#[derive(Default)]
 #[salsa::db(crate::Jar)]
-pub(crate) struct Database {
+pub struct Database {
     storage: salsa::Storage<Self>,
 
     // The logs are only used for testing and demonstrating reuse:
     //
-    logs: Option<Arc<Mutex<Vec<String>>>>,
+    logs: Option<Arc<Mutex<LogBuffer>>>,
+
+    /// Called with each event which passes `log_filter`, if set.
+    on_event: Option<EventCallback>,
+
+    log_filter: LogFilter,
+
+    /// The files loaded so far. Always set once the database is created.
+    files: Option<Files>,
+
+    /// Set by `CompileHandle::cancel` to stop a background compile.
+    cancelled: Option<Arc<AtomicBool>>,
+
+    /// Counts of each query's events, if enabled.
+    metrics: Option<Arc<Mutex<MetricsBuffer>>>,
+
+    /// The largest file loaded, in bytes, if not `DEFAULT_MAX_FILE_SIZE`.
+    max_file_size: Option<usize>,
 }
 // ANCHOR_END: db_struct
 
+impl Default for Database {
+    fn default() -> Self {
+        let mut db = Database {
+            storage: Default::default(),
+            logs: None,
+            on_event: None,
+            log_filter: LogFilter::default(),
+            files: None,
+            cancelled: None,
+            metrics: None,
+            max_file_size: None,
+        };
+        db.files = Some(Files::new(&db, HashMap::new(), HashMap::new()));
+        db
+    }
+}
+
+type EventCallback = Arc<dyn Fn(&salsa::Event) + Send + Sync>;
+
+/// The largest file loaded by default, in bytes.
+pub const DEFAULT_MAX_FILE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The buffered log of a `Database`.
+#[derive(Default)]
+struct LogBuffer {
+    events: VecDeque<LogEvent>,
+    /// The most events to keep. Older events are evicted first.
+    capacity: Option<usize>,
+}
+
+/// What a query did since the metrics were last reset, as counted by
+/// `Database::metrics`, and how many values it has memoized.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Default)]
+pub struct QueryMetrics {
+    /// The times the query was executed, rather than reused.
+    pub executions: usize,
+    /// The times a memoized value was checked and found still valid.
+    pub validations: usize,
+    /// The keys with a memoized value now: those ever executed, less those
+    /// whose values were discarded. Unlike the other counts, this isn't
+    /// reset.
+    pub memos: usize,
+}
+
+/// The counts of each query with any events, by name.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct Metrics {
+    pub queries: BTreeMap<String, QueryMetrics>,
+}
+
+impl Metrics {
+    /// The counts of the query `name`, which are zero if it had no events.
+    pub fn get(&self, name: &str) -> QueryMetrics {
+        self.queries.get(name).copied().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<28} {:>9} {:>9} {:>6}",
+            "query", "executed", "validated", "memos"
+        )?;
+        for (name, query) in &self.queries {
+            writeln!(
+                f,
+                "{name:<28} {:>9} {:>9} {:>6}",
+                query.executions, query.validations, query.memos
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct MetricsBuffer {
+    metrics: Metrics,
+    /// The keys with a memoized value, as `name(id)`.
+    memo_keys: HashSet<String>,
+}
+
+impl MetricsBuffer {
+    fn record(&mut self, event: &LogEvent) {
+        let (Some(query), Some(key)) = (event.query(), &event.key) else {
+            return;
+        };
+        let counts = self.metrics.queries.entry(query.to_string()).or_default();
+        match event.kind {
+            LogEventKind::WillExecute => {
+                counts.executions += 1;
+                if self.memo_keys.insert(key.clone()) {
+                    counts.memos += 1;
+                }
+            }
+            LogEventKind::DidValidateMemoizedValue => counts.validations += 1,
+            LogEventKind::DidDiscard if self.memo_keys.remove(key) => counts.memos -= 1,
+            _ => {}
+        }
+    }
+}
+
+/// A salsa event, or an event of the compiler itself, recorded by a
+/// `Database`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct LogEvent {
+    pub kind: LogEventKind,
+    /// The query the event is about, as `name(id)`, if any. For
+    /// `ParsedFunction`, the name of the function.
+    pub key: Option<String>,
+}
+
+/// The kinds of `salsa::EventKind`, without their data, and the compiler's
+/// own events.
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub enum LogEventKind {
+    DidValidateMemoizedValue,
+    WillBlockOn,
+    WillExecute,
+    WillCheckCancellation,
+    WillDiscardStaleOutput,
+    DidDiscard,
+    DidDiscardAccumulated,
+    /// A function was parsed, into a body of `node_count` expressions.
+    ParsedFunction {
+        node_count: usize,
+    },
+}
+
+impl LogEvent {
+    fn new(db: &Database, event: &salsa::Event) -> Self {
+        use salsa::EventKind::*;
+        let (kind, key) = match event.kind {
+            DidValidateMemoizedValue { database_key } => {
+                (LogEventKind::DidValidateMemoizedValue, Some(database_key))
+            }
+            WillBlockOn { database_key, .. } => (LogEventKind::WillBlockOn, Some(database_key)),
+            WillExecute { database_key } => (LogEventKind::WillExecute, Some(database_key)),
+            WillCheckCancellation => (LogEventKind::WillCheckCancellation, None),
+            WillDiscardStaleOutput { execute_key, .. } => {
+                (LogEventKind::WillDiscardStaleOutput, Some(execute_key))
+            }
+            DidDiscard { key } => (LogEventKind::DidDiscard, Some(key)),
+            DidDiscardAccumulated { executor_key, .. } => {
+                (LogEventKind::DidDiscardAccumulated, Some(executor_key))
+            }
+        };
+        Self {
+            kind,
+            key: key.map(|key| format!("{:?}", key.debug(db))),
+        }
+    }
+
+    /// A function named `name` was parsed.
+    pub fn parsed_function(name: &str, node_count: usize) -> Self {
+        Self {
+            kind: LogEventKind::ParsedFunction { node_count },
+            key: Some(name.to_string()),
+        }
+    }
+
+    /// The name of the query the event is about, if any.
+    pub fn query(&self) -> Option<&str> {
+        if let LogEventKind::ParsedFunction { .. } = self.kind {
+            return None;
+        }
+        let key = self.key.as_deref()?;
+        Some(key.split('(').next().unwrap_or(key))
+    }
+}
+
+impl fmt::Display for LogEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{:?} {key}", self.kind),
+            None => write!(f, "{:?}", self.kind),
+        }
+    }
+}
+
+/// The events logged by a `Database`, oldest first.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct Logs {
+    pub events: Vec<LogEvent>,
+}
+
+impl Logs {
+    /// The keys of the queries which were executed, rather than reused.
+    pub fn executed_queries(&self) -> Vec<String> {
+        self.events
+            .iter()
+            .filter(|event| event.kind == LogEventKind::WillExecute)
+            .filter_map(|event| event.key.clone())
+            .collect()
+    }
+
+    /// The number of events of `kind`.
+    pub fn count(&self, kind: LogEventKind) -> usize {
+        self.events
+            .iter()
+            .filter(|event| event.kind == kind)
+            .count()
+    }
+}
+
+/// Which events a `Database` logs.
+#[derive(Clone, Debug, Default)]
+pub enum LogFilter {
+    /// Queries being executed, rather than reused, and the functions
+    /// parsed.
+    #[default]
+    WillExecute,
+    /// Every event.
+    All,
+    /// Events of the queries with the given names, e.g.
+    /// `type_check_function`.
+    Queries(Vec<String>),
+}
+
+/// Configures how a `Database` logs salsa events. Without any logging
+/// options, it logs nothing.
+#[derive(Default)]
+pub struct DatabaseBuilder {
+    logging: bool,
+    log_filter: LogFilter,
+    log_capacity: Option<usize>,
+    on_event: Option<EventCallback>,
+    parse_lru_capacity: Option<usize>,
+    eval_lru_capacity: Option<usize>,
+    metrics: bool,
+    max_file_size: Option<usize>,
+}
+
+impl DatabaseBuilder {
+    /// Buffers the log messages, to be read with `Database::take_logs`.
+    pub fn logging(mut self, enabled: bool) -> Self {
+        self.logging = enabled;
+        self
+    }
+
+    pub fn log_filter(mut self, filter: LogFilter) -> Self {
+        self.log_filter = filter;
+        self
+    }
+
+    /// Keeps only the last `capacity` log messages.
+    pub fn log_capacity(mut self, capacity: usize) -> Self {
+        self.log_capacity = Some(capacity);
+        self
+    }
+
+    /// Streams the events which pass the filter to `callback` as they
+    /// happen, independently of buffering.
+    pub fn on_event(mut self, callback: impl Fn(&salsa::Event) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Arc::new(callback));
+        self
+    }
+
+    /// Keeps the parsed programs of only the `capacity` files parsed most
+    /// recently. The others are parsed again when needed.
+    pub fn parse_lru_capacity(mut self, capacity: usize) -> Self {
+        self.parse_lru_capacity = Some(capacity);
+        self
+    }
+
+    /// Counts the events of each query, whatever the log filter, to be read
+    /// with `Database::metrics`.
+    pub fn metrics(mut self, enabled: bool) -> Self {
+        self.metrics = enabled;
+        self
+    }
+
+    /// Keeps only the `capacity` evaluation results used most recently.
+    pub fn eval_lru_capacity(mut self, capacity: usize) -> Self {
+        self.eval_lru_capacity = Some(capacity);
+        self
+    }
+
+    /// Fails to load files longer than `bytes`, instead of reading them
+    /// into memory whole.
+    pub fn max_file_size(mut self, bytes: usize) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    pub fn build(self) -> Database {
+        let logs = self.logging.then(|| {
+            Arc::new(Mutex::new(LogBuffer {
+                events: VecDeque::new(),
+                capacity: self.log_capacity,
+            }))
+        });
+        let db = Database {
+            logs,
+            on_event: self.on_event,
+            log_filter: self.log_filter,
+            metrics: self.metrics.then(Default::default),
+            max_file_size: self.max_file_size,
+            ..Database::default()
+        };
+        if let Some(capacity) = self.parse_lru_capacity {
+            parse_statements::set_lru_capacity(&db, capacity);
+        }
+        if let Some(capacity) = self.eval_lru_capacity {
+            crate::eval::eval_program::set_lru_capacity(&db, capacity);
+        }
+        db
+    }
+}
+
 impl Database {
+    pub fn builder() -> DatabaseBuilder {
+        DatabaseBuilder::default()
+    }
+
     /// Enable logging of each salsa event.
     //    #[cfg(test)]
     pub fn enable_logging(self) -> Self {
         assert!(self.logs.is_none());
         Self {
-            storage: self.storage,
             logs: Some(Default::default()),
+            ..self
         }
     }
 
+    /// Removes and returns the buffered events.
     //   #[cfg(test)]
-    pub fn take_logs(&mut self) -> Vec<String> {
+    pub fn take_logs(&mut self) -> Logs {
         if let Some(logs) = &self.logs {
-            std::mem::take(&mut *logs.lock().unwrap())
+            Logs {
+                events: logs.lock().unwrap().events.drain(..).collect(),
+            }
         } else {
             panic!("logs not enabled");
         }
     }
+
+    /// The counts of each query's events since the database was built or
+    /// `reset_metrics` was last called. Memos dropped by an LRU limit
+    /// aren't noticed, so are still counted. Panics if the metrics weren't
+    /// enabled with `DatabaseBuilder::metrics`.
+    pub fn metrics(&self) -> Metrics {
+        match &self.metrics {
+            Some(metrics) => metrics.lock().unwrap().metrics.clone(),
+            None => panic!("metrics not enabled"),
+        }
+    }
+
+    /// Starts counting executions and validations from zero again.
+    pub fn reset_metrics(&self) {
+        if let Some(metrics) = &self.metrics {
+            for query in metrics.lock().unwrap().metrics.queries.values_mut() {
+                query.executions = 0;
+                query.validations = 0;
+            }
+        }
+    }
+
+    /// Creates an input for the program `text`. Queries derived only from
+    /// inputs of `Durability::HIGH`, which are expected to hardly ever
+    /// change, aren't revalidated when inputs of a lower durability change.
+    /// The durability can be changed when setting the text, with
+    /// `set_text(db).with_durability(..)`.
+    pub fn new_source(&mut self, text: String, durability: Durability) -> SourceProgram {
+        let source = SourceProgram::new(self, String::new());
+        source.set_text(self).with_durability(durability).to(text);
+        source
+    }
+
+    /// Loads the file at `path`, and the files it includes. Files are read
+    /// once: loading a file again gives the same input, without picking up
+    /// changes on disk.
+    pub fn load_source(&mut self, path: &Path) -> std::io::Result<SourceProgram> {
+        let path = path.canonicalize()?;
+        if let Some(source) = self.files().by_path(self).get(&path) {
+            return Ok(*source);
+        }
+        let text = self.read_file(&path)?;
+        let source = SourceProgram::new(self, text);
+        self.add_file(&path, source);
+        Ok(source)
+    }
+
+    /// Loads the file at `path` with a high durability, for a prelude or
+    /// library which doesn't change while the user's files are edited.
+    /// Includes of the file use this input.
+    pub fn load_prelude(&mut self, path: &Path) -> std::io::Result<SourceProgram> {
+        let path = path.canonicalize()?;
+        let text = self.read_file(&path)?;
+        let loaded = self.files().by_path(self).get(&path).copied();
+        let source = match loaded {
+            Some(source) => {
+                source
+                    .set_text(self)
+                    .with_durability(Durability::HIGH)
+                    .to(text);
+                source
+            }
+            None => self.new_source(text, Durability::HIGH),
+        };
+        self.add_file(&path, source);
+        Ok(source)
+    }
+
+    /// Records `source` as the program of the file at the canonical `path`,
+    /// and loads the files it includes.
+    fn add_file(&mut self, path: &Path, source: SourceProgram) {
+        let files = self.files();
+        let mut by_path = files.by_path(self).clone();
+        by_path.insert(path.to_path_buf(), source);
+        files.set_by_path(self).to(by_path);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        self.load_includes(source, dir);
+    }
+
+    /// Loads the files `source` includes, and the files they include, so
+    /// that its program can be parsed. Paths are relative to `dir`, which
+    /// for a file loaded from disk is its directory. This is done when a
+    /// file is loaded, but has to be done again when the includes of a
+    /// program change.
+    pub fn load_includes(&mut self, source: SourceProgram, dir: &Path) {
+        let (_, includes) = parse_included(self, source);
+        for include in includes.clone() {
+            let resolved = self
+                .load_source(&dir.join(&include.path))
+                .map_err(|err| err.to_string());
+            let files = self.files();
+            let key = (source, include.path);
+            if files.includes(self).get(&key) == Some(&resolved) {
+                continue;
+            }
+            let mut includes = files.includes(self).clone();
+            includes.insert(key, resolved);
+            files.set_includes(self).to(includes);
+        }
+    }
+
+    /// A read-only view of the database as it is now, which can be sent to
+    /// another thread to run queries there. Changing an input of this
+    /// database cancels the queries running on its snapshots, and waits for
+    /// the snapshots to be dropped.
+    pub fn snapshot(&self) -> salsa::Snapshot<Self> {
+        salsa::ParallelDatabase::snapshot(self)
+    }
+
+    /// Compiles `source_program` on another thread, with a snapshot of the
+    /// database. The compile is cancelled by `CompileHandle::cancel`, or by
+    /// changing an input of this database: salsa then unwinds the worker, and
+    /// the change goes ahead once the snapshot is dropped.
+    pub fn compile_in_background(&self, source_program: SourceProgram) -> CompileHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let snapshot = salsa::Snapshot::new(Database {
+            cancelled: Some(cancelled.clone()),
+            ..self.fork()
+        });
+        let thread = std::thread::spawn(move || {
+            let db = &*snapshot;
+            salsa::Cancelled::catch(std::panic::AssertUnwindSafe(|| {
+                compile_full(db, source_program, CompileMode::Check)
+            }))
+            .ok()
+        });
+        CompileHandle { cancelled, thread }
+    }
+
+    /// Reads standard input to the end into a new input, with the same
+    /// size limit as files. Its includes are relative to the current
+    /// directory.
+    pub fn load_stdin(&mut self) -> std::io::Result<SourceProgram> {
+        let text = self.read_limited(std::io::stdin().lock())?;
+        let source = SourceProgram::new(self, text);
+        self.load_includes(source, Path::new(""));
+        Ok(source)
+    }
+
+    fn read_file(&self, path: &Path) -> std::io::Result<String> {
+        self.read_limited(std::fs::File::open(path)?)
+    }
+
+    /// Reads `reader` to the end, stopping as soon as it's longer than the
+    /// file size limit.
+    fn read_limited(&self, reader: impl Read) -> std::io::Result<String> {
+        let limit = self.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE);
+        let mut bytes = vec![];
+        reader.take(limit as u64 + 1).read_to_end(&mut bytes)?;
+        if bytes.len() > limit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("the file is larger than the limit of {limit} bytes"),
+            ));
+        }
+        String::from_utf8(bytes)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    fn should_log(&self, event: &LogEvent) -> bool {
+        match &self.log_filter {
+            LogFilter::WillExecute => matches!(
+                event.kind,
+                LogEventKind::WillExecute | LogEventKind::ParsedFunction { .. }
+            ),
+            LogFilter::All => true,
+            LogFilter::Queries(names) => event
+                .query()
+                .is_some_and(|query| names.iter().any(|name| name == query)),
+        }
+    }
+}
+
+// ANCHOR: jar_db_impl
+impl crate::Db for Database {
+    fn files(&self) -> Files {
+        self.files
+            .expect("the files are set when the database is created")
+    }
+
+    fn log(&self, event: LogEvent) {
+        if self.should_log(&event) {
+            self.push_log(event);
+        }
+    }
 }
+// ANCHOR_END: jar_db_impl
 
 // ANCHOR: db_impl
 impl salsa::Database for Database {
     fn salsa_event(&self, event: salsa::Event) {
-        // Log interesting events, if logging is enabled
+        if let Some(cancelled) = &self.cancelled {
+            if cancelled.load(Ordering::Relaxed) {
+                // Unwinds the same way as salsa's own cancellation, so that
+                // `salsa::Cancelled::catch` stops the compile.
+                std::panic::resume_unwind(Box::new(salsa::Cancelled::PendingWrite));
+            }
+        }
+        if self.logs.is_none() && self.on_event.is_none() && self.metrics.is_none() {
+            return;
+        }
+        let log_event = LogEvent::new(self, &event);
+        if let Some(metrics) = &self.metrics {
+            metrics.lock().unwrap().record(&log_event);
+        }
+        if !self.should_log(&log_event) {
+            return;
+        }
+        if let Some(on_event) = &self.on_event {
+            on_event(&event);
+        }
+        self.push_log(log_event);
+    }
+}
+
+impl Database {
+    /// Logs `event`, if logging is enabled.
+    fn push_log(&self, event: LogEvent) {
         if let Some(logs) = &self.logs {
-            // don't log boring events
-            if let salsa::EventKind::WillExecute { .. } = event.kind {
-                logs.lock()
-                    .unwrap()
-                    .push(format!("Event: {:?}", event.debug(self)));
+            let mut logs = logs.lock().unwrap();
+            logs.events.push_back(event);
+            if let Some(capacity) = logs.capacity {
+                while logs.events.len() > capacity {
+                    logs.events.pop_front();
+                }
             }
         }
     }
@@ -54,10 +618,345 @@ impl salsa::Database for Database {
 // ANCHOR: par_db_impl
 impl salsa::ParallelDatabase for Database {
     fn snapshot(&self) -> salsa::Snapshot<Self> {
-        salsa::Snapshot::new(Database {
+        salsa::Snapshot::new(self.fork())
+    }
+}
+
+impl Database {
+    /// A database sharing this one's storage, to be wrapped in a
+    /// `salsa::Snapshot`.
+    fn fork(&self) -> Database {
+        Database {
             storage: self.storage.snapshot(),
             logs: self.logs.clone(),
-        })
+            on_event: self.on_event.clone(),
+            log_filter: self.log_filter.clone(),
+            files: self.files,
+            cancelled: self.cancelled.clone(),
+            metrics: self.metrics.clone(),
+            max_file_size: self.max_file_size,
+        }
     }
 }
 // ANCHOR_END: par_db_impl
+
+/// A compile running on another thread, started by
+/// `Database::compile_in_background`.
+pub struct CompileHandle {
+    cancelled: Arc<AtomicBool>,
+    thread: JoinHandle<Option<CompileResult>>,
+}
+
+impl CompileHandle {
+    /// Stops the compile at the next query it runs.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits for the compile to finish. Returns `None` if it was cancelled,
+    /// in which case its partial results are discarded.
+    pub fn join(self) -> Option<CompileResult> {
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+#[cfg(test)]
+fn type_check_string(db: &Database, source_text: &str) {
+    let source_program = SourceProgram::new(db, source_text.to_string());
+    let program = crate::parser::parse_statements(db, source_program);
+    crate::type_check::type_check_program(db, program);
+}
+
+#[test]
+fn take_logs_drains() {
+    let mut db = Database::builder().logging(true).build();
+    type_check_string(&db, "fn f(x) = x; fn g(x) = f(x);");
+    assert!(!db.take_logs().events.is_empty());
+    assert!(db.take_logs().events.is_empty());
+}
+
+#[test]
+fn log_capacity_evicts_oldest() {
+    let source_text = "fn f(x) = x; fn g(x) = f(x); print g(1);";
+    let mut unlimited = Database::builder().logging(true).build();
+    type_check_string(&unlimited, source_text);
+    let all = unlimited.take_logs().events;
+    assert!(all.len() > 2);
+
+    let mut db = Database::builder().logging(true).log_capacity(2).build();
+    type_check_string(&db, source_text);
+    assert_eq!(db.take_logs().events, all[all.len() - 2..]);
+}
+
+#[test]
+fn log_filter_by_query() {
+    let mut db = Database::builder()
+        .logging(true)
+        .log_filter(LogFilter::Queries(vec!["type_check_function".into()]))
+        .build();
+    type_check_string(&db, "fn f(x) = x; fn g(x) = f(x);");
+    let logs = db.take_logs();
+    assert_eq!(logs.count(LogEventKind::WillExecute), 2);
+    assert!(logs
+        .events
+        .iter()
+        .all(|event| event.query() == Some("type_check_function")));
+}
+
+#[test]
+fn log_parsed_functions() {
+    let mut db = Database::builder().logging(true).build();
+    let source_program = SourceProgram::new(&db, "fn f(x) = x * 2; print f(1);".to_string());
+    parse_statements(&db, source_program);
+    let parsed: Vec<_> = db
+        .take_logs()
+        .events
+        .iter()
+        .filter(|event| event.query().is_none())
+        .map(|event| event.to_string())
+        .collect();
+    assert_eq!(parsed, ["ParsedFunction { node_count: 3 } f"]);
+}
+
+#[test]
+fn stream_events() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let db = Database::builder()
+        .on_event({
+            let count = count.clone();
+            move |_| {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .build();
+    type_check_string(&db, "fn f(x) = x;");
+    let mut logged = Database::builder().logging(true).build();
+    type_check_string(&logged, "fn f(x) = x;");
+    assert_eq!(
+        count.load(Ordering::Relaxed),
+        logged.take_logs().events.len()
+    );
+}
+
+#[test]
+fn metrics_count_queries() {
+    let db = Database::builder().metrics(true).build();
+    type_check_string(&db, "fn f(x) = x; fn g(x) = f(x);");
+    let checked = QueryMetrics {
+        executions: 2,
+        validations: 0,
+        memos: 2,
+    };
+    assert_eq!(db.metrics().get("type_check_function"), checked);
+    assert_eq!(db.metrics().get("parse_statements").executions, 1);
+
+    // The memos are still there after a reset.
+    db.reset_metrics();
+    let reset = QueryMetrics {
+        executions: 0,
+        validations: 0,
+        memos: 2,
+    };
+    assert_eq!(db.metrics().get("type_check_function"), reset);
+    assert_eq!(db.metrics().get("no_such_query"), QueryMetrics::default());
+}
+
+#[test]
+fn metrics_across_edit() {
+    let mut db = Database::builder().metrics(true).build();
+    let source_program = SourceProgram::new(&db, "fn f(x) = x; fn g(x) = x * 2;".to_string());
+    crate::compile::compile_diagnostics(&db, source_program);
+    db.reset_metrics();
+
+    // Only `g` is parsed and checked again; `f`'s check is validated.
+    source_program
+        .set_text(&mut db)
+        .to("fn f(x) = x; fn g(x) = x * 3;".to_string());
+    crate::compile::compile_diagnostics(&db, source_program);
+    let metrics = db.metrics();
+    assert_eq!(metrics.get("parse_item").executions, 1);
+    let type_check = metrics.get("type_check_function");
+    assert_eq!(type_check.executions, 1);
+    assert!(type_check.validations >= 1);
+    assert_eq!(type_check.memos, 2);
+}
+
+#[test]
+fn executed_queries_on_recompile() {
+    let mut db = Database::builder().logging(true).build();
+    let source_program = SourceProgram::new(&db, "fn f(x) = x; fn g(x) = x * 2;".to_string());
+    crate::compile::compile_diagnostics(&db, source_program);
+    let executed = db.take_logs().executed_queries();
+    assert!(executed.contains(&"parse_statements(0)".to_string()));
+    let type_checked = executed
+        .iter()
+        .filter(|key| key.starts_with("type_check_function("))
+        .count();
+    assert_eq!(type_checked, 2);
+
+    // Nothing changed: every result is reused.
+    crate::compile::compile_diagnostics(&db, source_program);
+    assert_eq!(db.take_logs(), Logs::default());
+
+    // The same text again: the file is split into the same items as before,
+    // so nothing else runs.
+    source_program
+        .set_text(&mut db)
+        .to("fn f(x) = x; fn g(x) = x * 2;".to_string());
+    crate::compile::compile_diagnostics(&db, source_program);
+    assert_eq!(db.take_logs().executed_queries(), ["split_into_items(0)"]);
+}
+
+#[test]
+fn snapshot_sees_old_text() {
+    let mut db = Database::default();
+    let source_program = SourceProgram::new(&db, "fn f(x) = y;".to_string());
+    let snapshot = db.snapshot();
+    let reader = std::thread::spawn(move || source_program.text(&*snapshot).clone());
+    // The snapshot was taken before the edit, whenever the thread reads it.
+    // The edit waits for the thread to drop it.
+    source_program
+        .set_text(&mut db)
+        .to("fn f(x) = x;".to_string());
+    assert_eq!(reader.join().unwrap(), "fn f(x) = y;");
+    assert_eq!(source_program.text(&db), "fn f(x) = x;");
+    assert!(crate::compile::compile_diagnostics(&db, source_program).is_empty());
+}
+
+#[test]
+fn parse_lru_evicts_least_recent() {
+    let mut db = Database::builder()
+        .logging(true)
+        .parse_lru_capacity(2)
+        .build();
+    let sources: Vec<_> = (0..3)
+        .map(|i| SourceProgram::new(&db, format!("print {i};")))
+        .collect();
+    for source in &sources {
+        parse_statements(&db, *source);
+    }
+    db.take_logs();
+
+    // The last file parsed is still cached, but the first was evicted.
+    parse_statements(&db, sources[2]);
+    assert_eq!(db.take_logs(), Logs::default());
+    parse_statements(&db, sources[0]);
+    assert_eq!(db.take_logs().executed_queries(), ["parse_statements(0)"]);
+}
+
+/// Checks a prelude and a file including it, edits the file, and checks
+/// both again, returning the number of memoized values validated after the
+/// edit.
+#[cfg(test)]
+fn validations_after_edit(dir: &Path, prelude_loaded_as_prelude: bool) -> usize {
+    let mut db = Database::builder()
+        .logging(true)
+        .log_filter(LogFilter::All)
+        .build();
+    let path = dir.join("prelude.banana");
+    let prelude = if prelude_loaded_as_prelude {
+        db.load_prelude(&path)
+    } else {
+        db.load_source(&path)
+    }
+    .unwrap();
+    let user_text = |n| format!("include \"prelude.banana\"; print twice({n});");
+    let user = db.new_source(user_text(1), Durability::LOW);
+    db.load_includes(user, dir);
+    let check = |db: &Database| {
+        for source in [prelude, user] {
+            crate::lint::lint_program(db, parse_statements(db, source));
+        }
+    };
+    check(&db);
+    db.take_logs();
+    user.set_text(&mut db).to(user_text(2));
+    check(&db);
+    db.take_logs().count(LogEventKind::DidValidateMemoizedValue)
+}
+
+#[test]
+fn prelude_skips_revalidation() {
+    let dir = crate::parser::write_fixture(
+        "durability",
+        &[(
+            "prelude.banana",
+            "fn double(x) = x * 2; fn twice(x) = double(double(x));",
+        )],
+    );
+    // With a low durability, linting the prelude is validated by
+    // revalidating the lints of each of its functions too.
+    assert!(validations_after_edit(&dir, true) < validations_after_edit(&dir, false));
+}
+
+/// A database which sleeps for `delay` before each query it executes, so a
+/// compile can be cancelled part way.
+#[cfg(test)]
+fn slow_database(delay: std::time::Duration) -> Database {
+    Database::builder()
+        .on_event(move |_| std::thread::sleep(delay))
+        .build()
+}
+
+#[cfg(test)]
+fn many_functions() -> String {
+    (0..100)
+        .map(|i| format!("fn f{i}(x) = x * {i};\n"))
+        .collect()
+}
+
+#[test]
+fn background_compile() {
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, "fn f(x) = y;".to_string());
+    let result = db.compile_in_background(source_program).join().unwrap();
+    assert_eq!(result.program.functions(&db).len(), 1);
+    let messages: Vec<_> = result
+        .diagnostics
+        .into_iter()
+        .map(|(_, diagnostic)| diagnostic.message)
+        .collect();
+    assert_eq!(messages, ["the variable `y` is not declared"]);
+}
+
+#[test]
+fn cancel_background_compile() {
+    let db = slow_database(std::time::Duration::from_millis(10));
+    let source_program = SourceProgram::new(&db, many_functions());
+    let handle = db.compile_in_background(source_program);
+    handle.cancel();
+    assert!(handle.join().is_none());
+}
+
+#[test]
+fn edit_cancels_background_compile() {
+    let mut db = slow_database(std::time::Duration::from_millis(10));
+    let source_program = SourceProgram::new(&db, many_functions());
+    let handle = db.compile_in_background(source_program);
+    // Waits for the compile to unwind and drop its snapshot.
+    source_program
+        .set_text(&mut db)
+        .to("fn f(x) = x;".to_string());
+    assert!(handle.join().is_none());
+    assert!(crate::compile::compile_diagnostics(&db, source_program).is_empty());
+}
+
+#[test]
+fn max_file_size() {
+    let dir = crate::parser::write_fixture("max-file-size", &[("big.banana", "print 1234567890;")]);
+    let path = dir.join("big.banana");
+    let mut db = Database::builder().max_file_size(10).build();
+    let err = db.load_source(&path).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "the file is larger than the limit of 10 bytes"
+    );
+    // Exactly at the limit.
+    let mut db = Database::builder().max_file_size(17).build();
+    assert!(db.load_source(&path).is_ok());
+}
@@ -0,0 +1,1473 @@
+use std::{collections::HashMap, fmt};
+
+use ordered_float::OrderedFloat;
+
+use crate::builtins::{find_builtin, Builtin};
+use crate::ir::{
+    display_expr, Diagnostic, Diagnostics, ExprArena, ExprId, ExpressionData, Function, FunctionId,
+    Op, Program, Severity, Span, StatementData, VariableId,
+};
+use crate::parser::parse_expression;
+use crate::type_check::{
+    argument_order, arity_mismatch, bind_arguments, builtin_arguments, check_expression,
+    find_function, lambda_arity_mismatch,
+};
+use crate::value::{dispatch_op, Operands, Value, ValueKey};
+
+/// A line printed by a `print` statement or expression.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct OutputLine {
+    /// The `print` statement or expression.
+    pub span: Span,
+    pub value: Value,
+    /// The value as printed.
+    pub text: String,
+    /// How many diagnostics the run had reported when the line was printed,
+    /// so that the two can be shown in the order they happened.
+    pub diagnostics_before: usize,
+}
+
+impl fmt::Display for OutputLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// What running a program printed, and how much work it took.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct EvalOutput {
+    pub lines: Vec<OutputLine>,
+    pub stats: EvalStats,
+}
+
+/// Counters for a run of a program (`--stats`). They're deterministic, so
+/// that a query which runs a program has the same result each time; the run
+/// is timed by the caller.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct EvalStats {
+    /// Top-level statements run, including ones which failed.
+    pub statements: usize,
+    /// User function calls, not counting ones answered from the cache.
+    pub calls: usize,
+    /// The deepest nesting of user function calls.
+    pub max_depth: usize,
+    /// User function calls answered from the cache (see
+    /// `EvalOptions::memoize`).
+    pub memo_hits: usize,
+    /// Calls whose result was reused from the same call earlier in the
+    /// function body or statement (see `EvalOptions::cse`).
+    pub cse_hits: usize,
+}
+
+impl fmt::Display for EvalStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "statements: {}", self.statements)?;
+        writeln!(f, "calls: {}", self.calls)?;
+        writeln!(f, "max depth: {}", self.max_depth)?;
+        writeln!(f, "memo hits: {}", self.memo_hits)?;
+        writeln!(f, "cse hits: {}", self.cse_hits)
+    }
+}
+
+/// Something that happened while running a program.
+#[derive(Clone, Copy, Debug)]
+pub enum OutputEvent<'a> {
+    Print(&'a OutputLine),
+    Diagnostic(&'a Diagnostic),
+}
+
+/// Merges a run's output with the diagnostics it reported, in the order
+/// they happened.
+pub fn interleave<'a>(
+    output: &'a [OutputLine],
+    diagnostics: &'a [Diagnostic],
+) -> Vec<OutputEvent<'a>> {
+    let mut events = vec![];
+    let mut diagnostics = diagnostics.iter().enumerate().peekable();
+    for line in output {
+        while let Some((_, diagnostic)) = diagnostics.next_if(|(i, _)| *i < line.diagnostics_before)
+        {
+            events.push(OutputEvent::Diagnostic(diagnostic));
+        }
+        events.push(OutputEvent::Print(line));
+    }
+    events.extend(diagnostics.map(|(_, diagnostic)| OutputEvent::Diagnostic(diagnostic)));
+    events
+}
+
+/// Formats a number for output. Integers are printed without a decimal
+/// point, other values in the shortest form which parses back to the same
+/// value. Negative zero prints as `0`.
+pub fn format_number(value: f64) -> String {
+    if value == 0.0 {
+        "0".to_string()
+    } else if value.is_nan() {
+        "nan".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub struct EvalOptions {
+    /// Treat division by zero and operations producing NaN as errors which
+    /// stop evaluation (`--strict-math`), rather than warnings.
+    pub strict_math: bool,
+    /// The maximum number of nested user function calls (`--max-depth`).
+    /// Deeper calls stop the statement with an error, instead of
+    /// overflowing the stack, as long as the stack has room for this many
+    /// calls: a debug build needs about 11 KiB per call, so the `banana`
+    /// binary evaluates on a thread with a larger stack.
+    pub max_depth: usize,
+    /// Cache the results of user function calls by their arguments
+    /// (`--memoize`). Calls which print aren't cached, as they must print
+    /// again; math warnings are only reported for the first call.
+    pub memoize: bool,
+    /// Evaluate a call only once if it appears again, with the same values
+    /// of its variables, in the same evaluation of a function body or
+    /// statement (`--cse`), e.g. `f(x)` in `f(x) + f(x)`. As with
+    /// `memoize`, calls which print are evaluated every time.
+    pub cse: bool,
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self {
+            strict_math: false,
+            max_depth: 1000,
+            memoize: false,
+            cse: false,
+        }
+    }
+}
+
+/// The function `f` of `program` or the builtin of that name, if it takes
+/// `arity` arguments, as `Runtime::resolve_call`; otherwise the error
+/// message.
+pub(crate) fn resolve_callee(
+    db: &dyn crate::Db,
+    program: Option<Program>,
+    f: FunctionId,
+    arity: usize,
+) -> Result<Callee, String> {
+    let function = program.and_then(|program| find_function(db, program, f));
+    let (callee, expected) = if let Some(function) = function {
+        (Callee::User(function), Some(function.data(db).args.len()))
+    } else if let Some(builtin) = find_builtin(f.text(db)) {
+        (Callee::Builtin(builtin), builtin.arity())
+    } else {
+        return Err(format!("the function `{}` is not declared", f.text(db)));
+    };
+    match expected {
+        Some(expected) if expected != arity => Err(arity_mismatch(db, f, expected, arity)),
+        _ => Ok(callee),
+    }
+}
+
+/// How many calls of the call stack to show when the maximum depth is
+/// exceeded.
+const TRACE_LENGTH: usize = 4;
+
+/// Runs the top-level statements of the program in order, returning what
+/// they print. A statement which fails at runtime (e.g. calls an undeclared
+/// function) pushes a diagnostic and prints nothing; the following
+/// statements still run, unless it failed on a math event with
+/// `options.strict_math`.
+///
+/// The results are kept unless a limit is set with
+/// `DatabaseBuilder::eval_lru_capacity`.
+#[salsa::tracked(return_ref, lru = 0)]
+pub fn eval_program(db: &dyn crate::Db, program: Program, options: EvalOptions) -> EvalOutput {
+    let mut evaluator = Evaluator::new(db, Some(program), options);
+    for statement in program.statements(db) {
+        evaluator.runtime.stats.statements += 1;
+        match &statement.data {
+            StatementData::Print(expression) => {
+                let result = evaluator.eval_body(&statement.arena, *expression, &HashMap::new());
+                match result {
+                    Ok(value) => evaluator.runtime.print(value, statement.span),
+                    Err(diagnostic) => {
+                        evaluator.runtime.report(Some(diagnostic));
+                        if evaluator.runtime.math_failed {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    EvalOutput {
+        lines: evaluator.runtime.output,
+        stats: evaluator.runtime.stats,
+    }
+}
+
+/// Evaluates the expression `id` of `arena`, which can't refer to any
+/// variables or functions, i.e. the contents of a source file in expression
+/// mode.
+pub fn eval_bare_expression(
+    db: &dyn crate::Db,
+    arena: &ExprArena,
+    id: ExprId,
+) -> Result<f64, Diagnostic> {
+    let mut evaluator = Evaluator::new(db, None, EvalOptions::default());
+    let value = evaluator.eval(arena, id, &HashMap::new())?;
+    evaluator.runtime.expect_number(&value, arena[id].span)
+}
+
+/// Evaluates `source_text` as an expression which may call the program's
+/// functions, e.g. to use banana as a calculator. Fails with the diagnostics
+/// if the expression doesn't parse or type check, or if evaluating it fails.
+///
+/// ```
+/// use banana::{db::Database, eval_expr, ir::SourceProgram, parser::parse_statements};
+///
+/// let db = Database::default();
+/// let source = SourceProgram::new(&db, "fn area_rectangle(w, h) = w * h;".to_string());
+/// let program = parse_statements(&db, source);
+/// assert_eq!(eval_expr(&db, program, "area_rectangle(2, 3) * 3").unwrap(), 18.0);
+/// assert!(eval_expr(&db, program, "area_circle(2)").is_err());
+/// ```
+pub fn eval_expr(
+    db: &dyn crate::Db,
+    program: Program,
+    source_text: &str,
+) -> Result<f64, Vec<Diagnostic>> {
+    let source_text = source_text.to_string();
+    let value = eval_source_expression(db, program, source_text.clone());
+    let diagnostics = eval_source_expression::accumulated::<Diagnostics>(db, program, source_text);
+    match value {
+        Some(value) => Ok(value.into_inner()),
+        None => Err(diagnostics),
+    }
+}
+
+/// The query behind `eval_expr`. Diagnostics are accumulated, since the type
+/// checker reports them that way.
+#[salsa::tracked]
+pub fn eval_source_expression(
+    db: &dyn crate::Db,
+    program: Program,
+    source_text: String,
+) -> Option<OrderedFloat<f64>> {
+    let (arena, expression) = match parse_expression(db, &source_text) {
+        Ok(parsed) => parsed,
+        Err(diagnostic) => {
+            Diagnostics::push(db, diagnostic);
+            return None;
+        }
+    };
+    if !check_expression(db, program, &arena, expression) {
+        return None;
+    }
+    let mut evaluator = Evaluator::new(db, Some(program), EvalOptions::default());
+    let result = evaluator
+        .eval(&arena, expression, &HashMap::new())
+        .and_then(|value| {
+            evaluator
+                .runtime
+                .expect_number(&value, arena[expression].span)
+        });
+    let value = result.as_ref().ok().map(|&value| value.into());
+    evaluator.runtime.report(result.err());
+    value
+}
+
+struct Evaluator<'db> {
+    runtime: Runtime<'db>,
+    /// Results of the user function calls so far, if `options.memoize` is
+    /// set.
+    cache: HashMap<(Function, Vec<ValueKey>), Value>,
+    /// The calls made in each function body or statement being evaluated,
+    /// innermost last, if `options.cse` is set.
+    shared: Vec<SharedCalls>,
+}
+
+/// The results of calls by the structural hash of the call and the values
+/// of its variables, with the call they're the result of.
+type SharedCalls = HashMap<(u64, Vec<ValueKey>), (ExprId, Value)>;
+
+impl<'db> Evaluator<'db> {
+    fn new(db: &'db dyn crate::Db, program: Option<Program>, options: EvalOptions) -> Self {
+        Self {
+            runtime: Runtime::new(db, program, options),
+            cache: HashMap::new(),
+            shared: vec![],
+        }
+    }
+
+    /// Evaluates the expression `id` of `arena` as a function body or
+    /// statement, whose calls are shared only within it.
+    fn eval_body(
+        &mut self,
+        arena: &ExprArena,
+        id: ExprId,
+        env: &HashMap<VariableId, Value>,
+    ) -> Result<Value, Diagnostic> {
+        self.shared.push(HashMap::new());
+        let result = self.eval(arena, id, env);
+        self.shared.pop();
+        result
+    }
+
+    /// Evaluates the call `id`, or reuses the result of the same call made
+    /// before in the function body or statement.
+    fn eval_shared(
+        &mut self,
+        arena: &ExprArena,
+        id: ExprId,
+        env: &HashMap<VariableId, Value>,
+    ) -> Result<Value, Diagnostic> {
+        let values = arena
+            .free_variables(id)
+            .iter()
+            .map(|v| env.get(v).map(Value::key))
+            .collect::<Option<Vec<_>>>();
+        // An undeclared variable fails the same way every time.
+        let Some(values) = values else {
+            return self.eval_call(arena, id, env);
+        };
+        let key = (arena.structural_hash(id), values);
+        if let Some((other, value)) = self.shared.last().and_then(|shared| shared.get(&key)) {
+            if arena.same_tree(*other, id) {
+                self.runtime.stats.cse_hits += 1;
+                return Ok(value.clone());
+            }
+        }
+        let printed = self.runtime.output.len();
+        let result = self.eval_call(arena, id, env);
+        // A call which printed must print again.
+        let pure = self.runtime.output.len() == printed;
+        if let (true, Ok(value), Some(shared)) = (pure, &result, self.shared.last_mut()) {
+            shared.entry(key).or_insert((id, value.clone()));
+        }
+        result
+    }
+
+    /// Evaluates the expression `id` of `arena`.
+    fn eval(
+        &mut self,
+        arena: &ExprArena,
+        id: ExprId,
+        env: &HashMap<VariableId, Value>,
+    ) -> Result<Value, Diagnostic> {
+        let span = arena[id].span;
+        match &arena[id].data {
+            ExpressionData::Op(left, op, right) => {
+                let left = self.eval(arena, *left, env)?;
+                let right = self.eval(arena, *right, env)?;
+                self.runtime
+                    .apply_op(*op, &left, &right, span, Some((arena, id)))
+            }
+            ExpressionData::Neg(operand) => {
+                let operand = self.eval(arena, *operand, env)?;
+                Ok(Value::from(-self.runtime.expect_number(&operand, span)?))
+            }
+            ExpressionData::Abs(operand) => {
+                let operand = self.eval(arena, *operand, env)?;
+                Ok(Value::from(
+                    self.runtime.expect_number(&operand, span)?.abs(),
+                ))
+            }
+            ExpressionData::Number(n) => Ok(Value::Number(*n)),
+            ExpressionData::Bool(b) => Ok(Value::Bool(*b)),
+            ExpressionData::Variable(v) => match env.get(v) {
+                Some(value) => Ok(value.clone()),
+                None => Err(self.runtime.undeclared_variable(*v, span)),
+            },
+            ExpressionData::Call(..) if self.runtime.options.cse => {
+                self.eval_shared(arena, id, env)
+            }
+            ExpressionData::Call(..) => self.eval_call(arena, id, env),
+            ExpressionData::Lambda(params, body, args) => {
+                let args = args
+                    .iter()
+                    .map(|&arg| self.eval(arena, arg, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.runtime
+                    .check_lambda_arity(params.len(), args.len(), span)?;
+                let env = params.iter().copied().zip(args).collect();
+                self.eval(arena, *body, &env)
+            }
+            ExpressionData::Let(name, value, body) => {
+                let value = self.eval(arena, *value, env)?;
+                let mut env = env.clone();
+                env.insert(*name, value);
+                self.eval(arena, *body, &env)
+            }
+            ExpressionData::Print(operand) => {
+                let value = self.eval(arena, *operand, env)?;
+                self.runtime.print(value.clone(), span);
+                Ok(value)
+            }
+            ExpressionData::Error(message) => Err(self.runtime.raise(message, span)),
+        }
+    }
+
+    /// Evaluates the call `id` of `arena`.
+    fn eval_call(
+        &mut self,
+        arena: &ExprArena,
+        id: ExprId,
+        env: &HashMap<VariableId, Value>,
+    ) -> Result<Value, Diagnostic> {
+        let db = self.runtime.db;
+        let span = arena[id].span;
+        let ExpressionData::Call(f, args) = &arena[id].data else {
+            unreachable!("not a call");
+        };
+        let callee = self.runtime.resolve_call(*f, args.len(), span)?;
+        let bound = match &callee {
+            Callee::User(function) => bind_arguments(db, *f, &function.data(db).args, arena, args),
+            Callee::Builtin(_) => builtin_arguments(db, *f, arena, args),
+        }
+        .map_err(|(span, message)| self.runtime.raise(&message, span))?;
+        // Arguments are evaluated in the order they're written.
+        let values = args
+            .iter()
+            .map(|arg| self.eval(arena, arg.value, env))
+            .collect::<Result<Vec<_>, _>>()?;
+        let args: Vec<_> = argument_order(&bound, args)
+            .into_iter()
+            .map(|index| values[index].clone())
+            .collect();
+        match callee {
+            Callee::User(function) => {
+                let data = function.data(db);
+                let key = (function, args.iter().map(Value::key).collect());
+                if let Some(result) = self.cache.get(&key) {
+                    self.runtime.stats.memo_hits += 1;
+                    return Ok(result.clone());
+                }
+                let env = data.args.iter().copied().zip(args).collect();
+                let printed = self.runtime.output.len();
+                self.runtime.enter_call(*f, span)?;
+                let result = self.eval_body(data.arena(db), data.body, &env);
+                self.runtime.exit_call();
+                // A call which printed must print again.
+                let pure = self.runtime.output.len() == printed;
+                if let (true, true, Ok(result)) = (self.runtime.options.memoize, pure, &result) {
+                    self.cache.insert(key, result.clone());
+                }
+                result
+            }
+            Callee::Builtin(builtin) => self.runtime.call_builtin(builtin, *f, &args, span),
+        }
+    }
+}
+
+/// What a call refers to.
+pub(crate) enum Callee {
+    User(Function),
+    Builtin(&'static Builtin),
+}
+
+/// The state and runtime error reporting shared by the evaluator and the
+/// bytecode VM, so that both report problems the same way.
+pub(crate) struct Runtime<'db> {
+    pub db: &'db dyn crate::Db,
+    /// The program to look up user functions in, if any. Builtins are always
+    /// available.
+    pub program: Option<Program>,
+    pub options: EvalOptions,
+    /// Math warnings (see `EvalOptions::strict_math`) reported so far.
+    pub warnings: Vec<Diagnostic>,
+    /// Whether a math event was an error, with `strict_math`, which stops
+    /// the program rather than just the statement.
+    pub math_failed: bool,
+    /// The number of diagnostics pushed by `report`.
+    pub reported: usize,
+    /// Where `report` pushes diagnostics when evaluating outside a query,
+    /// as `Stepper` does, instead of to the `Diagnostics` accumulator.
+    pub collected: Option<Vec<Diagnostic>>,
+    pub stats: EvalStats,
+    /// The lines printed so far.
+    pub output: Vec<OutputLine>,
+    /// The user function calls being evaluated, innermost last.
+    stack: Vec<(FunctionId, Span)>,
+}
+
+impl<'db> Runtime<'db> {
+    pub fn new(db: &'db dyn crate::Db, program: Option<Program>, options: EvalOptions) -> Self {
+        Self {
+            db,
+            program,
+            options,
+            warnings: vec![],
+            math_failed: false,
+            reported: 0,
+            collected: None,
+            stats: EvalStats::default(),
+            output: vec![],
+            stack: vec![],
+        }
+    }
+
+    /// Pushes the warnings reported so far, followed by `error` if any.
+    pub fn report(&mut self, error: Option<Diagnostic>) {
+        for diagnostic in self.warnings.drain(..).chain(error) {
+            match &mut self.collected {
+                Some(collected) => collected.push(diagnostic),
+                None => Diagnostics::push(self.db, diagnostic),
+            }
+            self.reported += 1;
+        }
+    }
+
+    /// Prints `value` for the `print` at `span`, after the warnings reported
+    /// so far.
+    pub fn print(&mut self, value: Value, span: Span) {
+        self.report(None);
+        self.output.push(OutputLine {
+            span,
+            text: value.to_string(),
+            value,
+            diagnostics_before: self.reported,
+        });
+    }
+
+    /// Finds the function called at `span`, checking it takes `arity`
+    /// arguments. User functions take precedence over builtins.
+    pub fn resolve_call(
+        &self,
+        f: FunctionId,
+        arity: usize,
+        span: Span,
+    ) -> Result<Callee, Diagnostic> {
+        resolve_callee(self.db, self.program, f, arity).map_err(|message| self.error(span, message))
+    }
+
+    /// Checks that a lambda with `arity` parameters, called at `span`, is
+    /// given that many arguments.
+    pub fn check_lambda_arity(
+        &self,
+        arity: usize,
+        given: usize,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        if arity == given {
+            Ok(())
+        } else {
+            Err(self.error(span, lambda_arity_mismatch(arity, given)))
+        }
+    }
+
+    /// Records a call to the user function `f` at `span`, unless that would
+    /// exceed `options.max_depth`.
+    pub fn enter_call(&mut self, f: FunctionId, span: Span) -> Result<(), Diagnostic> {
+        if self.stack.len() >= self.options.max_depth {
+            return Err(self.max_depth_exceeded(f, span));
+        }
+        self.stack.push((f, span));
+        self.stats.calls += 1;
+        self.stats.max_depth = self.stats.max_depth.max(self.stack.len());
+        Ok(())
+    }
+
+    pub fn exit_call(&mut self) {
+        self.stack.pop();
+    }
+
+    /// The user function calls being evaluated, as the function and the
+    /// span of the call, innermost last.
+    pub fn call_stack(&self) -> &[(FunctionId, Span)] {
+        &self.stack
+    }
+
+    /// Applies `op`, reporting division by zero, `0 / 0` and NaN results. If
+    /// the operation's expression is available, the message quotes it.
+    pub fn apply_op(
+        &mut self,
+        op: Op,
+        left: &Value,
+        right: &Value,
+        span: Span,
+        expression: Option<(&ExprArena, ExprId)>,
+    ) -> Result<Value, Diagnostic> {
+        let (left, right) = match dispatch_op(op, left, right) {
+            Ok(Operands::Numbers(left, right)) => (left, right),
+            Err(message) => return Err(self.error(span, message)),
+        };
+        let result = op.apply(left, right);
+        let message = if op == Op::Divide && left == 0.0 && right == 0.0 {
+            match expression {
+                Some((arena, id)) => {
+                    format!("indeterminate 0/0: `{}`", display_expr(self.db, arena, id))
+                }
+                None => "indeterminate 0/0".to_string(),
+            }
+        } else if op == Op::Divide && right == 0.0 {
+            let operation = format!("{} / 0", format_number(left));
+            match expression {
+                Some((arena, id)) => format!(
+                    "division by zero: `{}` is `{operation}`",
+                    display_expr(self.db, arena, id)
+                ),
+                None => format!("division by zero: `{operation}`"),
+            }
+        } else if result.is_nan() && !left.is_nan() && !right.is_nan() {
+            let operation = format!("{} {op} {}", format_number(left), format_number(right));
+            match expression {
+                Some((arena, id)) => format!(
+                    "`{}` is not a number: `{operation}`",
+                    display_expr(self.db, arena, id)
+                ),
+                None => format!("`{operation}` is not a number"),
+            }
+        } else {
+            return Ok(result.into());
+        };
+        self.math_event(span, message)?;
+        Ok(result.into())
+    }
+
+    pub fn call_builtin(
+        &mut self,
+        builtin: &Builtin,
+        f: FunctionId,
+        args: &[Value],
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        let args = args
+            .iter()
+            .map(|arg| self.expect_number(arg, span))
+            .collect::<Result<Vec<_>, _>>()?;
+        let call = || {
+            let args: Vec<_> = args.iter().map(|arg| format_number(*arg)).collect();
+            format!("{}({})", f.text(self.db), args.join(", "))
+        };
+        let result = (builtin.eval)(&args)
+            .map_err(|message| self.error(span, format!("`{}` is undefined: {message}", call())))?;
+        if result.is_nan() && !args.iter().any(|arg| arg.is_nan()) {
+            let message = format!("`{}` is not a number", call());
+            self.math_event(span, message)?;
+        }
+        Ok(result.into())
+    }
+
+    /// The number `value` holds, or a type error at `span`.
+    pub fn expect_number(&self, value: &Value, span: Span) -> Result<f64, Diagnostic> {
+        value.as_number().ok_or_else(|| {
+            self.error(
+                span,
+                format!("expected a number, found a {}", value.type_name()),
+            )
+        })
+    }
+
+    pub fn undeclared_variable(&self, v: VariableId, span: Span) -> Diagnostic {
+        self.error(
+            span,
+            format!("the variable `{}` is not declared", v.text(self.db)),
+        )
+    }
+
+    /// The error for `error(message)` at `span`, showing the calls leading
+    /// to it.
+    pub fn raise(&self, message: &str, span: Span) -> Diagnostic {
+        self.with_call_trace(self.error(span, message.to_string()))
+    }
+
+    /// The error for calling `f` at `span` with a full stack, showing the
+    /// innermost calls leading to it.
+    fn max_depth_exceeded(&self, f: FunctionId, span: Span) -> Diagnostic {
+        let message = format!(
+            "maximum recursion depth exceeded while calling `{}`",
+            f.text(self.db)
+        );
+        self.with_call_trace(self.error(span, message))
+    }
+
+    /// Adds the innermost calls being evaluated to `diagnostic`.
+    fn with_call_trace(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        for (caller, call_span) in self.stack.iter().rev().take(TRACE_LENGTH) {
+            diagnostic = diagnostic.with_related(
+                *call_span,
+                format!("in a call to `{}`", caller.text(self.db)),
+            );
+        }
+        diagnostic
+    }
+
+    /// Reports a result which is probably a mistake, but has a well-defined
+    /// value: a warning, or an error with `strict_math`.
+    fn math_event(&mut self, span: Span, message: String) -> Result<(), Diagnostic> {
+        let diagnostic = self.error(span, message);
+        if self.options.strict_math {
+            self.math_failed = true;
+            return Err(diagnostic);
+        }
+        self.warnings.push(Diagnostic {
+            severity: Severity::Warning,
+            ..diagnostic
+        });
+        Ok(())
+    }
+
+    /// An error at `span`. Runtime diagnostics use offsets into the whole
+    /// source, since the function a span is relative to isn't obvious from
+    /// the output.
+    fn error(&self, span: Span, message: String) -> Diagnostic {
+        let (start, end) = match self.program {
+            Some(program) => span.absolute(self.db, program),
+            None => (span.start, span.end),
+        };
+        Diagnostic::new(start, end, message)
+    }
+}
+
+#[cfg(test)]
+fn eval_bare_string(source_text: &str) -> Option<Result<f64, Diagnostic>> {
+    let db = crate::db::Database::default();
+    crate::parser::parse_bare_expression(&db, source_text)
+        .map(|(arena, expression)| eval_bare_expression(&db, &arena, expression))
+}
+
+#[test]
+fn eval_bare_expression_mode() {
+    let expected = expect_test::expect![[r#"
+        Some(
+            Ok(
+                7.0,
+            ),
+        )
+    "#]];
+    expected.assert_debug_eq(&eval_bare_string("1 + 2 * 3"));
+}
+
+#[test]
+fn eval_bare_unknown_variable() {
+    let expected = expect_test::expect![[r#"
+        Some(
+            Err(
+                Diagnostic {
+                    start: 4,
+                    end: 5,
+                    message: "the variable `x` is not declared",
+                    severity: Error,
+                    related: [],
+                },
+            ),
+        )
+    "#]];
+    expected.assert_debug_eq(&eval_bare_string("1 + x"));
+}
+
+#[test]
+fn statements_are_not_bare_expressions() {
+    assert!(eval_bare_string("print 1 + 2;").is_none());
+    assert!(eval_bare_string("fn f(x) = x;").is_none());
+    // A `;` in a comment or a string doesn't make a statement.
+    assert_eq!(eval_bare_string("1 + 2 // one; two").unwrap().unwrap(), 3.0);
+    assert!(eval_bare_string("error(\"a; b\")").unwrap().is_err());
+}
+
+#[test]
+fn eval_expr_against_program() {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(
+        &db,
+        "fn area_circle(r) = 3 * r * r; fn twice(x) = 2 * x;".to_string(),
+    );
+    let program = parse_statements(&db, source_program);
+    assert_eq!(
+        eval_expr(&db, program, "area_circle(2) * 3").ok(),
+        Some(36.0)
+    );
+    assert_eq!(
+        eval_expr(&db, program, "twice(sqrt(4)) + -1").ok(),
+        Some(3.0)
+    );
+    // Math warnings don't make the evaluation fail.
+    assert_eq!(eval_expr(&db, program, "1 / 0").ok(), Some(f64::INFINITY));
+
+    let messages = |source_text| match eval_expr(&db, program, source_text) {
+        Ok(value) => panic!("`{source_text}` evaluated to {value}"),
+        Err(diagnostics) => diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                format!(
+                    "{}..{}: {}",
+                    diagnostic.start, diagnostic.end, diagnostic.message
+                )
+            })
+            .collect::<Vec<_>>(),
+    };
+    let expected = expect_test::expect![[r#"
+        [
+            "0..7: the function `area` is not declared",
+            "16..17: the variable `x` is not declared",
+        ]
+    "#]];
+    expected.assert_debug_eq(&messages("area(1) + twice(x)"));
+    let expected = expect_test::expect![[r#"
+        [
+            "4..5: expected an operand here",
+        ]
+    "#]];
+    expected.assert_debug_eq(&messages("1 + * 2"));
+    let expected = expect_test::expect![[r#"
+        [
+            "0..6: `log(0)` is undefined: the logarithm is only defined for positive numbers",
+        ]
+    "#]];
+    expected.assert_debug_eq(&messages("log(0)"));
+}
+
+#[test]
+fn eval_bare_unary_minus_and_fractions() {
+    let expected = expect_test::expect![[r#"
+        Some(
+            Ok(
+                -2.0,
+            ),
+        )
+    "#]];
+    expected.assert_debug_eq(&eval_bare_string("0.5 - -0.5 * -5"));
+}
+
+#[test]
+fn eval_bare_abs() {
+    let eval = |source_text| eval_bare_string(source_text).unwrap().unwrap();
+    assert_eq!(eval("|-3|"), 3.0);
+    assert_eq!(eval("|2 - 5| + |-1.5|"), 4.5);
+    assert_eq!(eval("-|-2| * ||1 - 3| - 4|"), -4.0);
+}
+
+#[cfg(test)]
+fn eval_program_string(source_text: &str, options: EvalOptions) -> (Vec<f64>, Vec<Diagnostic>) {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = parse_statements(&db, source_program);
+    let output = eval_program(&db, program, options)
+        .lines
+        .iter()
+        .map(|line| line.value.as_number().unwrap())
+        .collect();
+    (
+        output,
+        eval_program::accumulated::<Diagnostics>(&db, program, options),
+    )
+}
+
+/// Create a new database with the given source text and evaluate it.
+/// Returns the printed lines, each with the span of its `print` statement,
+/// interleaved with the rendered diagnostics.
+#[cfg(test)]
+fn eval_string(source_text: &str) -> String {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = parse_statements(&db, source_program);
+    let options = EvalOptions::default();
+    let run = eval_program(&db, program, options);
+    let diagnostics = eval_program::accumulated::<Diagnostics>(&db, program, options);
+    let mut output = String::new();
+    for event in interleave(&run.lines, &diagnostics) {
+        match event {
+            OutputEvent::Print(line) => {
+                output += &format!("{}..{}: {}\n", line.span.start, line.span.end, line.text)
+            }
+            OutputEvent::Diagnostic(diagnostic) => {
+                output += &crate::render::render_diagnostic(&db, source_text, diagnostic)
+            }
+        }
+    }
+    output
+}
+
+#[test]
+fn eval_example() {
+    let expected = expect_test::expect![[r#"
+        104..131: 12
+        144..165: 3.14
+        178..191: 22
+    "#]];
+    expected.assert_eq(&eval_string(
+        "
+            fn area_rectangle(w, h) = w * h;
+            fn area_circle(r) = 3.14 * r * r;
+            print area_rectangle(3, 4);
+            print area_circle(1);
+            print 11 * 2;
+        ",
+    ));
+}
+
+#[test]
+fn eval_nested_calls() {
+    let expected = expect_test::expect![[r#"
+        83..106: 2.5
+        119..145: 0.25
+        warning: division by zero: `x / n` is `1 / 0`
+          --> 28..33
+        158..178: inf
+    "#]];
+    expected.assert_eq(&eval_string(
+        "
+            fn avg(x, n) = x / n;
+            fn half(x) = avg(x, 2);
+            print half(avg(10, 2));
+            print half(half(half(2)));
+            print 1 + avg(1, 0);
+        ",
+    ));
+}
+
+#[test]
+fn eval_named_arguments() {
+    let expected = expect_test::expect![[r#"
+        47..71: 1.5
+        84..104: 1.5
+        117..141: 1.5
+        error: the function `div` has no parameter named `z`
+          --> 171..172
+        error: the argument `x` is given more than once
+          --> 204..205
+        error: a positional argument can't follow a named one
+          --> 237..238
+        error: the built-in function `sqrt` has no named parameters
+          --> 268..269
+    "#]];
+    expected.assert_eq(&eval_string(
+        "
+            fn div(x, y) = x / y;
+            print div(y = 2, x = 3);
+            print div(3, y = 2);
+            print div(x = 3, y = 2);
+            print div(3, z = 2);
+            print div(3, x = 2);
+            print div(x = 3, 2);
+            print sqrt(x = 4);
+        ",
+    ));
+}
+
+#[test]
+fn eval_named_arguments_in_source_order() {
+    let expected = expect_test::expect![[r#"
+        32..39: 1
+        45..52: 2
+        20..54: 1
+    "#]];
+    expected.assert_eq(&eval_string(
+        "fn f(x, y) = x - y; print f(y = print 1, x = print 2);",
+    ));
+}
+
+#[test]
+fn eval_output_interleaved_with_errors() {
+    let expected = expect_test::expect![[r#"
+        12..20: 1
+        error: the variable `x` is not declared
+          --> 27..28
+        30..38: 2
+        warning: division by zero: `1 / 0` is `1 / 0`
+          --> 45..50
+        39..51: inf
+        error: the function `g` is not declared
+          --> 58..62
+    "#]];
+    expected.assert_eq(&eval_string(
+        "fn f() = 1;\nprint 1; print x; print 2; print 1 / 0; print g(1);",
+    ));
+}
+
+#[test]
+fn eval_error_builtin() {
+    let expected = expect_test::expect![[r#"
+        57..65: 1
+        error: negative
+          --> 14..31
+        note: in a call to `check`
+          --> 14..22 in `f`
+        note: in a call to `f`
+          --> 72..76
+        78..86: 3
+    "#]];
+    expected.assert_eq(&eval_string(
+        "fn check(x) = error(\"negative\");\nfn f(x) = 1 + check(x);\nprint 1;\nprint f(2);\nprint 3;",
+    ));
+}
+
+#[test]
+fn eval_bools() {
+    let expected = expect_test::expect![[r#"
+        0..11: true
+        12..24: false
+        error: cannot apply `+` to bool and number
+          --> 31..39
+        error: expected a number, found a bool
+          --> 47..52
+    "#]];
+    expected.assert_eq(&eval_string(
+        "print true; print false; print true + 1; print -true;",
+    ));
+}
+
+#[test]
+fn eval_runtime_errors() {
+    let expected = expect_test::expect![[r#"
+        (
+            [
+                1.0,
+                3.0,
+            ],
+            [
+                Diagnostic {
+                    start: 31,
+                    end: 35,
+                    message: "the function `g` is not declared",
+                    severity: Error,
+                    related: [],
+                },
+            ],
+        )
+    "#]];
+    expected.assert_debug_eq(&eval_program_string(
+        "fn f(x) = x; print f(1); print g(1); print 3;",
+        EvalOptions::default(),
+    ));
+}
+
+#[cfg(test)]
+const MATH_EVENTS_PROGRAM: &str = "
+    fn f(x) = 1 / x;
+    print f(0);
+    print 0 * f(0) + 1;
+    print sqrt(-1);
+    print 2;
+";
+
+#[test]
+fn eval_math_warnings() {
+    let expected = expect_test::expect![[r#"
+        (
+            [
+                inf,
+                NaN,
+                NaN,
+                2.0,
+            ],
+            [
+                Diagnostic {
+                    start: 15,
+                    end: 20,
+                    message: "division by zero: `1 / x` is `1 / 0`",
+                    severity: Warning,
+                    related: [],
+                },
+                Diagnostic {
+                    start: 15,
+                    end: 20,
+                    message: "division by zero: `1 / x` is `1 / 0`",
+                    severity: Warning,
+                    related: [],
+                },
+                Diagnostic {
+                    start: 48,
+                    end: 56,
+                    message: "`0 * f(0)` is not a number: `0 * inf`",
+                    severity: Warning,
+                    related: [],
+                },
+                Diagnostic {
+                    start: 72,
+                    end: 80,
+                    message: "`sqrt(-1)` is not a number",
+                    severity: Warning,
+                    related: [],
+                },
+            ],
+        )
+    "#]];
+    expected.assert_debug_eq(&eval_program_string(
+        MATH_EVENTS_PROGRAM,
+        EvalOptions::default(),
+    ));
+}
+
+#[test]
+fn eval_division_by_zero_parameter() {
+    let expected = expect_test::expect![[r#"
+        (
+            [
+                inf,
+                NaN,
+            ],
+            [
+                Diagnostic {
+                    start: 10,
+                    end: 15,
+                    message: "division by zero: `1 / n` is `1 / 0`",
+                    severity: Warning,
+                    related: [],
+                },
+                Diagnostic {
+                    start: 27,
+                    end: 32,
+                    message: "indeterminate 0/0: `n / n`",
+                    severity: Warning,
+                    related: [],
+                },
+            ],
+        )
+    "#]];
+    expected.assert_debug_eq(&eval_program_string(
+        "fn f(n) = 1 / n; fn g(n) = n / n; print f(0); print g(0);",
+        EvalOptions::default(),
+    ));
+}
+
+#[test]
+fn eval_strict_math() {
+    let expected = expect_test::expect![[r#"
+        (
+            [],
+            [
+                Diagnostic {
+                    start: 15,
+                    end: 20,
+                    message: "division by zero: `1 / x` is `1 / 0`",
+                    severity: Error,
+                    related: [],
+                },
+            ],
+        )
+    "#]];
+    expected.assert_debug_eq(&eval_program_string(
+        MATH_EVENTS_PROGRAM,
+        EvalOptions {
+            strict_math: true,
+            ..EvalOptions::default()
+        },
+    ));
+}
+
+#[test]
+fn eval_strict_math_after_error() {
+    // Only math events stop the program; other errors stop the statement.
+    let (output, diagnostics) = eval_program_string(
+        "fn f(x) = error(\"boom\"); print f(1); print 2;",
+        EvalOptions {
+            strict_math: true,
+            ..EvalOptions::default()
+        },
+    );
+    assert_eq!(output, [2.0]);
+    assert_eq!(diagnostics.len(), 1);
+}
+
+#[test]
+fn eval_max_depth() {
+    let expected = expect_test::expect![[r#"
+        (
+            [
+                3.0,
+            ],
+            [
+                Diagnostic {
+                    start: 19,
+                    end: 23,
+                    message: "maximum recursion depth exceeded while calling `f`",
+                    severity: Error,
+                    related: [
+                        (
+                            Span(f, 10..14),
+                            "in a call to `f`",
+                        ),
+                        (
+                            Span(f, 10..14),
+                            "in a call to `f`",
+                        ),
+                        (
+                            Span(134..138),
+                            "in a call to `f`",
+                        ),
+                    ],
+                },
+            ],
+        )
+    "#]];
+    // `f` recurses forever; `a` is three calls deep, which is allowed.
+    let source_text = "
+        fn f(x) = f(x) + 1;
+        fn a() = b() + 1;
+        fn b() = c() + 1;
+        fn c() = 1;
+        print a();
+        print f(1);
+        ";
+    let db = crate::db::Database::default();
+    let source_program = crate::ir::SourceProgram::new(&db, source_text.to_string());
+    let program = crate::parser::parse_statements(&db, source_program);
+    let options = EvalOptions {
+        max_depth: 3,
+        ..EvalOptions::default()
+    };
+    let output: Vec<_> = eval_program(&db, program, options)
+        .lines
+        .iter()
+        .map(|line| line.value.as_number().unwrap())
+        .collect();
+    let diagnostics = eval_program::accumulated::<Diagnostics>(&db, program, options);
+    expected.assert_debug_eq(&(
+        output,
+        salsa::DebugWithDb::debug(&diagnostics, &db as &dyn crate::Db),
+    ));
+}
+
+#[test]
+fn format_numbers() {
+    let formatted: Vec<_> = [
+        3.0,
+        -3.0,
+        0.1 + 0.2,
+        2.5,
+        -0.0,
+        1e21,
+        1e-7,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NAN,
+    ]
+    .into_iter()
+    .map(format_number)
+    .collect();
+    let expected = expect_test::expect![[r#"
+        [
+            "3",
+            "-3",
+            "0.30000000000000004",
+            "2.5",
+            "0",
+            "1000000000000000000000",
+            "0.0000001",
+            "inf",
+            "-inf",
+            "nan",
+        ]
+    "#]];
+    expected.assert_debug_eq(&formatted);
+}
+
+#[test]
+fn eval_builtins() {
+    let expected = expect_test::expect![[r#"
+        (
+            [
+                8.0,
+                0.0,
+                1.0,
+                1.0,
+            ],
+            [
+                Diagnostic {
+                    start: 78,
+                    end: 84,
+                    message: "`log(0)` is undefined: the logarithm is only defined for positive numbers",
+                    severity: Error,
+                    related: [],
+                },
+            ],
+        )
+    "#]];
+    expected.assert_debug_eq(&eval_program_string(
+        "print pow(2, 3); print log(1); print sin(0) + cos(0); print max(1, -1); print log(0);",
+        EvalOptions::default(),
+    ));
+}
+
+#[test]
+fn eval_variadic_sum() {
+    let (output, diagnostics) = eval_program_string(
+        "fn f(x) = x; print sum(); print sum(1, 2, 3); print sum(f(4)); print f(1, 2);",
+        EvalOptions::default(),
+    );
+    assert_eq!(output, [0.0, 6.0, 4.0]);
+    // Only builtins can be variadic.
+    let messages: Vec<_> = diagnostics
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect();
+    assert_eq!(
+        messages,
+        ["the function `f` expects 1 argument, but 2 were given"]
+    );
+}
+
+#[test]
+fn eval_lambda() {
+    let (output, diagnostics) = eval_program_string(
+        r"
+            fn f(x) = (\(a, b) -> a * b + 1)(x, 2);
+            print f(3);
+            print (\(x) -> (\(y) -> y - 1)(x * 10))(f(1));
+            print (\(x) -> x)(1, 2);
+        ",
+        EvalOptions::default(),
+    );
+    assert_eq!(output, [7.0, 29.0]);
+    let messages: Vec<_> = diagnostics
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect();
+    assert_eq!(
+        messages,
+        ["the lambda expects 1 argument, but 2 were given"]
+    );
+}
+
+#[test]
+fn eval_nested_print() {
+    let output = eval_string(
+        "fn f(x) = print x * 2;\nprint (print 1) + 1;\nprint f(f(1)) + 1;\nprint print print 3;",
+    );
+    // The operand of a `print` is printed first.
+    let expected = expect_test::expect![[r#"
+        29..38: 1
+        23..43: 2
+        10..21: 2
+        10..21: 4
+        44..62: 5
+        75..82: 3
+        69..82: 3
+        63..83: 3
+    "#]];
+    expected.assert_eq(&output);
+
+    // A memoized call which printed still prints.
+    let memoized = EvalOptions {
+        memoize: true,
+        ..EvalOptions::default()
+    };
+    let (output, _) = eval_program_string("fn f(x) = print x; print f(1) + f(1);", memoized);
+    assert_eq!(output, [1.0, 1.0, 2.0]);
+}
+
+/// A chain of functions where each calls the previous one twice, so that
+/// evaluating the last one without a cache takes `2^depth` calls.
+#[cfg(test)]
+fn doubling_program(depth: usize) -> String {
+    let mut source_text = "fn f0(x) = x + 1;".to_string();
+    for i in 1..=depth {
+        source_text += &format!("fn f{i}(x) = f{}(x) + f{}(x);", i - 1, i - 1);
+    }
+    source_text + &format!("print f{depth}(1); print f{depth}(2); print f0(5);")
+}
+
+#[test]
+fn eval_memoized() {
+    let (output, diagnostics) = eval_program_string(
+        &doubling_program(25),
+        EvalOptions {
+            memoize: true,
+            ..EvalOptions::default()
+        },
+    );
+    assert_eq!(output, [2.0 * 2f64.powi(25), 3.0 * 2f64.powi(25), 6.0]);
+    assert!(diagnostics.is_empty());
+}
+
+#[test]
+fn eval_stats() {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, doubling_program(3));
+    let program = parse_statements(&db, source_program);
+    let stats = |memoize| {
+        eval_program(
+            &db,
+            program,
+            EvalOptions {
+                memoize,
+                ..EvalOptions::default()
+            },
+        )
+        .stats
+        .clone()
+    };
+    let expected = EvalStats {
+        statements: 3,
+        calls: 31,
+        max_depth: 4,
+        memo_hits: 0,
+        cse_hits: 0,
+    };
+    assert_eq!(stats(false), expected);
+    let expected = EvalStats {
+        calls: 9,
+        memo_hits: 6,
+        ..expected
+    };
+    assert_eq!(stats(true), expected);
+}
+
+#[test]
+fn eval_common_subexpressions() {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_text = "
+        fn f(x) = x * 2;
+        fn g(x) = f(x) + f(x);
+        print f(1) + f(1);
+        print g(3);
+        print f(1) + f(2);
+        print let x = 1 in f(x) + (let x = 2 in f(x));
+        fn p(x) = print x;
+        print p(1) + p(1);
+    ";
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = parse_statements(&db, source_program);
+    let run = |cse| {
+        let output = eval_program(
+            &db,
+            program,
+            EvalOptions {
+                cse,
+                ..EvalOptions::default()
+            },
+        );
+        let values: Vec<_> = output.lines.iter().map(|line| line.text.clone()).collect();
+        (values, output.stats.calls, output.stats.cse_hits)
+    };
+    let (values, calls, cse_hits) = run(true);
+    // `f(1) + f(1)` calls `f` once. A call which prints still prints every
+    // time.
+    assert_eq!(values, ["4", "12", "6", "6", "1", "1", "2"]);
+    assert_eq!((calls, cse_hits), (9, 2));
+    let (uncached, calls, _) = run(false);
+    assert_eq!(uncached, values);
+    assert_eq!(calls, 11);
+}
+
+#[test]
+fn eval_memoized_matches_uncached() {
+    let memoized = EvalOptions {
+        memoize: true,
+        ..EvalOptions::default()
+    };
+    let cse = EvalOptions {
+        cse: true,
+        ..EvalOptions::default()
+    };
+    // `0` and `-0` are different arguments.
+    let zeros = "fn f(x) = 1 / x; print f(0); print f(-0); \
+                 print let x = 0 in f(x) - (let x = -0 in f(x));";
+    for source_text in [doubling_program(5).as_str(), zeros] {
+        let expected = eval_program_string(source_text, EvalOptions::default()).0;
+        assert_eq!(eval_program_string(source_text, memoized).0, expected);
+        assert_eq!(eval_program_string(source_text, cse).0, expected);
+    }
+    assert_eq!(
+        eval_program_string(zeros, memoized).0,
+        [f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY]
+    );
+}
@@ -0,0 +1,81 @@
+//! Constant folding: computing the value of expressions which are the same
+//! on every run, without running the program.
+
+use crate::builtins::find_builtin;
+use crate::ir::{ExprArena, ExprId, ExpressionData, Program};
+use crate::type_check::{builtin_arguments, find_function};
+use crate::value::{dispatch_op, Operands, Value};
+
+/// The value of the expression `id` of `arena` if it's built only from literals, operators and
+/// builtins. Calls of the program's functions aren't folded, and neither are
+/// expressions which would fail at runtime, e.g. `log(0)`, so that the
+/// evaluator still reports them.
+pub fn fold_constant(
+    db: &dyn crate::Db,
+    program: Program,
+    arena: &ExprArena,
+    id: ExprId,
+) -> Option<Value> {
+    let fold = |id| fold_constant(db, program, arena, id);
+    match &arena[id].data {
+        ExpressionData::Op(left, op, right) => {
+            match dispatch_op(*op, &fold(*left)?, &fold(*right)?) {
+                Ok(Operands::Numbers(left, right)) => Some(op.apply(left, right).into()),
+                Err(_) => None,
+            }
+        }
+        ExpressionData::Neg(operand) => Some((-fold(*operand)?.as_number()?).into()),
+        ExpressionData::Abs(operand) => Some(fold(*operand)?.as_number()?.abs().into()),
+        ExpressionData::Number(n) => Some(Value::Number(*n)),
+        ExpressionData::Bool(b) => Some(Value::Bool(*b)),
+        // Lambdas and `let`s aren't folded, like calls of the program's
+        // functions, and neither is `print`, which has to run.
+        ExpressionData::Variable(_)
+        | ExpressionData::Lambda(..)
+        | ExpressionData::Let(..)
+        | ExpressionData::Print(_)
+        | ExpressionData::Error(_) => None,
+        ExpressionData::Call(f, args) => {
+            // User definitions take precedence over builtins of the same name.
+            if find_function(db, program, *f).is_some() {
+                return None;
+            }
+            let builtin = find_builtin(f.text(db))?;
+            if !builtin.accepts(args.len()) {
+                return None;
+            }
+            let args = builtin_arguments(db, *f, arena, args).ok()?;
+            let args = args
+                .iter()
+                .map(|&arg| fold(arg)?.as_number())
+                .collect::<Option<Vec<_>>>()?;
+            (builtin.eval)(&args).ok().map(Value::from)
+        }
+    }
+}
+
+#[test]
+fn fold_constants() {
+    use crate::{
+        db::Database, ir::SourceProgram, parser::parse_expression, parser::parse_statements,
+    };
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, "fn sin(x) = x;".to_string());
+    let program = parse_statements(&db, source_program);
+    let fold = |source_text| {
+        let (arena, expression) = parse_expression(&db, source_text).unwrap();
+        fold_constant(&db, program, &arena, expression).map(|value| value.to_string())
+    };
+    assert_eq!(fold("1 + 2 * 3").as_deref(), Some("7"));
+    assert_eq!(fold("-|1 - 3| / 4").as_deref(), Some("-0.5"));
+    assert_eq!(fold("max(pow(2, 3), sqrt(16))").as_deref(), Some("8"));
+    assert_eq!(fold("true").as_deref(), Some("true"));
+    assert_eq!(fold("1 / 0").as_deref(), Some("inf"));
+    assert_eq!(fold("x + 1"), None);
+    assert_eq!(fold("sin(0)"), None);
+    assert_eq!(fold("log(0)"), None);
+    assert_eq!(fold("max(1)"), None);
+    assert_eq!(fold("true + 1"), None);
+    assert_eq!(fold("1 + error(\"no\")"), None);
+}
@@ -0,0 +1,195 @@
+//! Reprinting a file in a canonical layout (`banana fmt`).
+
+use std::fmt;
+
+use crate::ir::{display_expr, program_eq_ignoring_spans, SourceProgram, StatementData};
+use crate::lexer::{tokenize_with_comments, TokenKind};
+use crate::parser::{parse_item, parse_item_statements, parse_statements, split_into_items};
+use crate::raw::escape;
+
+/// Why a file can't be formatted.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum FormatError {
+    /// The file doesn't parse.
+    Parse,
+    /// The formatted text parses to a different program, which is a bug
+    /// of the formatter.
+    Changed,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Parse => write!(f, "the file doesn't parse"),
+            FormatError::Changed => {
+                write!(f, "formatting would change the meaning of the file")
+            }
+        }
+    }
+}
+
+/// The text of `source` in a canonical layout, or `None` if it can't be
+/// formatted. See `format_checked`.
+pub fn format_source(db: &dyn crate::Db, source: SourceProgram) -> Option<String> {
+    format_checked(db, source).ok()
+}
+
+/// The text of `source` in a canonical layout: one item per line, with
+/// expressions printed by `display_expr`, and each comment on a line of its
+/// own before the item it was in or after. Formatting the result again
+/// gives the same text.
+///
+/// As a safety check, the result is parsed again and must give the same
+/// program as `source`, apart from the spans.
+pub fn format_checked(db: &dyn crate::Db, source: SourceProgram) -> Result<String, FormatError> {
+    let text = layout(db, source).ok_or(FormatError::Parse)?;
+    let formatted = SourceProgram::new(db, text.clone());
+    if !program_eq_ignoring_spans(
+        db,
+        parse_statements(db, source),
+        parse_statements(db, formatted),
+    ) {
+        return Err(FormatError::Changed);
+    }
+    Ok(text)
+}
+
+fn layout(db: &dyn crate::Db, source: SourceProgram) -> Option<String> {
+    let mut out = String::new();
+    let tokens = tokenize_with_comments(source.text(db));
+    let mut comments = tokens
+        .iter()
+        .filter(|token| token.kind == TokenKind::Comment)
+        .peekable();
+    for item in split_into_items(db, source) {
+        let end = item.start + item.text.text(db).len();
+        while let Some(comment) = comments.next_if(|comment| comment.start < end) {
+            out += &format!("{}\n", comment.text.trim_end());
+        }
+        if let Some(function) = parse_item(db, item.text) {
+            let data = function.data(db);
+            if !data.allow.is_empty() {
+                out += &format!("#[allow({})]\n", data.allow.join(", "));
+            }
+            let args: Vec<_> = data.args.iter().map(|arg| arg.text(db).as_str()).collect();
+            out += &format!(
+                "fn {}({}) = {};\n",
+                function.name(db).text(db),
+                args.join(", "),
+                display_expr(db, data.arena(db), data.body)
+            );
+            continue;
+        }
+        let (statements, includes) = parse_item_statements(db, item.text).as_ref()?;
+        for statement in statements {
+            match &statement.data {
+                StatementData::Print(expression) => {
+                    out += &format!(
+                        "print {};\n",
+                        display_expr(db, &statement.arena, *expression)
+                    )
+                }
+            }
+        }
+        for include in includes {
+            out += &format!("include \"{}\";\n", escape(&include.path));
+        }
+    }
+    for comment in comments {
+        out += &format!("{}\n", comment.text.trim_end());
+    }
+    Some(out)
+}
+
+/// Formats `source_text` twice, checking that the second time doesn't
+/// change it.
+#[cfg(test)]
+fn format_string(source_text: &str) -> String {
+    let db = crate::db::Database::default();
+    let source = SourceProgram::new(&db, source_text.to_string());
+    let formatted = format_checked(&db, source).unwrap();
+    let again = SourceProgram::new(&db, formatted.clone());
+    assert_eq!(format_checked(&db, again).as_ref(), Ok(&formatted));
+    formatted
+}
+
+#[test]
+fn format_example() {
+    let expected = expect_test::expect![[r#"
+        #[allow(shadow_builtin)]
+        fn sqrt(x) = x * (1 + 2);
+        // twice
+        print sqrt(2);
+        include "lib.bn";
+    "#]];
+    expected.assert_eq(&format_string(
+        "#[allow(shadow_builtin)]  fn  sqrt(x)=x*(1+2) ;\n// twice\nprint  sqrt( 2 ,) ;include \"lib.bn\";",
+    ));
+
+    let db = crate::db::Database::default();
+    let broken = SourceProgram::new(&db, "print 1 +;".to_string());
+    assert_eq!(format_source(&db, broken), None);
+    assert_eq!(format_checked(&db, broken), Err(FormatError::Parse));
+}
+
+#[test]
+fn format_parentheses() {
+    let expected = expect_test::expect![[r#"
+        print (1 + 2) * 3;
+        print 1 + 2 * 3;
+        print 1 - 2 - 3;
+        print 1 - (2 - 3);
+        print 8 / (4 / 2);
+        print -(1 + 2) * -x;
+        print |a - b| / 2;
+        print (let y = 1 in y + 1) * 2;
+        print f(g(1, 2), (\(a) -> a * a)(3));
+        print 1 + (print 2);
+    "#]];
+    expected.assert_eq(&format_string(
+        r"
+        print ((1 + 2)) * 3;
+        print 1 + (2 * 3);
+        print (1 - 2) - 3;
+        print 1 - (2 - 3);
+        print 8 / (4 / 2);
+        print (-(1 + 2)) * (-x);
+        print (|(a - b)|) / (2);
+        print (let y = 1 in (y + 1)) * 2;
+        print f(g(1,2),(\(a)->a*a)(3));
+        print 1 + (print 2);
+        ",
+    ));
+}
+
+#[test]
+fn format_comments() {
+    let expected = expect_test::expect![[r#"
+        // The area of a rectangle.
+        /* with
+           two lines */
+        fn area(w, h) = w * h;
+        // inside
+        /* the body */
+        fn twice(x) = x * 2;
+        print area(1, 2);
+        // at the end
+    "#]];
+    expected.assert_eq(&format_string(
+        "// The area of a rectangle.   \n/* with\n   two lines */\nfn area(w,h)=w*h;\n\n\
+         fn twice(x) = // inside\n  x /* the body */ * 2;\nprint area(1, 2); // at the end\n",
+    ));
+}
+
+#[test]
+fn format_named_arguments_and_bools() {
+    let expected = expect_test::expect![[r#"
+        fn div(x, y) = x / y;
+        print div(y = 2, x = 1);
+        print true;
+        print error("no \"quotes\"");
+    "#]];
+    expected.assert_eq(&format_string(
+        "fn div(x,y)=x/y; print div(y=2,x=1); print (true); print error(\"no \\\"quotes\\\"\");",
+    ));
+}
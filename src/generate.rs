@@ -0,0 +1,38 @@
+//! Generated programs of any size, for benchmarks.
+
+use std::fmt::Write;
+
+/// A program of `functions` function definitions followed by `statements`
+/// print statements calling them. The functions all differ, so each is
+/// parsed and checked on its own.
+pub fn generate_program(functions: usize, statements: usize) -> String {
+    let mut source_text = String::new();
+    for i in 0..functions {
+        writeln!(
+            source_text,
+            "fn f{i}(x, y) = x * {i} + y / (x + {}) - |y - {i}|;",
+            i + 1
+        )
+        .unwrap();
+    }
+    for j in 0..statements {
+        if functions == 0 {
+            writeln!(source_text, "print {j};").unwrap();
+        } else {
+            writeln!(source_text, "print f{}({j}, 2);", j % functions).unwrap();
+        }
+    }
+    source_text
+}
+
+#[test]
+fn generated_program_parses() {
+    use crate::{compile::compile_diagnostics, db::Database, ir::SourceProgram};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, generate_program(30, 50));
+    assert!(compile_diagnostics(&db, source_program).is_empty());
+    let program = crate::parser::parse_statements(&db, source_program);
+    assert_eq!(program.functions(&db).len(), 30);
+    assert_eq!(program.statements(&db).len(), 50);
+}
@@ -1,7 +1,18 @@
 #![allow(clippy::needless_borrow)]
 
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    ops::{Index, IndexMut, Range},
+    path::PathBuf,
+};
+
 use derive_new::new;
 use ordered_float::OrderedFloat;
+use salsa::DebugWithDb;
+
+use crate::raw::escape;
 
 // ANCHOR: input
 #[salsa::input]
@@ -11,6 +22,78 @@ pub struct SourceProgram {
 }
 // ANCHOR_END: input
 
+impl SourceProgram {
+    /// Replaces `range` of the text with `replacement`, as
+    /// `apply_edits(db, &[(range, replacement)])`.
+    pub fn apply_edit(
+        self,
+        db: &mut dyn crate::Db,
+        range: Range<usize>,
+        replacement: &str,
+    ) -> Result<(), String> {
+        self.apply_edits(db, &[(range, replacement)])
+    }
+
+    /// Replaces ranges of the text, given as byte offsets into the current
+    /// text, in one change. The ranges must be in bounds, on character
+    /// boundaries and not overlap; otherwise the text is left as it is. The
+    /// text isn't set at all if the edits leave it the same, so no query
+    /// has to be checked again.
+    pub fn apply_edits(
+        self,
+        db: &mut dyn crate::Db,
+        edits: &[(Range<usize>, &str)],
+    ) -> Result<(), String> {
+        let text = self.text(db);
+        let mut edits: Vec<_> = edits.iter().collect();
+        edits.sort_by_key(|(range, _)| range.start);
+        for (range, _) in &edits {
+            if range.start > range.end || range.end > text.len() {
+                return Err(format!(
+                    "edit at {range:?} is out of bounds of the text of length {}",
+                    text.len()
+                ));
+            }
+            if !text.is_char_boundary(range.start) || !text.is_char_boundary(range.end) {
+                return Err(format!("edit at {range:?} splits a character"));
+            }
+        }
+        for pair in edits.windows(2) {
+            let ((first, _), (second, _)) = (pair[0], pair[1]);
+            if first.end > second.start {
+                return Err(format!("edits at {first:?} and {second:?} overlap"));
+            }
+        }
+        // Back to front, so the ranges still to apply stay valid.
+        let mut new_text = text.clone();
+        for (range, replacement) in edits.into_iter().rev() {
+            new_text.replace_range(range.clone(), replacement);
+        }
+        if new_text != *text {
+            self.set_text(db).to(new_text);
+        }
+        Ok(())
+    }
+}
+
+/// The files loaded from disk, and what the `include`s of each file resolve
+/// to: the program of the included file, or why it couldn't be read. A
+/// database has one, changed only by `Database::load_source` and the other
+/// ways of loading a file, so that queries resolve includes without reading
+/// files themselves.
+#[salsa::input]
+pub struct Files {
+    /// The program of each file loaded, by canonical path. Loading the same
+    /// file again gives the same input, which is the id the spans of its
+    /// functions and statements refer to, so that each is resolved against
+    /// the right file's text.
+    #[return_ref]
+    pub by_path: HashMap<PathBuf, SourceProgram>,
+    /// The file each `include` path of a program resolves to.
+    #[return_ref]
+    pub includes: HashMap<(SourceProgram, String), Result<SourceProgram, String>>,
+}
+
 // ANCHOR: interned_ids
 #[salsa::interned]
 pub struct VariableId {
@@ -38,92 +121,487 @@ impl DefId {
 #[derive(Eq, PartialEq, Clone, Hash, Debug)]
 pub enum DefIdData {
     Unknown,
-    Function(FunctionId),
+    /// A function, by the file it's defined in and its name.
+    Function(SourceProgram, FunctionId),
+    /// The top-level statements of a file, whose spans are relative to the
+    /// start of that file.
+    File(SourceProgram),
 }
 // ANCHOR_END: interned_ids
 
+/// The expressions of a function. They're interned so that salsa compares
+/// the ids of arenas, rather than the arenas, when checking whether a
+/// function changed.
+#[salsa::interned]
+pub struct InternedArena {
+    #[return_ref]
+    pub arena: ExprArena,
+}
+
+/// The text of a top-level item of `file`, as split by
+/// `parser::split_into_items`. Items with the same text are parsed once.
+#[salsa::interned]
+pub struct ItemText {
+    pub file: SourceProgram,
+    #[return_ref]
+    pub text: String,
+}
+
 // ANCHOR: program
 #[salsa::tracked]
 pub struct Program {
     #[return_ref]
     pub functions: Vec<Function>,
+
+    /// The top-level statements other than function definitions, in source
+    /// order.
+    #[return_ref]
+    pub statements: Vec<Statement>,
 }
 // ANCHOR_END: program
 
 // ANCHOR: statements_and_expressions
-#[derive(Eq, PartialEq, Debug, Hash, new)]
+#[derive(Eq, PartialEq, Clone, Hash, new)]
 pub struct Statement {
     pub span: Span,
 
+    /// The expressions of the statement.
+    pub arena: ExprArena,
+
     pub data: StatementData,
 }
 
+impl Statement {
+    fn eq_ignoring_spans(&self, other: &Self) -> bool {
+        self.data == other.data && self.arena.eq_ignoring_spans(&other.arena)
+    }
+}
+
+impl Statement {
+    /// Shows the expressions inline, as a tree, with spans and names
+    /// resolved if there's a database.
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, db: Option<&dyn crate::Db>) -> fmt::Result {
+        struct Data<'a>(&'a Statement, Option<&'a dyn crate::Db>);
+
+        impl fmt::Debug for Data<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let Data(statement, db) = *self;
+                match statement.data {
+                    StatementData::Print(expression) => f
+                        .debug_tuple("Print")
+                        .field(&statement.arena.debug_with(db, expression))
+                        .finish(),
+                }
+            }
+        }
+
+        f.debug_struct("Statement")
+            .field("span", &DebugSpan(self.span, db))
+            .field("data", &Data(self, db))
+            .finish()
+    }
+}
+
+impl fmt::Debug for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, None)
+    }
+}
+
+impl<'db> DebugWithDb<dyn crate::Db + 'db> for Statement {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        db: &(dyn crate::Db + 'db),
+        _include_all_fields: bool,
+    ) -> fmt::Result {
+        self.fmt_with(f, Some(db))
+    }
+}
+
 impl Visit for Statement {
     fn traverse<V: Visitor>(&mut self, db: &dyn crate::Db, v: &mut V) {
         v.visit_span(&mut self.span);
-        self.data.traverse(db, v);
+        self.arena.traverse(db, v);
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Hash)]
+/// A top-level statement other than a function definition or include, which
+/// the parser turns into `Function`s and loaded files.
+#[derive(Eq, PartialEq, Clone, Debug, Hash)]
 pub enum StatementData {
-    /// Defines `fn <name>(<args>) = <body>`
-    Function {
-        name: FunctionId,
-        data: FunctionData,
-    },
     /// Defines `print <expr>`
-    Print(Expression),
+    Print(ExprId),
 }
 
-impl Visit for StatementData {
-    fn traverse<V: Visitor>(&mut self, db: &dyn crate::Db, v: &mut V) {
-        match self {
-            Self::Function { data, .. } => data.traverse(db, v),
-            Self::Print(x) => x.traverse(db, v),
+/// An expression in an `ExprArena`.
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+impl ExprId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// The expressions of a function or statement, side by side. Expressions
+/// refer to their operands by id, and operands are added before the
+/// expressions using them.
+#[derive(Eq, PartialEq, Clone, Hash, Debug, Default)]
+pub struct ExprArena {
+    expressions: Vec<Expression>,
+}
+
+impl ExprArena {
+    pub fn alloc(&mut self, expression: Expression) -> ExprId {
+        let id = u32::try_from(self.expressions.len()).expect("too many expressions");
+        self.expressions.push(expression);
+        ExprId(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.expressions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expressions.is_empty()
+    }
+
+    /// The expressions in the order they were added, operands first.
+    pub fn iter(&self) -> impl Iterator<Item = (ExprId, &Expression)> {
+        (0..).map(ExprId).zip(&self.expressions)
+    }
+
+    /// Formats the expression `id` as a tree, with its operands inline.
+    pub fn debug(&self, id: ExprId) -> DebugExpr<'_> {
+        self.debug_with(None, id)
+    }
+
+    /// Like `debug`, but with spans shown by the definition they're in and
+    /// names by their text, rather than by interned ids, if there's a
+    /// database to look them up in.
+    pub fn debug_with<'a>(&'a self, db: Option<&'a dyn crate::Db>, id: ExprId) -> DebugExpr<'a> {
+        DebugExpr {
+            arena: self,
+            id,
+            db,
+        }
+    }
+
+    /// Compares the structure and names of the expressions, but not their
+    /// spans. The parser adds the expressions of equal trees in the same
+    /// order, so they can be compared one by one.
+    pub fn eq_ignoring_spans(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .expressions
+                .iter()
+                .zip(&other.expressions)
+                .all(|(a, b)| a.data == b.data)
+    }
+
+    /// A hash of the expression `id` and its operands which ignores their
+    /// spans, so that an expression written twice hashes the same.
+    pub fn structural_hash(&self, id: ExprId) -> u64 {
+        fn hash_tree(arena: &ExprArena, id: ExprId, hasher: &mut DefaultHasher) {
+            let data = &arena[id].data;
+            data.shape().hash(hasher);
+            for operand in data.operands() {
+                hash_tree(arena, operand, hasher);
+            }
         }
+        let mut hasher = DefaultHasher::new();
+        hash_tree(self, id, &mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether the expressions `a` and `b` are the same tree, apart from
+    /// their spans.
+    pub fn same_tree(&self, a: ExprId, b: ExprId) -> bool {
+        let (a, b) = (&self[a].data, &self[b].data);
+        a.shape() == b.shape()
+            && a.operands()
+                .into_iter()
+                .zip(b.operands())
+                .all(|(a, b)| self.same_tree(a, b))
+    }
+
+    /// The variables the expression `id` refers to which aren't bound in
+    /// it by a `let`, in the order they first appear. A lambda's body only
+    /// sees its parameters, so its variables aren't included.
+    pub fn free_variables(&self, id: ExprId) -> Vec<VariableId> {
+        fn collect(
+            arena: &ExprArena,
+            id: ExprId,
+            bound: &[VariableId],
+            free: &mut Vec<VariableId>,
+        ) {
+            match &arena[id].data {
+                ExpressionData::Variable(v) => {
+                    if !bound.contains(v) && !free.contains(v) {
+                        free.push(*v);
+                    }
+                }
+                ExpressionData::Let(name, value, body) => {
+                    collect(arena, *value, bound, free);
+                    collect(arena, *body, &[bound, &[*name]].concat(), free);
+                }
+                ExpressionData::Lambda(_, _, args) => {
+                    for &arg in args {
+                        collect(arena, arg, bound, free);
+                    }
+                }
+                data => {
+                    for operand in data.operands() {
+                        collect(arena, operand, bound, free);
+                    }
+                }
+            }
+        }
+        let mut free = vec![];
+        collect(self, id, &[], &mut free);
+        free
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Hash, new)]
-pub struct Expression {
-    pub span: Span,
+impl Index<ExprId> for ExprArena {
+    type Output = Expression;
 
-    pub data: ExpressionData,
+    fn index(&self, id: ExprId) -> &Expression {
+        &self.expressions[id.index()]
+    }
 }
 
-impl Visit for Expression {
-    fn traverse<V: Visitor>(&mut self, db: &dyn crate::Db, v: &mut V) {
-        v.visit_expr(self);
-        v.visit_span(&mut self.span);
-        self.data.traverse(db, v);
+impl IndexMut<ExprId> for ExprArena {
+    fn index_mut(&mut self, id: ExprId) -> &mut Expression {
+        &mut self.expressions[id.index()]
+    }
+}
+
+impl Visit for ExprArena {
+    fn traverse<V: Visitor>(&mut self, _: &dyn crate::Db, v: &mut V) {
+        for (id, expression) in (0..).map(ExprId).zip(&mut self.expressions) {
+            v.visit_expr(id, expression);
+            v.visit_span(&mut expression.span);
+        }
+    }
+}
+
+/// See `ExprArena::debug`.
+pub struct DebugExpr<'a> {
+    arena: &'a ExprArena,
+    id: ExprId,
+    db: Option<&'a dyn crate::Db>,
+}
+
+impl fmt::Debug for DebugExpr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Data<'a>(&'a ExprArena, &'a ExpressionData, Option<&'a dyn crate::Db>);
+
+        impl fmt::Debug for Data<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let Data(arena, data, db) = *self;
+                let expr = |id| arena.debug_with(db, id);
+                let all = |ids: &[ExprId]| ids.iter().map(|&id| expr(id)).collect::<Vec<_>>();
+                let variable = |id| DebugName(Name::Variable(id), db);
+                let variables =
+                    |ids: &[VariableId]| ids.iter().map(|&id| variable(id)).collect::<Vec<_>>();
+                match data {
+                    ExpressionData::Op(left, op, right) => f
+                        .debug_tuple("Op")
+                        .field(&expr(*left))
+                        .field(op)
+                        .field(&expr(*right))
+                        .finish(),
+                    ExpressionData::Neg(operand) => {
+                        f.debug_tuple("Neg").field(&expr(*operand)).finish()
+                    }
+                    ExpressionData::Abs(operand) => {
+                        f.debug_tuple("Abs").field(&expr(*operand)).finish()
+                    }
+                    ExpressionData::Number(n) => f.debug_tuple("Number").field(n).finish(),
+                    ExpressionData::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+                    ExpressionData::Variable(v) => {
+                        f.debug_tuple("Variable").field(&variable(*v)).finish()
+                    }
+                    ExpressionData::Call(function, args) => {
+                        let args: Vec<_> = args
+                            .iter()
+                            .map(|arg| DebugCallArg(arg.name.map(variable), expr(arg.value)))
+                            .collect();
+                        f.debug_tuple("Call")
+                            .field(&DebugName(Name::Function(*function), db))
+                            .field(&args)
+                            .finish()
+                    }
+                    ExpressionData::Lambda(params, body, args) => f
+                        .debug_tuple("Lambda")
+                        .field(&variables(params))
+                        .field(&expr(*body))
+                        .field(&all(args))
+                        .finish(),
+                    ExpressionData::Let(name, value, body) => f
+                        .debug_tuple("Let")
+                        .field(&variable(*name))
+                        .field(&expr(*value))
+                        .field(&expr(*body))
+                        .finish(),
+                    ExpressionData::Print(operand) => {
+                        f.debug_tuple("Print").field(&expr(*operand)).finish()
+                    }
+                    ExpressionData::Error(message) => {
+                        f.debug_tuple("Error").field(message).finish()
+                    }
+                }
+            }
+        }
+
+        let expression = &self.arena[self.id];
+        f.debug_struct("Expression")
+            .field("span", &DebugSpan(expression.span, self.db))
+            .field("data", &Data(self.arena, &expression.data, self.db))
+            .finish()
+    }
+}
+
+/// A span, formatted with `DebugWithDb` if there's a database.
+struct DebugSpan<'a>(Span, Option<&'a dyn crate::Db>);
+
+impl fmt::Debug for DebugSpan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            Some(db) => DebugWithDb::fmt(&self.0, f, db, false),
+            None => fmt::Debug::fmt(&self.0, f),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Name {
+    Variable(VariableId),
+    Function(FunctionId),
+}
+
+/// An interned name, formatted as its text if there's a database.
+struct DebugName<'a>(Name, Option<&'a dyn crate::Db>);
+
+impl fmt::Debug for DebugName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.0, self.1) {
+            (Name::Variable(id), Some(db)) => fmt::Debug::fmt(id.text(db), f),
+            (Name::Function(id), Some(db)) => fmt::Debug::fmt(id.text(db), f),
+            (Name::Variable(id), None) => fmt::Debug::fmt(&id, f),
+            (Name::Function(id), None) => fmt::Debug::fmt(&id, f),
+        }
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Hash)]
+/// An argument of a call, formatted as its value, or `Named(name, value)`.
+struct DebugCallArg<'a>(Option<DebugName<'a>>, DebugExpr<'a>);
+
+impl fmt::Debug for DebugCallArg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(name) => f.debug_tuple("Named").field(name).field(&self.1).finish(),
+            None => self.1.fmt(f),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Hash, new)]
+pub struct Expression {
+    pub span: Span,
+
+    pub data: ExpressionData,
+}
+
+/// An expression, with its operands in the same `ExprArena`.
+#[derive(Eq, PartialEq, Clone, Debug, Hash)]
 pub enum ExpressionData {
-    Op(Box<Expression>, Op, Box<Expression>),
+    Op(ExprId, Op, ExprId),
+    /// Unary minus, `-<expr>`
+    Neg(ExprId),
+    /// Absolute value, `|<expr>|`
+    Abs(ExprId),
     Number(OrderedFloat<f64>),
+    /// `true` or `false`.
+    Bool(bool),
     Variable(VariableId),
-    Call(FunctionId, Vec<Expression>),
+    /// A call of a function of the program or a builtin. The arguments are
+    /// bound to the parameters by `type_check::bind_arguments`.
+    Call(FunctionId, Vec<CallArg>),
+    /// `(\(<params>) -> <body>)(<args>)`, a lambda called immediately. The
+    /// body only sees the lambda's parameters, not the variables around it.
+    Lambda(Vec<VariableId>, ExprId, Vec<ExprId>),
+    /// `let <name> = <value> in <body>`. The name is in scope in the body
+    /// only, where it shadows a variable of the same name; the value sees
+    /// the variables around the `let`, so it can't refer to the name itself.
+    Let(VariableId, ExprId, ExprId),
+    /// `print <expr>` inside an expression: prints the value of the operand
+    /// and evaluates to it. The operand is evaluated, and anything it
+    /// prints is printed, first; so `print (print 1) + 1;` prints `1`, then
+    /// `2`.
+    Print(ExprId),
+    /// `error("<message>")`, which stops evaluation with the message.
+    Error(String),
 }
 
-impl Visit for ExpressionData {
-    fn traverse<V: Visitor>(&mut self, db: &dyn crate::Db, v: &mut V) {
+impl ExpressionData {
+    /// The expressions this one refers to, in the order they appear in the
+    /// source.
+    pub fn operands(&self) -> Vec<ExprId> {
         match self {
-            Self::Op(l, _, r) => {
-                l.traverse(db, v);
-                r.traverse(db, v);
+            ExpressionData::Op(left, _, right) => vec![*left, *right],
+            ExpressionData::Neg(operand)
+            | ExpressionData::Abs(operand)
+            | ExpressionData::Print(operand) => vec![*operand],
+            ExpressionData::Number(_)
+            | ExpressionData::Bool(_)
+            | ExpressionData::Variable(_)
+            | ExpressionData::Error(_) => vec![],
+            ExpressionData::Call(_, args) => args.iter().map(|arg| arg.value).collect(),
+            ExpressionData::Lambda(_, body, args) => {
+                std::iter::once(*body).chain(args.iter().copied()).collect()
             }
-            Self::Number(_) => {}
-            Self::Variable(_) => {}
-            Self::Call(_, args) => {
-                args.traverse(db, v);
+            ExpressionData::Let(_, value, body) => vec![*value, *body],
+        }
+    }
+
+    /// The expression with its operands left out, to compare two trees
+    /// node by node.
+    fn shape(&self) -> ExpressionData {
+        let placeholder = ExprId(0);
+        match self {
+            ExpressionData::Op(_, op, _) => ExpressionData::Op(placeholder, *op, placeholder),
+            ExpressionData::Neg(_) => ExpressionData::Neg(placeholder),
+            ExpressionData::Abs(_) => ExpressionData::Abs(placeholder),
+            ExpressionData::Print(_) => ExpressionData::Print(placeholder),
+            ExpressionData::Call(f, args) => ExpressionData::Call(
+                *f,
+                args.iter()
+                    .map(|arg| CallArg {
+                        name: arg.name,
+                        value: placeholder,
+                    })
+                    .collect(),
+            ),
+            ExpressionData::Lambda(params, _, args) => {
+                ExpressionData::Lambda(params.clone(), placeholder, vec![placeholder; args.len()])
             }
+            ExpressionData::Let(name, _, _) => ExpressionData::Let(*name, placeholder, placeholder),
+            data => data.clone(),
         }
     }
 }
 
+/// An argument of a call: `<value>`, or `<name> = <value>` to pass it to
+/// the parameter `name`. Named arguments come after the positional ones.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
+pub struct CallArg {
+    pub name: Option<VariableId>,
+    pub value: ExprId,
+}
+
 #[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
 pub enum Op {
     Add,
@@ -131,6 +609,233 @@ pub enum Op {
     Multiply,
     Divide,
 }
+
+impl Op {
+    /// The arithmetic semantics of the operator, shared by every pass that
+    /// computes values. Follows IEEE 754: dividing by zero gives an infinity
+    /// (or NaN for `0 / 0`) rather than failing.
+    pub fn apply(self, lhs: f64, rhs: f64) -> f64 {
+        match self {
+            Op::Add => lhs + rhs,
+            Op::Subtract => lhs - rhs,
+            Op::Multiply => lhs * rhs,
+            Op::Divide => lhs / rhs,
+        }
+    }
+
+    /// Operators with higher precedence bind tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Subtract => 1,
+            Op::Multiply | Op::Divide => 2,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Op::Add => "+",
+            Op::Subtract => "-",
+            Op::Multiply => "*",
+            Op::Divide => "/",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+/// Renders the expression `id` as source text, adding only the parentheses
+/// needed to preserve its structure.
+pub fn display_expr(db: &dyn crate::Db, arena: &ExprArena, id: ExprId) -> String {
+    let display = |id| display_expr(db, arena, id);
+    match &arena[id].data {
+        ExpressionData::Op(left, op, right) => {
+            // Operators are left-associative, so an operand on the right of
+            // the same precedence needs parentheses: `a - (b - c)`.
+            let left = display_operand(db, arena, *left, |inner| {
+                inner.precedence() < op.precedence()
+            });
+            let right = display_operand(db, arena, *right, |inner| {
+                inner.precedence() <= op.precedence()
+            });
+            format!("{left} {op} {right}")
+        }
+        ExpressionData::Neg(operand) => {
+            format!("-{}", display_operand(db, arena, *operand, |_| true))
+        }
+        ExpressionData::Abs(operand) => format!("|{}|", display(*operand)),
+        ExpressionData::Number(n) => n.to_string(),
+        ExpressionData::Bool(b) => b.to_string(),
+        ExpressionData::Variable(v) => v.text(db).clone(),
+        ExpressionData::Call(f, args) => {
+            let args: Vec<_> = args
+                .iter()
+                .map(|arg| match arg.name {
+                    Some(name) => format!("{} = {}", name.text(db), display(arg.value)),
+                    None => display(arg.value),
+                })
+                .collect();
+            format!("{}({})", f.text(db), args.join(", "))
+        }
+        ExpressionData::Lambda(params, body, args) => {
+            let params: Vec<_> = params.iter().map(|param| param.text(db).as_str()).collect();
+            let args: Vec<_> = args.iter().map(|&arg| display(arg)).collect();
+            format!(
+                "(\\({}) -> {})({})",
+                params.join(", "),
+                display(*body),
+                args.join(", ")
+            )
+        }
+        ExpressionData::Let(name, value, body) => format!(
+            "let {} = {} in {}",
+            name.text(db),
+            display(*value),
+            display(*body)
+        ),
+        ExpressionData::Print(operand) => format!("print {}", display(*operand)),
+        ExpressionData::Error(message) => format!("error(\"{}\")", escape(message)),
+    }
+}
+
+/// Renders an operand, in parentheses if it's an operation for which
+/// `needs_parens` returns true.
+fn display_operand(
+    db: &dyn crate::Db,
+    arena: &ExprArena,
+    operand: ExprId,
+    needs_parens: impl Fn(Op) -> bool,
+) -> String {
+    match &arena[operand].data {
+        ExpressionData::Op(_, op, _) if needs_parens(*op) => {
+            format!("({})", display_expr(db, arena, operand))
+        }
+        // Its body, or operand, would take in the rest of the operation.
+        ExpressionData::Let(..) | ExpressionData::Print(_) => {
+            format!("({})", display_expr(db, arena, operand))
+        }
+        _ => display_expr(db, arena, operand),
+    }
+}
+
+/// The syntax tree of `program` as text (`--emit ast`): one node per line,
+/// indented two spaces under its parent, with names as their text and the
+/// offsets of each node in the file.
+pub fn fmt_ast(db: &dyn crate::Db, program: &Program) -> String {
+    let program = *program;
+    let mut out = String::new();
+    let span = |span: Span| {
+        let (start, end) = span.absolute(db, program);
+        format!("{start}..{end}")
+    };
+    for function in program.functions(db) {
+        let data = function.data(db);
+        let args: Vec<_> = data.args.iter().map(|arg| arg.text(db).as_str()).collect();
+        out += &format!(
+            "fn {}({}) {}\n",
+            function.name(db).text(db),
+            args.join(", "),
+            span(data.name_span)
+        );
+        fmt_ast_expr(db, &span, data.arena(db), data.body, 1, &mut out);
+    }
+    for statement in program.statements(db) {
+        match &statement.data {
+            StatementData::Print(expression) => {
+                out += &format!("print {}\n", span(statement.span));
+                fmt_ast_expr(db, &span, &statement.arena, *expression, 1, &mut out);
+            }
+        }
+    }
+    out
+}
+
+fn fmt_ast_expr(
+    db: &dyn crate::Db,
+    span: &dyn Fn(Span) -> String,
+    arena: &ExprArena,
+    id: ExprId,
+    depth: usize,
+    out: &mut String,
+) {
+    let expression = &arena[id];
+    let mut line = |label: String| {
+        *out += &format!(
+            "{:indent$}{label} {}\n",
+            "",
+            span(expression.span),
+            indent = 2 * depth
+        );
+    };
+    let children: Vec<ExprId> = match &expression.data {
+        ExpressionData::Op(left, op, right) => {
+            line(format!("Op {op}"));
+            vec![*left, *right]
+        }
+        ExpressionData::Neg(operand) => {
+            line("Neg".to_string());
+            vec![*operand]
+        }
+        ExpressionData::Abs(operand) => {
+            line("Abs".to_string());
+            vec![*operand]
+        }
+        ExpressionData::Number(n) => {
+            line(format!("Number {n}"));
+            vec![]
+        }
+        ExpressionData::Bool(b) => {
+            line(format!("Bool {b}"));
+            vec![]
+        }
+        ExpressionData::Variable(v) => {
+            line(format!("Variable {}", v.text(db)));
+            vec![]
+        }
+        ExpressionData::Call(f, args) => {
+            line(format!("Call {}", f.text(db)));
+            for arg in args {
+                match arg.name {
+                    // The name has no node of its own: it's shown with the
+                    // span of the value.
+                    Some(name) => {
+                        let value = &arena[arg.value];
+                        *out += &format!(
+                            "{:indent$}Named {} {}\n",
+                            "",
+                            name.text(db),
+                            span(value.span),
+                            indent = 2 * (depth + 1)
+                        );
+                        fmt_ast_expr(db, span, arena, arg.value, depth + 2, out);
+                    }
+                    None => fmt_ast_expr(db, span, arena, arg.value, depth + 1, out),
+                }
+            }
+            vec![]
+        }
+        ExpressionData::Lambda(params, body, args) => {
+            let params: Vec<_> = params.iter().map(|param| param.text(db).as_str()).collect();
+            line(format!("Lambda ({})", params.join(", ")));
+            std::iter::once(*body).chain(args.iter().copied()).collect()
+        }
+        ExpressionData::Let(name, value, body) => {
+            line(format!("Let {}", name.text(db)));
+            vec![*value, *body]
+        }
+        ExpressionData::Print(operand) => {
+            line("Print".to_string());
+            vec![*operand]
+        }
+        ExpressionData::Error(message) => {
+            line(format!("Error {message:?}"));
+            vec![]
+        }
+    };
+    for child in children {
+        fmt_ast_expr(db, span, arena, child, depth + 1, out);
+    }
+}
 // ANCHOR_END: statements_and_expressions
 
 // ANCHOR: functions
@@ -141,22 +846,99 @@ pub struct Function {
 
     #[return_ref]
     pub data: FunctionData,
+
+    /// Offset of the definition in the source text. Spans inside the function
+    /// are relative to it.
+    pub start: usize,
 }
 
-#[derive(Eq, PartialEq, Hash, Debug, new)]
+#[derive(Eq, PartialEq, Clone, Hash, Debug, new)]
 pub struct FunctionData {
     pub name_span: Span,
 
     pub args: Vec<VariableId>,
 
-    pub body: Expression,
+    /// The span of each of `args`.
+    pub arg_spans: Vec<Span>,
+
+    /// The expressions of the body.
+    pub exprs: InternedArena,
+
+    pub body: ExprId,
+
+    /// Lints suppressed with `#[allow(...)]`.
+    pub allow: Vec<String>,
 }
 // ANCHOR_END: functions
 
+impl FunctionData {
+    pub fn allows(&self, lint: &str) -> bool {
+        self.allow.iter().any(|allowed| allowed == lint)
+    }
+
+    pub fn arena<'db>(&self, db: &'db dyn crate::Db) -> &'db ExprArena {
+        self.exprs.arena(db)
+    }
+
+    fn eq_ignoring_spans(&self, db: &dyn crate::Db, other: &Self) -> bool {
+        self.args == other.args
+            && self.allow == other.allow
+            && self.body == other.body
+            && self.arena(db).eq_ignoring_spans(other.arena(db))
+    }
+}
+
+/// Shows the body as a tree, with spans and names resolved as by
+/// `ExprArena::debug_with`.
+impl<'db> DebugWithDb<dyn crate::Db + 'db> for FunctionData {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        db: &(dyn crate::Db + 'db),
+        _include_all_fields: bool,
+    ) -> fmt::Result {
+        let args: Vec<_> = self
+            .args
+            .iter()
+            .map(|&arg| DebugName(Name::Variable(arg), Some(db)))
+            .collect();
+        f.debug_struct("FunctionData")
+            .field("name_span", &DebugSpan(self.name_span, Some(db)))
+            .field("args", &args)
+            .field("body", &self.arena(db).debug_with(Some(db), self.body))
+            .field("allow", &self.allow)
+            .finish()
+    }
+}
+
+/// Whether the programs define the same functions and statements, in the
+/// same order. Unlike comparing the syntax trees with `==`, spans are
+/// ignored, so differently formatted sources compare equal.
+pub fn program_eq_ignoring_spans(db: &dyn crate::Db, a: Program, b: Program) -> bool {
+    let (functions_a, functions_b) = (a.functions(db), b.functions(db));
+    let (statements_a, statements_b) = (a.statements(db), b.statements(db));
+    functions_a.len() == functions_b.len()
+        && functions_a
+            .iter()
+            .zip(functions_b)
+            .all(|(f, g)| f.name(db) == g.name(db) && f.data(db).eq_ignoring_spans(db, g.data(db)))
+        && statements_a.len() == statements_b.len()
+        && statements_a
+            .iter()
+            .zip(statements_b)
+            .all(|(x, y)| x.eq_ignoring_spans(y))
+}
+
 impl Visit for FunctionData {
     fn traverse<V: Visitor>(&mut self, db: &dyn crate::Db, v: &mut V) {
         self.name_span.traverse(db, v);
-        self.body.traverse(db, v);
+        for span in &mut self.arg_spans {
+            span.traverse(db, v);
+        }
+        // A changed arena is a different arena, interned anew.
+        let mut arena = self.arena(db).clone();
+        arena.traverse(db, v);
+        self.exprs = InternedArena::new(db, arena);
     }
 }
 
@@ -167,6 +949,42 @@ pub struct Span {
     pub end: usize,
 }
 
+impl Span {
+    /// The offsets of the span in the whole source text.
+    pub fn absolute(self, db: &dyn crate::Db, program: Program) -> (usize, usize) {
+        let offset = match self.id.data(db) {
+            DefIdData::Function(..) => program
+                .functions(db)
+                .iter()
+                .find(|function| function.data(db).name_span.id == self.id)
+                .map_or(0, |function| function.start(db)),
+            DefIdData::File(_) | DefIdData::Unknown => 0,
+        };
+        (offset + self.start, offset + self.end)
+    }
+}
+
+/// Shows the definition the span is in rather than its interned id:
+/// `Span(area_rectangle, 3..8)` in a function, and just the offsets for a
+/// top-level statement.
+impl<'db> DebugWithDb<dyn crate::Db + 'db> for Span {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        db: &(dyn crate::Db + 'db),
+        _include_all_fields: bool,
+    ) -> fmt::Result {
+        match self.id.data(db) {
+            DefIdData::Function(_, name) => {
+                write!(f, "Span({}, {}..{})", name.text(db), self.start, self.end)
+            }
+            DefIdData::File(_) | DefIdData::Unknown => {
+                write!(f, "Span({}..{})", self.start, self.end)
+            }
+        }
+    }
+}
+
 impl Visit for Span {
     fn traverse<V: Visitor>(&mut self, _: &dyn crate::Db, v: &mut V) {
         v.visit_span(self);
@@ -177,17 +995,89 @@ impl Visit for Span {
 #[salsa::accumulator]
 pub struct Diagnostics(Diagnostic);
 
-#[derive(new, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Diagnostic {
     pub start: usize,
     pub end: usize,
     pub message: String,
+    pub severity: Severity,
+    /// Secondary locations relevant to the diagnostic, e.g. a previous definition.
+    pub related: Vec<(Span, String)>,
+}
+
+impl Diagnostic {
+    /// An error at `start..end`.
+    pub fn new(start: usize, end: usize, message: String) -> Self {
+        debug_assert!(start <= end, "inverted diagnostic span {start}..{end}");
+        Self {
+            start,
+            end,
+            message,
+            severity: Severity::Error,
+            related: vec![],
+        }
+    }
+
+    pub fn warning(start: usize, end: usize, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(start, end, message)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// A remark about code which is probably fine, shown with `--pedantic`.
+    pub fn note(start: usize, end: usize, message: String) -> Self {
+        Self {
+            severity: Severity::Note,
+            ..Self::new(start, end, message)
+        }
+    }
+
+    pub fn with_related(mut self, span: Span, message: String) -> Self {
+        self.related.push((span, message));
+        self
+    }
+}
+
+/// Formats the related spans by the definitions they're in, rather than by
+/// the ids of those, which change whenever something else is interned first.
+impl<'db> DebugWithDb<dyn crate::Db + 'db> for Diagnostic {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        db: &(dyn crate::Db + 'db),
+        _include_all_fields: bool,
+    ) -> fmt::Result {
+        let related: Vec<_> = self
+            .related
+            .iter()
+            .map(|(span, message)| (DebugSpan(*span, Some(db)), message))
+            .collect();
+        f.debug_struct("Diagnostic")
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .field("message", &self.message)
+            .field("severity", &self.severity)
+            .field("related", &related)
+            .finish()
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
 }
 // ANCHOR_END: diagnostic
 
 pub trait Visitor {
     fn visit_statement(&mut self, _: &mut Statement) {}
-    fn visit_expr(&mut self, _: &mut Expression) {}
+    fn visit_expr(&mut self, _: ExprId, _: &mut Expression) {}
     fn visit_span(&mut self, _: &mut Span) {}
 }
 
@@ -202,3 +1092,235 @@ impl<T: Visit> Visit for Vec<T> {
         }
     }
 }
+
+/// Records the spans it visits.
+#[derive(Default)]
+struct RecordSpans {
+    spans: Vec<Span>,
+}
+
+impl Visitor for RecordSpans {
+    fn visit_span(&mut self, span: &mut Span) {
+        self.spans.push(*span);
+    }
+}
+
+/// Every span in the program, in the order they are visited: those of each
+/// function (relative to its start), then those of the statements.
+pub fn all_spans(db: &dyn crate::Db, program: Program) -> Vec<Span> {
+    let mut recorder = RecordSpans::default();
+    for function in program.functions(db) {
+        function.data(db).clone().traverse(db, &mut recorder);
+    }
+    program.statements(db).clone().traverse(db, &mut recorder);
+    recorder.spans
+}
+
+#[test]
+fn op_apply() {
+    assert_eq!(Op::Add.apply(3.0, 2.0), 5.0);
+    assert_eq!(Op::Subtract.apply(3.0, 2.0), 1.0);
+    assert_eq!(Op::Multiply.apply(3.0, 2.0), 6.0);
+    assert_eq!(Op::Divide.apply(3.0, 2.0), 1.5);
+}
+
+#[test]
+fn op_apply_divide_by_zero() {
+    assert_eq!(Op::Divide.apply(1.0, 0.0), f64::INFINITY);
+    assert_eq!(Op::Divide.apply(-1.0, 0.0), f64::NEG_INFINITY);
+    assert!(Op::Divide.apply(0.0, 0.0).is_nan());
+}
+
+#[test]
+fn op_display() {
+    let ops = [Op::Add, Op::Subtract, Op::Multiply, Op::Divide].map(|op| op.to_string());
+    assert_eq!(ops, ["+", "-", "*", "/"]);
+}
+
+#[test]
+fn expr_arena_debug_inlines_operands() {
+    let db = crate::db::Database::default();
+    let (arena, root) = crate::parser::parse_bare_expression(&db, "-x").unwrap();
+    // Operands are added first.
+    assert_eq!(arena.len(), 2);
+    assert_eq!(root, ExprId(1));
+    let expected = expect_test::expect![[r#"
+        Expression {
+            span: Span(0..2),
+            data: Neg(
+                Expression {
+                    span: Span(1..2),
+                    data: Variable(
+                        "x",
+                    ),
+                },
+            ),
+        }"#]];
+    let db: &dyn crate::Db = &db;
+    expected.assert_eq(&format!("{:#?}", arena.debug_with(Some(db), root)));
+}
+
+#[test]
+fn display_expr_parenthesizes_only_where_needed() {
+    let db = crate::db::Database::default();
+    let display = |source_text| {
+        let (arena, expression) = crate::parser::parse_bare_expression(&db, source_text).unwrap();
+        display_expr(&db, &arena, expression)
+    };
+    assert_eq!(display("1+2*x"), "1 + 2 * x");
+    assert_eq!(display("((1 + 2)) * f(x, -y)"), "(1 + 2) * f(x, -y)");
+    assert_eq!(display("a - (b - c) - d"), "a - (b - c) - d");
+    assert_eq!(display("a / (b * c)"), "a / (b * c)");
+    assert_eq!(display("-(1 - 2.5) + -3"), "-(1 - 2.5) + -3");
+    assert_eq!(display("|(a + b)| * -|-c|"), "|a + b| * -|-c|");
+}
+
+#[test]
+fn program_eq_ignores_spans() {
+    let db = crate::db::Database::default();
+    let parse = |source_text: &str| {
+        let source_program = SourceProgram::new(&db, source_text.to_string());
+        crate::parser::parse_statements(&db, source_program)
+    };
+    let program = parse("fn f(x) = x * (2 + g(x, 1));\nprint f(3);");
+    let reformatted = parse("/* f */ fn f( x )=x*(2+g(x,1)) ;  print   f(3);");
+    assert!(program_eq_ignoring_spans(&db, program, reformatted));
+    assert!(program_eq_ignoring_spans(&db, reformatted, program));
+
+    let different_body = parse("fn f(x) = x * (2 + g(x, 2));\nprint f(3);");
+    assert!(!program_eq_ignoring_spans(&db, program, different_body));
+    let different_args = parse("fn f(y) = x * (2 + g(x, 1));\nprint f(3);");
+    assert!(!program_eq_ignoring_spans(&db, program, different_args));
+    let extra_statement = parse("fn f(x) = x * (2 + g(x, 1));\nprint f(3); print 1;");
+    assert!(!program_eq_ignoring_spans(&db, program, extra_statement));
+}
+
+#[test]
+fn fmt_ast_example() {
+    let db = crate::db::Database::default();
+    let source_text = "fn area_rectangle(w, h) = w * h;
+fn area_circle(r) = 3.14 * r * r;
+print area_rectangle(3, 4);
+print area_circle(r = 1);
+print 11 * 2;
+";
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = crate::parser::parse_statements(&db, source_program);
+    expect_test::expect![[r#"
+        fn area_rectangle(w, h) 3..17
+          Op * 26..31
+            Variable w 26..27
+            Variable h 30..31
+        fn area_circle(r) 36..47
+          Op * 53..65
+            Op * 53..61
+              Number 3.14 53..57
+              Variable r 60..61
+            Variable r 64..65
+        print 67..94
+          Call area_rectangle 73..93
+            Number 3 88..89
+            Number 4 91..92
+        print 95..120
+          Call area_circle 101..119
+            Named r 117..118
+              Number 1 117..118
+        print 121..134
+          Op * 127..133
+            Number 11 127..129
+            Number 2 132..133
+    "#]]
+    .assert_eq(&fmt_ast(&db, &program));
+}
+
+#[test]
+fn structural_hash_ignores_spans() {
+    let db = crate::db::Database::default();
+    let (arena, root) =
+        crate::parser::parse_bare_expression(&db, "f(x,1) + f( x, 1 ) + f(x, 2)").unwrap();
+    let ExpressionData::Op(left, _, third) = arena[root].data else {
+        panic!("not an operation");
+    };
+    let ExpressionData::Op(first, _, second) = arena[left].data else {
+        panic!("not an operation");
+    };
+    assert!(arena.same_tree(first, second));
+    assert_eq!(arena.structural_hash(first), arena.structural_hash(second));
+    assert!(!arena.same_tree(first, third));
+    assert_ne!(arena.structural_hash(first), arena.structural_hash(third));
+
+    let (arena, root) =
+        crate::parser::parse_bare_expression(&db, r"let x = y in x + z + (\(y) -> y + w)(z)")
+            .unwrap();
+    let free: Vec<_> = arena
+        .free_variables(root)
+        .into_iter()
+        .map(|v| v.text(&db).clone())
+        .collect();
+    assert_eq!(free, ["y", "z"]);
+}
+
+#[test]
+fn all_spans_in_bounds() {
+    let db = crate::db::Database::default();
+    let source_text = "
+        fn area_rectangle(w, h) = w * h;
+        // circles
+        fn area_circle(r) = 3.14 * r * r;
+        print area_rectangle(3, 4);
+        print area_circle(1);
+    ";
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = crate::parser::parse_statements(&db, source_program);
+    let spans = all_spans(&db, program);
+    // The names, parameters and bodies of the functions, then the
+    // statements and their expressions.
+    assert_eq!(spans.len(), (1 + 2 + 3) + (1 + 1 + 5) + (1 + 3) + (1 + 2));
+    for span in spans {
+        let (start, end) = span.absolute(&db, program);
+        assert!(start <= end && end <= source_text.len(), "{span:?}");
+    }
+}
+
+#[test]
+fn apply_edits() {
+    let mut db = crate::db::Database::default();
+    let source_program = SourceProgram::new(&db, "print 1 + 2;".to_string());
+    source_program
+        .apply_edits(&mut db, &[(10..11, "f(x)"), (6..7, "3"), (0..0, "// é\n")])
+        .unwrap();
+    assert_eq!(source_program.text(&db), "// é\nprint 3 + f(x);");
+    source_program.apply_edit(&mut db, 6..12, "").unwrap();
+    assert_eq!(source_program.text(&db), "// é\n3 + f(x);");
+
+    // Nothing is applied if any edit is invalid.
+    let mut error = |edits: &[(Range<usize>, &str)]| {
+        let error = source_program.apply_edits(&mut db, edits).unwrap_err();
+        assert_eq!(source_program.text(&db), "// é\n3 + f(x);");
+        error
+    };
+    assert_eq!(
+        error(&[(0..1, ""), (15..16, "")]),
+        "edit at 15..16 is out of bounds of the text of length 15"
+    );
+    assert_eq!(error(&[(4..5, "e")]), "edit at 4..5 splits a character");
+    assert_eq!(
+        error(&[(6..9, "4"), (8..10, "g")]),
+        "edits at 6..9 and 8..10 overlap"
+    );
+}
+
+#[test]
+fn empty_edit_changes_nothing() {
+    let mut db = crate::db::Database::builder().logging(true).build();
+    let source_program = SourceProgram::new(&db, "fn f(x) = x; print f(1);".to_string());
+    crate::compile::compile_diagnostics(&db, source_program);
+    db.take_logs();
+
+    source_program.apply_edit(&mut db, 3..3, "").unwrap();
+    source_program
+        .apply_edits(&mut db, &[(0..2, "fn"), (5..5, "")])
+        .unwrap();
+    crate::compile::compile_diagnostics(&db, source_program);
+    assert_eq!(db.take_logs(), crate::db::Logs::default());
+}
@@ -0,0 +1,408 @@
+//! The parsed program as JSON (`--emit json`), for tools outside the
+//! compiler such as visualizers. Names are resolved to their text and spans
+//! are absolute `[start, end]` offsets in the file.
+//!
+//! The JSON is built as a `Json` value rather than with a serialization
+//! library, since the interned ids of the IR only mean something together
+//! with the database.
+
+use std::fmt;
+
+use crate::eval::format_number;
+use crate::ir::{ExprArena, ExprId, ExpressionData, Program, Span, StatementData};
+
+/// A JSON value. Objects keep their keys in the order they were added.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn object<const N: usize>(fields: [(&str, Json); N]) -> Self {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn string(text: &str) -> Self {
+        Json::String(text.to_string())
+    }
+
+    /// The value of `key`, if this is an object which has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Reads a JSON document. Returns `None` if it isn't valid JSON.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parser = JsonParser {
+            text: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        (parser.pos == text.len()).then_some(value)
+    }
+}
+
+/// Written without whitespace, with strings escaped as JSON requires.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{b}"),
+            Json::Number(n) => write!(f, "{}", format_number(*n)),
+            Json::String(text) => write_string(f, text),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct JsonParser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.text.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips whitespace, then `token` if it's next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.text[self.pos..].starts_with(token.as_bytes());
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        match *self.text.get(self.pos)? {
+            b'n' if self.eat("null") => Some(Json::Null),
+            b't' if self.eat("true") => Some(Json::Bool(true)),
+            b'f' if self.eat("false") => Some(Json::Bool(false)),
+            b'"' => self.string().map(Json::String),
+            b'[' => {
+                self.pos += 1;
+                let mut items = vec![];
+                if !self.eat("]") {
+                    loop {
+                        items.push(self.value()?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        self.eat(",").then_some(())?;
+                    }
+                }
+                Some(Json::Array(items))
+            }
+            b'{' => {
+                self.pos += 1;
+                let mut fields = vec![];
+                if !self.eat("}") {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.eat(":").then_some(())?;
+                        fields.push((key, self.value()?));
+                        if self.eat("}") {
+                            break;
+                        }
+                        self.eat(",").then_some(())?;
+                    }
+                }
+                Some(Json::Object(fields))
+            }
+            _ => {
+                let start = self.pos;
+                while self
+                    .text
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_ascii_digit() || b"+-.eE".contains(c))
+                {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.pos]).ok()?;
+                number.parse().ok().map(Json::Number)
+            }
+        }
+    }
+
+    /// A string, starting at its opening quote.
+    fn string(&mut self) -> Option<String> {
+        if self.text.get(self.pos) != Some(&b'"') {
+            return None;
+        }
+        self.pos += 1;
+        let mut bytes = vec![];
+        loop {
+            let c = *self.text.get(self.pos)?;
+            self.pos += 1;
+            match c {
+                b'"' => return String::from_utf8(bytes).ok(),
+                b'\\' => {
+                    let escaped = *self.text.get(self.pos)?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let hex = self.text.get(self.pos..self.pos + 4)?;
+                            self.pos += 4;
+                            let code = u32::from_str_radix(std::str::from_utf8(hex).ok()?, 16);
+                            char::from_u32(code.ok()?)?
+                        }
+                        _ => return None,
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                c => bytes.push(c),
+            }
+        }
+    }
+}
+
+/// The functions and statements of `program`, with their expressions as
+/// nested objects with a `kind`, e.g.
+/// `{"kind":"op","op":"+","lhs":...,"rhs":...,"span":[3,8]}`.
+pub fn program_json(db: &dyn crate::Db, program: Program) -> Json {
+    let span = |span: Span| {
+        let (start, end) = span.absolute(db, program);
+        Json::Array(vec![Json::Number(start as f64), Json::Number(end as f64)])
+    };
+    let functions = program
+        .functions(db)
+        .iter()
+        .map(|function| {
+            let data = function.data(db);
+            Json::object([
+                ("name", Json::string(function.name(db).text(db))),
+                (
+                    "params",
+                    Json::Array(
+                        data.args
+                            .iter()
+                            .map(|arg| Json::string(arg.text(db)))
+                            .collect(),
+                    ),
+                ),
+                ("span", span(data.name_span)),
+                ("body", expr_json(db, &span, data.arena(db), data.body)),
+            ])
+        })
+        .collect();
+    let statements = program
+        .statements(db)
+        .iter()
+        .map(|statement| match &statement.data {
+            StatementData::Print(expression) => Json::object([
+                ("kind", Json::string("print")),
+                ("span", span(statement.span)),
+                (
+                    "expression",
+                    expr_json(db, &span, &statement.arena, *expression),
+                ),
+            ]),
+        })
+        .collect();
+    Json::object([
+        ("functions", Json::Array(functions)),
+        ("statements", Json::Array(statements)),
+    ])
+}
+
+fn expr_json(
+    db: &dyn crate::Db,
+    span: &dyn Fn(Span) -> Json,
+    arena: &ExprArena,
+    id: ExprId,
+) -> Json {
+    let expr = |id| expr_json(db, span, arena, id);
+    let exprs = |ids: &[ExprId]| Json::Array(ids.iter().map(|&id| expr(id)).collect());
+    let (kind, mut fields) = match &arena[id].data {
+        ExpressionData::Op(lhs, op, rhs) => (
+            "op",
+            vec![
+                ("op", Json::String(op.to_string())),
+                ("lhs", expr(*lhs)),
+                ("rhs", expr(*rhs)),
+            ],
+        ),
+        ExpressionData::Neg(operand) => ("neg", vec![("operand", expr(*operand))]),
+        ExpressionData::Abs(operand) => ("abs", vec![("operand", expr(*operand))]),
+        ExpressionData::Number(n) => ("number", vec![("value", Json::Number(n.into_inner()))]),
+        ExpressionData::Bool(b) => ("bool", vec![("value", Json::Bool(*b))]),
+        ExpressionData::Variable(v) => ("variable", vec![("name", Json::string(v.text(db)))]),
+        ExpressionData::Call(f, args) => {
+            let args = args
+                .iter()
+                .map(|arg| {
+                    let name = arg
+                        .name
+                        .map_or(Json::Null, |name| Json::string(name.text(db)));
+                    Json::object([("name", name), ("value", expr(arg.value))])
+                })
+                .collect();
+            (
+                "call",
+                vec![
+                    ("function", Json::string(f.text(db))),
+                    ("args", Json::Array(args)),
+                ],
+            )
+        }
+        ExpressionData::Lambda(params, body, args) => (
+            "lambda",
+            vec![
+                (
+                    "params",
+                    Json::Array(
+                        params
+                            .iter()
+                            .map(|param| Json::string(param.text(db)))
+                            .collect(),
+                    ),
+                ),
+                ("body", expr(*body)),
+                ("args", exprs(args)),
+            ],
+        ),
+        ExpressionData::Let(name, value, body) => (
+            "let",
+            vec![
+                ("name", Json::string(name.text(db))),
+                ("value", expr(*value)),
+                ("body", expr(*body)),
+            ],
+        ),
+        ExpressionData::Print(operand) => ("print", vec![("operand", expr(*operand))]),
+        ExpressionData::Error(message) => ("error", vec![("message", Json::string(message))]),
+    };
+    fields.insert(0, ("kind", Json::string(kind)));
+    fields.push(("span", span(arena[id].span)));
+    Json::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+#[test]
+fn json_round_trip() {
+    let text = r#"{"a":[1,-2.5,true,null],"b":"say \"hi\"\n\u0001","c":{}}"#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(
+        json.get("b").and_then(Json::as_str),
+        Some("say \"hi\"\n\u{1}")
+    );
+    assert_eq!(json.to_string(), text);
+    assert_eq!(Json::parse(" [ 1 , 2 ] ").unwrap().to_string(), "[1,2]");
+    for invalid in ["", "[1,]", "{\"a\"}", "[1] 2", "\"open"] {
+        assert_eq!(Json::parse(invalid), None, "{invalid}");
+    }
+}
+
+#[test]
+fn json_of_example_program() {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_text = "fn area_rectangle(w, h) = w * h;
+fn area_circle(r) = 3.14 * r * r;
+print area_rectangle(3, 4);
+print area_circle(r = 1);
+print 11 * 2;
+";
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = parse_statements(&db, source_program);
+    let json = program_json(&db, program);
+    // The output is valid JSON, which reads back the same.
+    assert_eq!(Json::parse(&json.to_string()), Some(json.clone()));
+
+    let items = ["functions", "statements"]
+        .into_iter()
+        .flat_map(|key| json.get(key).and_then(Json::as_array).unwrap())
+        .map(|item| format!("{item}\n"))
+        .collect::<String>();
+    let expected = expect_test::expect![[r#"
+        {"name":"area_rectangle","params":["w","h"],"span":[3,17],"body":{"kind":"op","op":"*","lhs":{"kind":"variable","name":"w","span":[26,27]},"rhs":{"kind":"variable","name":"h","span":[30,31]},"span":[26,31]}}
+        {"name":"area_circle","params":["r"],"span":[36,47],"body":{"kind":"op","op":"*","lhs":{"kind":"op","op":"*","lhs":{"kind":"number","value":3.14,"span":[53,57]},"rhs":{"kind":"variable","name":"r","span":[60,61]},"span":[53,61]},"rhs":{"kind":"variable","name":"r","span":[64,65]},"span":[53,65]}}
+        {"kind":"print","span":[67,94],"expression":{"kind":"call","function":"area_rectangle","args":[{"name":null,"value":{"kind":"number","value":3,"span":[88,89]}},{"name":null,"value":{"kind":"number","value":4,"span":[91,92]}}],"span":[73,93]}}
+        {"kind":"print","span":[95,120],"expression":{"kind":"call","function":"area_circle","args":[{"name":"r","value":{"kind":"number","value":1,"span":[117,118]}}],"span":[101,119]}}
+        {"kind":"print","span":[121,134],"expression":{"kind":"op","op":"*","lhs":{"kind":"number","value":11,"span":[127,129]},"rhs":{"kind":"number","value":2,"span":[132,133]},"span":[127,133]}}
+    "#]];
+    expected.assert_eq(&items);
+}
@@ -0,0 +1,282 @@
+//! A standalone tokenizer mirroring the `match` block in `grammar.lalrpop`.
+//!
+//! The parser uses the lexer generated by lalrpop; this one exists for
+//! debugging (`--emit tokens`) and must be kept in sync with the grammar.
+
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub enum TokenKind {
+    Fn,
+    Print,
+    Include,
+    True,
+    False,
+    Let,
+    In,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Pipe,
+    Backslash,
+    Arrow,
+    LParen,
+    RParen,
+    Semicolon,
+    Equals,
+    Comma,
+    Dot,
+    Hash,
+    LBracket,
+    RBracket,
+    Number,
+    Identifier,
+    String,
+    /// A `// ...` or `/* ... */` comment, only produced by
+    /// `tokenize_with_comments`.
+    Comment,
+    /// A character (or unterminated comment or string) the grammar doesn't
+    /// accept.
+    Error,
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct Token<'input> {
+    pub kind: TokenKind,
+    pub text: &'input str,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = tokenize_with_comments(text);
+    tokens.retain(|token| token.kind != TokenKind::Comment);
+    tokens
+}
+
+/// Like `tokenize`, but with the comments as `Comment` tokens, for tools
+/// which keep them, such as the formatter.
+pub fn tokenize_with_comments(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while let Some(c) = text[pos..].chars().next() {
+        let rest = &text[pos..];
+        let (kind, len) = if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        } else if rest.starts_with("//") {
+            (
+                TokenKind::Comment,
+                take_while(rest, |c| c != '\n' && c != '\r'),
+            )
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.find("*/") {
+                Some(end) => (TokenKind::Comment, end + 4),
+                None => (TokenKind::Error, rest.len()),
+            }
+        } else if let Some(string) = rest.strip_prefix(r#"""""#) {
+            match string.find(r#"""""#) {
+                Some(end) => (TokenKind::String, end + 6),
+                None => (TokenKind::Error, rest.len()),
+            }
+        } else if let Some(string) = rest.strip_prefix("r\"") {
+            match string.find('"') {
+                Some(end) => (TokenKind::String, end + 3),
+                None => (TokenKind::Error, rest.len()),
+            }
+        } else if let Some(string) = rest.strip_prefix('"') {
+            match string_literal_len(string) {
+                Some(len) => (TokenKind::String, len + 2),
+                None => (TokenKind::Error, rest.len()),
+            }
+        } else if c.is_ascii_digit() {
+            let mut len = take_while(rest, |c| c.is_ascii_digit());
+            if let Some(fraction) = rest[len..].strip_prefix('.') {
+                let digits = take_while(fraction, |c| c.is_ascii_digit());
+                if digits > 0 {
+                    len += 1 + digits;
+                }
+            }
+            (TokenKind::Number, len)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = take_while(rest, |c| c.is_ascii_alphanumeric() || c == '_');
+            let kind = match &rest[..len] {
+                "fn" => TokenKind::Fn,
+                "print" => TokenKind::Print,
+                "include" => TokenKind::Include,
+                "true" => TokenKind::True,
+                "false" => TokenKind::False,
+                "let" => TokenKind::Let,
+                "in" => TokenKind::In,
+                _ => TokenKind::Identifier,
+            };
+            (kind, len)
+        } else if rest.starts_with("->") {
+            (TokenKind::Arrow, 2)
+        } else {
+            let kind = match c {
+                '+' => TokenKind::Plus,
+                '-' => TokenKind::Minus,
+                '*' => TokenKind::Star,
+                '/' => TokenKind::Slash,
+                '|' => TokenKind::Pipe,
+                '\\' => TokenKind::Backslash,
+                '(' => TokenKind::LParen,
+                ')' => TokenKind::RParen,
+                ';' => TokenKind::Semicolon,
+                '=' => TokenKind::Equals,
+                ',' => TokenKind::Comma,
+                '.' => TokenKind::Dot,
+                '#' => TokenKind::Hash,
+                '[' => TokenKind::LBracket,
+                ']' => TokenKind::RBracket,
+                _ => TokenKind::Error,
+            };
+            (kind, c.len_utf8())
+        };
+        tokens.push(Token {
+            kind,
+            text: &rest[..len],
+            start: pos,
+            end: pos + len,
+        });
+        pos += len;
+    }
+    tokens
+}
+
+/// Renders the token stream one token per line, as `Kind "text" start..end`.
+pub fn dump_tokens(text: &str) -> String {
+    tokenize(text)
+        .iter()
+        .map(|token| {
+            format!(
+                "{:?} {:?} {}..{}\n",
+                token.kind, token.text, token.start, token.end
+            )
+        })
+        .collect()
+}
+
+/// The length of the contents of a `"..."` string, up to the closing quote,
+/// if it is closed on the same line.
+fn string_literal_len(string: &str) -> Option<usize> {
+    let mut chars = string.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some(i),
+            '\n' | '\r' => return None,
+            '\\' => {
+                if let Some((_, '\n' | '\r')) | None = chars.next() {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn take_while(text: &str, predicate: impl Fn(char) -> bool) -> usize {
+    text.find(|c| !predicate(c)).unwrap_or(text.len())
+}
+
+#[test]
+fn dump_print() {
+    let expected = expect_test::expect![[r#"
+        Print "print" 0..5
+        Number "1" 6..7
+        Plus "+" 8..9
+        Number "2" 10..11
+        Semicolon ";" 11..12
+    "#]];
+    expected.assert_eq(&dump_tokens("print 1 + 2;"));
+}
+
+#[test]
+fn dump_skips_comments() {
+    let expected = expect_test::expect![[r#"
+        Fn "fn" 15..17
+        Identifier "f_1" 18..21
+        Error "$" 29..30
+    "#]];
+    expected.assert_eq(&dump_tokens("// comment\n/**/fn f_1 /* x */$"));
+}
+
+#[test]
+fn comment_tokens() {
+    let kinds: Vec<_> = tokenize_with_comments("// a\nfn /* b */ f")
+        .iter()
+        .map(|token| (token.kind, token.text))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (TokenKind::Comment, "// a"),
+            (TokenKind::Fn, "fn"),
+            (TokenKind::Comment, "/* b */"),
+            (TokenKind::Identifier, "f"),
+        ]
+    );
+}
+
+#[test]
+fn dump_fractional_and_negative() {
+    let expected = expect_test::expect![[r#"
+        Number "3" 0..1
+        Minus "-" 1..2
+        Minus "-" 2..3
+        Number "2.5" 3..6
+        Pipe "|" 6..7
+        Minus "-" 7..8
+        Number "1" 8..9
+        Pipe "|" 9..10
+    "#]];
+    expected.assert_eq(&dump_tokens("3--2.5|-1|"));
+}
+
+#[test]
+fn dump_method_call_on_number() {
+    // A `.` without digits after it isn't part of the number.
+    let expected = expect_test::expect![[r#"
+        Number "2" 0..1
+        Dot "." 1..2
+        Identifier "sqrt" 2..6
+        LParen "(" 6..7
+        RParen ")" 7..8
+    "#]];
+    expected.assert_eq(&dump_tokens("2.sqrt()"));
+}
+
+#[test]
+fn dump_bool_keywords() {
+    let expected = expect_test::expect![[r#"
+        True "true" 0..4
+        False "false" 5..10
+        Identifier "trueish" 11..18
+    "#]];
+    expected.assert_eq(&dump_tokens("true false trueish"));
+}
+
+#[test]
+fn dump_strings() {
+    let expected = expect_test::expect![[r#"
+        String "\"a \\\" b\"" 0..8
+        String "r\"c:\\d\\\"" 9..17
+        String "\"\"\"x\n\"y\"\n\"\"\"" 18..30
+        Error "\"z\n\"" 31..35
+    "#]];
+    expected.assert_eq(&dump_tokens(
+        "\"a \\\" b\" r\"c:\\d\\\" \"\"\"x\n\"y\"\n\"\"\" \"z\n\"",
+    ));
+}
+
+#[test]
+fn dump_include() {
+    let expected = expect_test::expect![[r#"
+        Include "include" 0..7
+        String "\"a b\"" 8..13
+        Semicolon ";" 13..14
+        Error "\"x" 15..17
+    "#]];
+    expected.assert_eq(&dump_tokens(r#"include "a b"; "x"#));
+}
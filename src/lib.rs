@@ -0,0 +1,92 @@
+//! An incremental compiler for a small language of numeric functions and
+//! `print` statements, built on salsa. The `banana` binary is a command line
+//! interface to this library.
+
+#[macro_use]
+extern crate lalrpop_util;
+
+use ir::Files;
+
+// ANCHOR: jar_struct
+#[salsa::jar(db = Db)]
+pub struct Jar(
+    crate::compile::compile,
+    crate::ir::SourceProgram,
+    crate::ir::Files,
+    crate::ir::Program,
+    crate::ir::VariableId,
+    crate::ir::FunctionId,
+    crate::ir::Function,
+    crate::ir::Diagnostics,
+    crate::ir::DefId,
+    crate::ir::InternedArena,
+    crate::ir::ItemText,
+    crate::parser::parse_statements,
+    crate::parser::split_into_items,
+    crate::parser::parse_item,
+    crate::parser::parse_item_statements,
+    crate::parser::parse_included,
+    crate::type_check::type_check_program,
+    crate::type_check::check_duplicate_definitions,
+    crate::type_check::type_check_function,
+    crate::type_check::type_check_statements,
+    crate::type_check::expression_types,
+    crate::type_check::find_function,
+    crate::lint::lint_program,
+    crate::lint::lint_function,
+    crate::lint::pedantic_program,
+    crate::render::resolve_span,
+    crate::call_graph::callees,
+    crate::call_graph::call_depths,
+    crate::call_graph::check_call_depth,
+    crate::eval::eval_program,
+    crate::eval::eval_source_expression,
+    crate::bytecode::compile_function_bytecode,
+    crate::bytecode::compile_program_bytecode,
+    crate::bytecode::run_bytecode,
+    crate::signature::signature,
+    crate::workspace::Workspace,
+    crate::workspace::workspace_program,
+);
+// ANCHOR_END: jar_struct
+
+// ANCHOR: jar_db
+pub trait Db: salsa::DbWithJar<Jar> {
+    /// The files loaded from disk, which includes are resolved against.
+    fn files(&self) -> Files;
+
+    /// Records an event of the compiler in the database's log, if logging is
+    /// enabled and the log filter lets it through.
+    fn log(&self, event: db::LogEvent);
+}
+// ANCHOR_END: jar_db
+
+pub mod builtins;
+pub mod bytecode;
+pub mod cache;
+pub mod call_graph;
+pub mod codegen;
+pub mod compile;
+pub mod db;
+pub mod eval;
+pub mod fold;
+pub mod format;
+pub mod generate;
+pub mod ir;
+pub mod json_ast;
+pub mod lexer;
+pub mod lint;
+pub mod locate;
+pub mod parser;
+pub mod raw;
+pub mod render;
+pub mod repl;
+pub mod service;
+pub mod signature;
+pub mod stepper;
+pub mod timing;
+pub mod type_check;
+pub mod value;
+pub mod workspace;
+
+pub use eval::eval_expr;
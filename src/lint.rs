@@ -0,0 +1,193 @@
+use crate::fold::fold_constant;
+use std::collections::HashSet;
+
+use crate::ir::{
+    display_expr, Diagnostic, Diagnostics, ExprArena, ExpressionData, Function, FunctionData,
+    Program, StatementData, VariableId,
+};
+#[cfg(test)]
+use expect_test::expect;
+
+/// Optional lints. Unlike type errors, these only produce warnings, and are
+/// only run when requested (`--lint` on the command line).
+#[salsa::tracked]
+pub fn lint_program(db: &dyn crate::Db, program: Program) {
+    for function in program.functions(db) {
+        lint_function(db, *function)
+    }
+}
+
+#[salsa::tracked]
+pub fn lint_function(db: &dyn crate::Db, function: Function) {
+    let data = function.data(db);
+    for (first, second) in parameters_differing_by_case(db, data) {
+        Diagnostics::push(
+            db,
+            Diagnostic::warning(
+                data.name_span.start,
+                data.name_span.end,
+                format!(
+                    "parameters `{}` and `{}` differ only by case",
+                    first.text(db),
+                    second.text(db)
+                ),
+            ),
+        );
+    }
+}
+
+/// Pedantic lints (`--pedantic` on the command line): notes about code which
+/// is probably fine, but may be a mistake.
+#[salsa::tracked]
+pub fn pedantic_program(db: &dyn crate::Db, program: Program) {
+    for function in program.functions(db) {
+        let data = function.data(db);
+        if ignores_all_parameters(db, data) {
+            Diagnostics::push(
+                db,
+                Diagnostic::note(
+                    data.name_span.start,
+                    data.name_span.end,
+                    format!(
+                        "`{}` ignores all of its parameters; could it be a constant?",
+                        function.name(db).text(db)
+                    ),
+                ),
+            );
+        }
+    }
+    for statement in program.statements(db) {
+        match &statement.data {
+            StatementData::Print(expression) => {
+                if let Some(value) = fold_constant(db, program, &statement.arena, *expression) {
+                    Diagnostics::push(
+                        db,
+                        Diagnostic::note(
+                            statement.span.start,
+                            statement.span.end,
+                            format!(
+                                "`{}` always prints {value}; is this a leftover debug print?",
+                                display_expr(db, &statement.arena, *expression)
+                            ),
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Returns pairs of distinct parameters whose names are equal when lowercased.
+pub fn parameters_differing_by_case(
+    db: &dyn crate::Db,
+    data: &FunctionData,
+) -> Vec<(VariableId, VariableId)> {
+    let mut result = vec![];
+    for (i, first) in data.args.iter().enumerate() {
+        for second in &data.args[i + 1..] {
+            if first != second && first.text(db).to_lowercase() == second.text(db).to_lowercase() {
+                result.push((*first, *second));
+            }
+        }
+    }
+    result
+}
+
+/// The variables referenced anywhere in `arena`. A reference to a name bound
+/// by a `let` or a lambda counts too, even if it shadows a parameter.
+pub fn referenced_variables(arena: &ExprArena) -> HashSet<VariableId> {
+    arena
+        .iter()
+        .filter_map(|(_, expression)| match &expression.data {
+            ExpressionData::Variable(variable) => Some(*variable),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether the function has parameters, but its body references none of them.
+pub fn ignores_all_parameters(db: &dyn crate::Db, data: &FunctionData) -> bool {
+    let referenced = referenced_variables(data.arena(db));
+    !data.args.is_empty() && !data.args.iter().any(|arg| referenced.contains(arg))
+}
+
+#[cfg(test)]
+fn lint_string(source_text: &str, expected_diagnostics: expect_test::Expect) {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = parse_statements(&db, source_program);
+    expected_diagnostics.assert_debug_eq(&lint_program::accumulated::<Diagnostics>(&db, program));
+}
+
+#[test]
+fn lint_parameters_differing_by_case() {
+    lint_string(
+        "fn f(X, x) = X + x;",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 3,
+                    end: 4,
+                    message: "parameters `X` and `x` differ only by case",
+                    severity: Warning,
+                    related: [],
+                },
+            ]
+        "#]],
+    );
+}
+
+#[test]
+fn lint_distinct_parameters() {
+    lint_string(
+        "fn f(a, b) = a + b;",
+        expect![[r#"
+            []
+        "#]],
+    );
+}
+
+#[cfg(test)]
+fn pedantic_string(source_text: &str) -> Vec<String> {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = parse_statements(&db, source_program);
+    pedantic_program::accumulated::<Diagnostics>(&db, program)
+        .into_iter()
+        .map(|diagnostic| {
+            format!(
+                "{:?} {}..{}: {}",
+                diagnostic.severity, diagnostic.start, diagnostic.end, diagnostic.message
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn pedantic_constant_print() {
+    let expected = expect![[r#"
+        [
+            "Note 0..9: `42` always prints 42; is this a leftover debug print?",
+            "Note 27..48: `sqrt(4) * -|3|` always prints -6; is this a leftover debug print?",
+        ]
+    "#]];
+    expected.assert_debug_eq(&pedantic_string(
+        "print 42;\nfn f(x) = x * 2;\nprint sqrt(4) * -|3|;\nprint f(x);\nprint f(1);",
+    ));
+}
+
+#[test]
+fn pedantic_ignored_parameters() {
+    let expected = expect![[r#"
+        [
+            "Note 3..4: `f` ignores all of its parameters; could it be a constant?",
+        ]
+    "#]];
+    expected.assert_debug_eq(&pedantic_string(
+        "fn f(a, b) = 5;\nfn g(a, b) = b + 1;\nfn h() = 2;",
+    ));
+}
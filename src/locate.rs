@@ -0,0 +1,109 @@
+//! Finding the node of a program at a position, for tools such as hover and
+//! go-to-definition.
+
+use crate::ir::{DefIdData, ExprId, Expression, Function, SourceProgram, Span, Visit, Visitor};
+use crate::parser::{parse_statements, split_into_items};
+use crate::render::resolve_span;
+
+/// A node of the program: a function definition, a statement or an
+/// expression.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum NodeRef {
+    Function(Function),
+    /// A statement, by its span.
+    Statement(Span),
+    /// An expression, by its span.
+    Expression(Span),
+}
+
+/// Records the spans of the expressions it visits.
+#[derive(Default)]
+struct RecordExpressions {
+    spans: Vec<Span>,
+}
+
+impl Visitor for RecordExpressions {
+    fn visit_expr(&mut self, _: ExprId, expression: &mut Expression) {
+        self.spans.push(expression.span);
+    }
+}
+
+/// The innermost node of the program in `source` containing the byte
+/// `offset`. An offset in whitespace or a comment inside a function or
+/// statement gives that function or statement; one between them, or past
+/// the end of the text, gives `None`.
+pub fn node_at_offset(db: &dyn crate::Db, source: SourceProgram, offset: usize) -> Option<NodeRef> {
+    let item = split_into_items(db, source)
+        .iter()
+        .find(|item| item.start <= offset && offset < item.start + item.text.text(db).len())?;
+    let program = parse_statements(db, source);
+    let mut expressions = RecordExpressions::default();
+    // Functions from included files may start at the same offset.
+    let in_source = |function: &Function| match function.data(db).name_span.id.data(db) {
+        DefIdData::Function(file, _) => file == source,
+        DefIdData::File(_) | DefIdData::Unknown => false,
+    };
+    let node = if let Some(function) = program
+        .functions(db)
+        .iter()
+        .find(|function| function.start(db) == item.start && in_source(function))
+    {
+        function.data(db).clone().traverse(db, &mut expressions);
+        NodeRef::Function(*function)
+    } else {
+        let mut statement = program
+            .statements(db)
+            .iter()
+            .find(|statement| statement.span.start == item.start)?
+            .clone();
+        statement.traverse(db, &mut expressions);
+        NodeRef::Statement(statement.span)
+    };
+    let innermost = expressions
+        .spans
+        .into_iter()
+        .map(|span| (span, resolve_span(db, source, span)))
+        .filter(|(_, resolved)| resolved.abs_start <= offset && offset < resolved.abs_end)
+        .min_by_key(|(_, resolved)| resolved.abs_end - resolved.abs_start);
+    Some(match innermost {
+        Some((span, _)) => NodeRef::Expression(span),
+        None => node,
+    })
+}
+
+#[test]
+fn node_at_offsets() {
+    let db = crate::db::Database::default();
+    let source_text = "fn area(w, h) = w * h;\n\n// areas\nprint area(2, 3 + 4);\n";
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let offset = |text: &str| source_text.find(text).unwrap();
+    let text_at = |offset| {
+        node_at_offset(&db, source_program, offset).map(|node| match node {
+            NodeRef::Function(function) => format!("fn {}", function.name(&db).text(&db)),
+            NodeRef::Statement(span) => {
+                format!("statement {}", resolve_span(&db, source_program, span).text)
+            }
+            NodeRef::Expression(span) => resolve_span(&db, source_program, span).text.clone(),
+        })
+    };
+
+    // In a call argument.
+    assert_eq!(text_at(offset("3 + 4")).as_deref(), Some("3"));
+    assert_eq!(text_at(offset("2, 3")).as_deref(), Some("2"));
+    // On an operator, in a statement and in a function.
+    assert_eq!(text_at(offset("+ 4")).as_deref(), Some("3 + 4"));
+    assert_eq!(text_at(offset("* h")).as_deref(), Some("w * h"));
+    // On the call's name.
+    assert_eq!(text_at(offset("area(2")).as_deref(), Some("area(2, 3 + 4)"));
+    // Outside of any expression.
+    assert_eq!(text_at(offset("area(w")).as_deref(), Some("fn area"));
+    assert_eq!(
+        text_at(offset(" area(2")).as_deref(),
+        Some("statement print area(2, 3 + 4);")
+    );
+    // Between statements, and past the end.
+    assert_eq!(text_at(offset("\n\n") + 1), None);
+    assert_eq!(text_at(offset("areas")), None);
+    assert_eq!(text_at(source_text.len()), None);
+    assert_eq!(text_at(source_text.len() + 10), None);
+}
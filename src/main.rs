@@ -1,53 +1,483 @@
-#[macro_use]
-extern crate lalrpop_util;
-
-use std::{fs::File, io::Read};
-
-use ir::{Diagnostics, SourceProgram};
-
-// ANCHOR: jar_struct
-#[salsa::jar(db = Db)]
-pub struct Jar(
-    crate::compile::compile,
-    crate::ir::SourceProgram,
-    crate::ir::Program,
-    crate::ir::VariableId,
-    crate::ir::FunctionId,
-    crate::ir::Function,
-    crate::ir::Diagnostics,
-    crate::ir::DefId,
-    crate::parser::parse_statements,
-    crate::type_check::type_check_program,
-    crate::type_check::type_check_function,
-    crate::type_check::find_function,
-);
-// ANCHOR_END: jar_struct
-
-// ANCHOR: jar_db
-pub trait Db: salsa::DbWithJar<Jar> {}
-// ANCHOR_END: jar_db
-
-// ANCHOR: jar_db_impl
-impl<DB> Db for DB where DB: ?Sized + salsa::DbWithJar<Jar> {}
-// ANCHOR_END: jar_db_impl
-
-mod compile;
-mod db;
-mod ir;
-mod parser;
-mod type_check;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use banana::compile::{self, Backend, CompileMode};
+use banana::ir::{self, Diagnostics};
+use banana::{
+    bytecode, cache, call_graph, codegen, db, eval, format, json_ast, lexer, lint, parser, render,
+    repl, timing, type_check, workspace,
+};
+
+/// Command line options.
+#[derive(Default)]
+struct Options {
+    /// Run the optional lints in addition to the compiler.
+    lint: bool,
+    /// Add notes about code which may be a mistake (`--pedantic`).
+    pedantic: bool,
+    emit: Option<Emit>,
+    /// Warn about functions with a deeper call depth (`--max-call-depth`).
+    max_call_depth: Option<usize>,
+    /// Show at most this many errors (`--max-errors`).
+    max_errors: Option<usize>,
+    /// Hide diagnostics less severe than this (`--min-severity`).
+    min_severity: Option<ir::Severity>,
+    eval: eval::EvalOptions,
+    backend: Backend,
+    /// Print statistics about running each program, and the queries each
+    /// compile ran (`--stats`).
+    stats: bool,
+    /// Print how long each phase of compiling a file took (`--time`).
+    time: bool,
+    /// Print the compiler's log, such as the queries it ran and the
+    /// functions it parsed (`--verbose`).
+    verbose: bool,
+    /// Never color diagnostics (`--no-color`), even on a terminal.
+    no_color: bool,
+    /// Reuse type checking results from the last run, kept in
+    /// `cache::CACHE_FILE` (`--cache`).
+    cache: bool,
+    /// Refuse to read files longer than this many bytes (`--max-file-size`),
+    /// instead of `db::DEFAULT_MAX_FILE_SIZE`.
+    max_file_size: Option<usize>,
+    /// Only parse and type check the files (`--check`), without running
+    /// them or evaluating bare expressions. Errors make the exit code 1.
+    check: bool,
+    /// Start an interactive session (`--repl`), which is also the default
+    /// without any files if standard input is a terminal.
+    repl: bool,
+    /// The files to compile, where `-` is standard input.
+    files: Vec<String>,
+}
+
+/// Intermediate output to print instead of compiling (`--emit <what>`).
+#[derive(Clone, Copy)]
+enum Emit {
+    Tokens,
+    Types,
+    CallDepth,
+    Bytecode,
+    Rust,
+    Wat,
+    /// The parsed program, with names resolved.
+    Ast,
+    /// The parsed program as JSON, for other tools.
+    Json,
+}
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--lint" => options.lint = true,
+                "--pedantic" => options.pedantic = true,
+                "--strict-math" => options.eval.strict_math = true,
+                "--memoize" => options.eval.memoize = true,
+                "--cse" => options.eval.cse = true,
+                "--repl" => options.repl = true,
+                "--stats" => options.stats = true,
+                "--time" => options.time = true,
+                "--verbose" => options.verbose = true,
+                "--cache" => options.cache = true,
+                "--check" => options.check = true,
+                "--no-color" => options.no_color = true,
+                "--emit" => {
+                    let what = args.next().ok_or("`--emit` requires an argument")?;
+                    options.emit = Some(Emit::parse(&what)?);
+                }
+                "--max-depth" => {
+                    options.eval.max_depth = parse_value(&arg, args.next())?;
+                }
+                "--max-call-depth" => {
+                    options.max_call_depth = Some(parse_value(&arg, args.next())?);
+                }
+                "--max-errors" => {
+                    options.max_errors = Some(parse_value(&arg, args.next())?);
+                }
+                "--max-file-size" => {
+                    options.max_file_size = Some(parse_value(&arg, args.next())?);
+                }
+                "--min-severity" => {
+                    let level = args.next().ok_or("`--min-severity` requires an argument")?;
+                    options.min_severity = Some(parse_severity(&level)?);
+                }
+                "--backend" => {
+                    let engine = args.next().ok_or("`--backend` requires an argument")?;
+                    options.backend = parse_backend(&engine)?;
+                }
+                _ => {
+                    if let Some(what) = arg.strip_prefix("--emit=") {
+                        options.emit = Some(Emit::parse(what)?);
+                    } else if let Some(level) = arg.strip_prefix("--min-severity=") {
+                        options.min_severity = Some(parse_severity(level)?);
+                    } else if let Some(engine) = arg.strip_prefix("--backend=") {
+                        options.backend = parse_backend(engine)?;
+                    } else {
+                        options.files.push(arg);
+                    }
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    /// Whether `diagnostic` is severe enough to show.
+    fn shows(&self, diagnostic: &ir::Diagnostic) -> bool {
+        self.min_severity
+            .is_none_or(|min_severity| diagnostic.severity >= min_severity)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("`{flag}` requires an argument"))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{value}` for `{flag}`"))
+}
+
+fn parse_severity(level: &str) -> Result<ir::Severity, String> {
+    match level {
+        "error" => Ok(ir::Severity::Error),
+        "warning" => Ok(ir::Severity::Warning),
+        "note" => Ok(ir::Severity::Note),
+        _ => Err(format!("unknown `--min-severity` level `{level}`")),
+    }
+}
+
+fn parse_backend(engine: &str) -> Result<Backend, String> {
+    match engine {
+        "ast" => Ok(Backend::Ast),
+        "vm" => Ok(Backend::Vm),
+        _ => Err(format!("unknown `--backend` engine `{engine}`")),
+    }
+}
+
+impl Emit {
+    fn parse(what: &str) -> Result<Self, String> {
+        match what {
+            "tokens" => Ok(Self::Tokens),
+            "types" => Ok(Self::Types),
+            "call-depth" => Ok(Self::CallDepth),
+            "bytecode" => Ok(Self::Bytecode),
+            "rust" => Ok(Self::Rust),
+            "wat" => Ok(Self::Wat),
+            "ast" => Ok(Self::Ast),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown `--emit` kind `{what}`")),
+        }
+    }
+}
+
+/// The name of standard input in diagnostics.
+const STDIN_NAME: &str = "<stdin>";
+
+/// `banana fmt [--check] <files>`: formats the files in place, or with
+/// `--check`, lists those which aren't formatted. Standard input is
+/// formatted to standard output. Returns the exit code: 1 if a file can't
+/// be formatted, or isn't formatted with `--check`.
+fn format_files(args: impl IntoIterator<Item = String>) -> std::io::Result<i32> {
+    let mut check = false;
+    let mut files = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() && !std::io::stdin().is_terminal() {
+        files.push("-".to_string());
+    }
+    let mut db = db::Database::default();
+    let mut code = 0;
+    for filename in &files {
+        let (name, loaded) = match filename.as_str() {
+            "-" => (STDIN_NAME, db.load_stdin()),
+            _ => (filename.as_str(), db.load_source(Path::new(filename))),
+        };
+        let source = match loaded {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("error: cannot read `{name}`: {err}");
+                code = 1;
+                continue;
+            }
+        };
+        let formatted = match format::format_checked(&db, source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                eprintln!("error: cannot format `{name}`: {err}");
+                code = 1;
+                continue;
+            }
+        };
+        if filename == "-" && !check {
+            print!("{formatted}");
+        } else if formatted != *source.text(&db) {
+            if check {
+                println!("{name}");
+                code = 1;
+            } else {
+                std::fs::write(filename, formatted)?;
+            }
+        }
+    }
+    Ok(code)
+}
+
+/// The stack size of the thread the compiler runs on. Evaluation recurses
+/// for each nested call and expression, and a debug build needs about 11
+/// KiB of stack per call of even a small function, so the default
+/// `--max-depth` of 1000 would overflow the usual 8 MiB before the limit
+/// is reached.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
 pub fn main() -> std::io::Result<()> {
-    let mut db = db::Database::default().enable_logging();
-    let source_program = SourceProgram::new(&db, String::new());
-    for filename in std::env::args().skip(1) {
-        let mut input = String::new();
-        File::open(filename)?.read_to_string(&mut input)?;
-        source_program.set_text(&mut db).to(input);
-        compile::compile(&db, source_program);
-        let diagnostics = compile::compile::accumulated::<Diagnostics>(&db, source_program);
-        eprintln!("{diagnostics:?}");
-        eprintln!("{:#?}", db.take_logs());
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)?
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn run() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "fmt").is_some() {
+        std::process::exit(format_files(args)?);
+    }
+    let mut options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {message}");
+            std::process::exit(2);
+        }
+    };
+    if options.files.is_empty() && !options.repl && !std::io::stdin().is_terminal() {
+        options.files.push("-".to_string());
+    }
+    if options.repl || options.files.is_empty() {
+        return repl::run();
+    }
+    let color = render::use_color(
+        options.no_color,
+        std::env::var("NO_COLOR").ok().as_deref(),
+        std::io::stderr().is_terminal(),
+    );
+    let mut timer = timing::Timer::system();
+    let mut builder = db::Database::builder()
+        .logging(options.verbose)
+        .on_event(timer.event_counter())
+        .metrics(options.stats);
+    if let Some(bytes) = options.max_file_size {
+        builder = builder.max_file_size(bytes);
+    }
+    let mut db = builder.build();
+    let mut files = vec![];
+    // A file which can't be read is reported, and the others are still
+    // compiled, but not run.
+    let mut unreadable = false;
+    for filename in &options.files {
+        let (filename, loaded) = match filename.as_str() {
+            "-" => (STDIN_NAME, db.load_stdin()),
+            _ => (filename.as_str(), db.load_source(Path::new(filename))),
+        };
+        let source_program = match loaded {
+            Ok(source_program) => source_program,
+            Err(err) => {
+                let message = format!("cannot read `{filename}`: {err}");
+                let diagnostic = ir::Diagnostic::new(0, 0, message);
+                eprint!(
+                    "{}",
+                    render::render_diagnostic_colored(&db, "", &diagnostic, color)
+                );
+                unreadable = true;
+                continue;
+            }
+        };
+        let input = source_program.text(&db);
+        if let Some(Emit::Tokens) = options.emit {
+            print!("{}", lexer::dump_tokens(input));
+            continue;
+        }
+        if let Some((arena, expression)) = parser::parse_bare_expression(&db, input) {
+            if options.check {
+                continue;
+            }
+            match eval::eval_bare_expression(&db, &arena, expression) {
+                Ok(value) => println!("{}", eval::format_number(value)),
+                Err(diagnostic) => {
+                    eprint!(
+                        "{}",
+                        render::render_diagnostic_colored(&db, input, &diagnostic, color)
+                    )
+                }
+            }
+            continue;
+        }
+        files.push((PathBuf::from(filename), source_program));
+    }
+    let Some(&(_, main_source)) = files.first() else {
+        return Ok(());
+    };
+    // The files are compiled together, so that each can call the functions
+    // defined in the others.
+    let workspace = workspace::Workspace::new(&db, files);
+    let program = timer.time("parse", || workspace::workspace_program(&db, workspace));
+    // A missing or unreadable cache is the same as an empty one.
+    let previous = if options.cache {
+        std::fs::read_to_string(cache::CACHE_FILE)
+            .ok()
+            .and_then(|text| cache::CheckCache::parse(&text))
+            .unwrap_or_default()
+    } else {
+        cache::CheckCache::default()
+    };
+    let mut check = cache::CachedCheck::new(&previous);
+    let mut check_function = |function, program| {
+        if options.cache {
+            check.check(&db, function, program)
+        } else {
+            type_check::type_check_function::accumulated::<Diagnostics>(&db, function, program)
+        }
+    };
+    // Programs are only run if all the files could be read, and not when
+    // emitting code or other output instead.
+    let mode = if options.check || options.emit.is_some() || unreadable {
+        CompileMode::Check
+    } else {
+        CompileMode::Run(options.eval, options.backend)
+    };
+    if options.time {
+        // The phases are timed one at a time, before compiling the
+        // workspace reuses their results.
+        timer.time("type check", || {
+            workspace::workspace_diagnostics_with(&db, workspace, &mut check_function)
+        });
+        // Nothing is compiled to be run with `--check`.
+        if !options.check {
+            timer.time("compile", || {
+                bytecode::compile_program_bytecode(&db, program)
+            });
+        }
+        eprint!("{}", timer.take_report());
+    }
+    let result = compile::compile_workspace(&db, workspace, mode, check_function);
+    let mut diagnostics = result.diagnostics;
+    // The diagnostics of the whole program, and runtime errors, don't say
+    // which file they're in. Like those in included files, they're shown
+    // against the first file.
+    let mut program_diagnostics = vec![];
+    let max_call_depth = options
+        .max_call_depth
+        .unwrap_or(call_graph::DEFAULT_MAX_CALL_DEPTH);
+    program_diagnostics.extend(call_graph::check_call_depth::accumulated::<Diagnostics>(
+        &db,
+        program,
+        max_call_depth,
+    ));
+    if options.lint {
+        lint::lint_program(&db, program);
+        program_diagnostics.extend(lint::lint_program::accumulated::<Diagnostics>(&db, program));
+    }
+    if options.pedantic {
+        program_diagnostics.extend(lint::pedantic_program::accumulated::<Diagnostics>(
+            &db, program,
+        ));
+    }
+    diagnostics.extend(
+        program_diagnostics
+            .into_iter()
+            .map(|diagnostic| (main_source, diagnostic)),
+    );
+    // Like the rendering, the exit code only depends on the diagnostics
+    // shown. Errors are never hidden.
+    diagnostics.retain(|(_, diagnostic)| options.shows(diagnostic));
+    let success = !unreadable
+        && !diagnostics
+            .iter()
+            .any(|(_, diagnostic)| diagnostic.is_error());
+    // Code is only generated for a program which compiled without errors.
+    let generates_code = matches!(options.emit, Some(Emit::Rust | Emit::Wat));
+    // Only a successful compile is cached.
+    if options.cache && success {
+        std::fs::write(cache::CACHE_FILE, check.next.to_text())?;
+    }
+    let text = main_source.text(&db);
+    let mut error_limit = render::ErrorLimit::new(options.max_errors);
+    for (source, diagnostic) in &diagnostics {
+        if error_limit.admit(diagnostic) {
+            eprint!(
+                "{}",
+                render::render_diagnostic_colored(&db, source.text(&db), diagnostic, color)
+            );
+        }
+    }
+    if let Some(lines) = &result.output {
+        let runtime_diagnostics = options.backend.diagnostics(&db, program, options.eval);
+        // Flush each line before the next diagnostic, so that they
+        // appear in order when stdout and stderr go to the same place.
+        for event in eval::interleave(lines, &runtime_diagnostics) {
+            match event {
+                eval::OutputEvent::Print(line) => {
+                    println!("{line}");
+                    std::io::stdout().flush()?;
+                }
+                eval::OutputEvent::Diagnostic(diagnostic) => {
+                    if options.shows(diagnostic) && error_limit.admit(diagnostic) {
+                        eprint!(
+                            "{}",
+                            render::render_diagnostic_colored(&db, text, diagnostic, color)
+                        );
+                    }
+                }
+            }
+        }
+        if options.stats {
+            let output = options.backend.run(&db, program, options.eval);
+            eprint!("{}", output.stats);
+            if let Some(time) = result.run_time {
+                eprintln!("time: {time:?}");
+            }
+        }
+    }
+    if let Some(summary) = error_limit.summary() {
+        eprint!("{summary}");
+    }
+    if options.stats {
+        eprint!("{}", db.metrics());
+    }
+    match options.emit {
+        Some(Emit::Types) => {
+            for function in program.functions(&db) {
+                let name = function.name(&db).text(&db);
+                for (span, ty) in type_check::expression_types(&db, *function, program) {
+                    println!("{name} {}..{}: {ty}", span.start, span.end);
+                }
+            }
+        }
+        Some(Emit::CallDepth) => {
+            for (function, depth) in call_graph::call_depths(&db, program) {
+                println!("{}: {depth}", function.name(&db).text(&db));
+            }
+        }
+        Some(Emit::Bytecode) => print!("{}", bytecode::disassemble_program(&db, program)),
+        Some(Emit::Rust) if success => print!("{}", codegen::rust::emit_program(&db, program)),
+        Some(Emit::Wat) if success => print!("{}", codegen::wat::emit_program(&db, program)),
+        Some(Emit::Rust | Emit::Wat) => {
+            eprintln!("error: no code is generated for a program with errors")
+        }
+        Some(Emit::Ast) => print!("{}", ir::fmt_ast(&db, &program)),
+        Some(Emit::Json) => println!("{}", json_ast::program_json(&db, program)),
+        Some(Emit::Tokens) | None => {}
+    }
+    if options.verbose {
+        for event in db.take_logs().events {
+            eprintln!("{event}");
+        }
+    }
+    if (options.check || generates_code) && !success {
+        std::process::exit(1);
     }
     Ok(())
 }
@@ -1,15 +1,24 @@
 #![allow(dead_code)]
 
+use std::ops::Range;
+
+use crate::db::LogEvent;
 use crate::ir::{DefId, DefIdData, Visit, Visitor};
+#[cfg(test)]
 use salsa::debug::DebugWithDb;
 
 use crate::ir::{
-    Diagnostic, Diagnostics, Expression, ExpressionData, Function, FunctionId, Op, Program,
-    SourceProgram, Span, Statement, StatementData, VariableId,
+    CallArg, Diagnostic, Diagnostics, ExprArena, ExprId, Expression, ExpressionData, Function,
+    FunctionData, FunctionId, InternedArena, ItemText, Program, SourceProgram, Span, Statement,
+    StatementData, VariableId,
 };
+use crate::lexer::{tokenize, TokenKind};
+use crate::raw::{RawExpression, RawExpressionData, RawStatement, RawStatementData};
 
 lalrpop_mod!(grammar);
 
+use lalrpop_util::lexer::Token;
+
 struct RewriteSpans<'a> {
     db: &'a dyn crate::Db,
     start_offset: usize,
@@ -19,55 +28,536 @@ struct RewriteSpans<'a> {
 impl<'a> Visitor for RewriteSpans<'a> {
     fn visit_span(&mut self, span: &mut Span) {
         span.id = self.def_id;
-        span.start -= self.start_offset;
-        span.end -= self.start_offset;
+        // Spans in a function are never before its start, but a bad span
+        // shouldn't bring down the parser.
+        span.start = span.start.saturating_sub(self.start_offset);
+        span.end = span.end.saturating_sub(self.start_offset);
     }
 }
 
 // ANCHOR: parse_statements
-#[salsa::tracked]
+/// Parses a file and the files it includes. Never panics, whatever the
+/// input: problems are reported as diagnostics.
+///
+/// The results for every file are kept unless a limit is set with
+/// `DatabaseBuilder::parse_lru_capacity`.
+#[salsa::tracked(lru = 0)]
 pub fn parse_statements(db: &dyn crate::Db, source: SourceProgram) -> Program {
-    // Get the source text from the database
+    let file = parse_file(db, source);
+    let mut functions = file.functions;
+    let mut included = vec![source];
+    include_files(
+        db,
+        &file.includes,
+        &mut vec![source],
+        &mut included,
+        &mut functions,
+    );
+    Program::new(db, functions, file.statements)
+}
+// ANCHOR_END: parse_statements
+
+/// An `include "<path>";` statement.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Include {
+    pub span: Span,
+    pub path: String,
+}
+
+struct ParsedFile {
+    functions: Vec<Function>,
+    statements: Vec<Statement>,
+    includes: Vec<Include>,
+}
+
+/// Moves spans in an item to where the item is in the file.
+struct ShiftSpans {
+    offset: usize,
+}
+
+impl Visitor for ShiftSpans {
+    fn visit_span(&mut self, span: &mut Span) {
+        span.start += self.offset;
+        span.end += self.offset;
+    }
+}
+
+/// Parses a file item by item, so that editing one function only parses
+/// that function again.
+fn parse_file(db: &dyn crate::Db, source: SourceProgram) -> ParsedFile {
+    let mut file = ParsedFile {
+        functions: vec![],
+        statements: vec![],
+        includes: vec![],
+    };
+    for item in split_into_items(db, source) {
+        if let Some(function) = parse_item(db, item.text) {
+            // Spans in the function are relative to its start, so only the
+            // start needs moving.
+            file.functions.push(Function::new(
+                db,
+                function.name(db),
+                function.data(db).clone(),
+                item.start + function.start(db),
+            ));
+            continue;
+        }
+        let Some((statements, includes)) = parse_item_statements(db, item.text) else {
+            // Parsed again for the error, which is positioned in the whole
+            // text.
+            if let Err(err) = grammar::ProgramParser::new().parse(item.text.text(db)) {
+                Diagnostics::push(
+                    db,
+                    parse_error_diagnostic(
+                        source.text(db),
+                        err.map_location(|location| location + item.start),
+                    ),
+                );
+            }
+            continue;
+        };
+        let mut shift = ShiftSpans { offset: item.start };
+        for statement in statements {
+            let mut statement = statement.clone();
+            statement.traverse(db, &mut shift);
+            file.statements.push(statement);
+        }
+        for include in includes {
+            let mut include = include.clone();
+            include.span.traverse(db, &mut shift);
+            file.includes.push(include);
+        }
+    }
+    file
+}
+
+/// The statements and includes of an item which isn't a function
+/// definition, with spans relative to the item, or `None` if it doesn't
+/// parse. Like `parse_item`, this only runs again when the item's text
+/// changes, rather than on every edit of the file.
+#[salsa::tracked(return_ref)]
+pub fn parse_item_statements(
+    db: &dyn crate::Db,
+    item: ItemText,
+) -> Option<(Vec<Statement>, Vec<Include>)> {
+    let mut statements = vec![];
+    let mut includes = vec![];
+    // Spans are only moved to where the item is in the file later.
+    let mut rewrite = RewriteSpans {
+        db,
+        start_offset: 0,
+        def_id: DefId::new(db, DefIdData::File(item.file(db))),
+    };
+    for statement in grammar::ProgramParser::new().parse(item.text(db)).ok()? {
+        let span = lower_span(db, statement.span);
+        match statement.data {
+            // Parsed by `parse_item`.
+            RawStatementData::Function { .. } => {}
+            RawStatementData::Print(expression) => {
+                let mut arena = ExprArena::default();
+                let expression = lower_expression(db, &mut arena, expression);
+                let mut statement = Statement::new(span, arena, StatementData::Print(expression));
+                statement.traverse(db, &mut rewrite);
+                statements.push(statement)
+            }
+            RawStatementData::Include(path) => {
+                let mut span = span;
+                span.traverse(db, &mut rewrite);
+                includes.push(Include { span, path })
+            }
+        }
+    }
+    Some((statements, includes))
+}
+
+/// The items of `source` which aren't function definitions: statements and
+/// includes, or items which don't parse.
+pub fn statement_items(db: &dyn crate::Db, source: SourceProgram) -> Vec<Item> {
+    split_into_items(db, source)
+        .iter()
+        .filter(|item| parse_item(db, item.text).is_none())
+        .copied()
+        .collect()
+}
+
+/// Counts the expressions it visits.
+#[derive(Default)]
+struct CountExpressions {
+    count: usize,
+}
+
+impl Visitor for CountExpressions {
+    fn visit_expr(&mut self, _: ExprId, _: &mut Expression) {
+        self.count += 1;
+    }
+}
+
+/// A top-level item of a file: a function, statement or include.
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub struct Item {
+    pub text: ItemText,
+    /// Offset of the item in the file.
+    pub start: usize,
+}
+
+/// Splits a file into its top-level items without parsing it: each item
+/// ends with a `;` outside parentheses. The whitespace and comments between
+/// items belong to none of them, so editing those only moves the items.
+#[salsa::tracked(return_ref)]
+pub fn split_into_items(db: &dyn crate::Db, source: SourceProgram) -> Vec<Item> {
     let source_text = source.text(db);
+    let mut items = vec![];
+    let item = |start: usize, end: usize| Item {
+        text: ItemText::new(db, source, source_text[start..end].to_string()),
+        start,
+    };
+    let mut start = None;
+    let mut end = 0;
+    let mut depth = 0usize;
+    for token in tokenize(source_text) {
+        let item_start = *start.get_or_insert(token.start);
+        end = token.end;
+        match token.kind {
+            TokenKind::LParen => depth += 1,
+            TokenKind::RParen => depth = depth.saturating_sub(1),
+            TokenKind::Semicolon if depth == 0 => {
+                items.push(item(item_start, end));
+                start = None;
+            }
+            TokenKind::Number => check_number_precision(db, token.text, token.start),
+            _ => {}
+        }
+    }
+    // A last item without a `;`, which won't parse.
+    if let Some(start) = start {
+        items.push(item(start, end));
+    }
+    items
+}
 
-    match grammar::ProgramParser::new().parse(db, &source_text) {
-        Ok(stmts) => Program::new(
+/// Warns about a number literal with more digits than an `f64` holds, e.g.
+/// `9007199254740993` (2^53 + 1), which is read as `9007199254740992`. A
+/// literal is exact if printing the number gives it back, up to leading and
+/// trailing zeros; `0.1` is, though it isn't exactly one tenth.
+fn check_number_precision(db: &dyn crate::Db, literal: &str, start: usize) {
+    let Ok(value) = literal.parse::<f64>() else {
+        return;
+    };
+    let (integer, fraction) = literal.split_once('.').unwrap_or((literal, ""));
+    let integer = match integer.trim_start_matches('0') {
+        "" => "0",
+        integer => integer,
+    };
+    let normalized = match fraction.trim_end_matches('0') {
+        "" => integer.to_string(),
+        fraction => format!("{integer}.{fraction}"),
+    };
+    if value.to_string() != normalized {
+        Diagnostics::push(
             db,
-            stmts
-                .into_iter()
-                .flat_map(|x| match x.data {
-                    StatementData::Function { name, mut data } => {
-                        data.traverse(
-                            db,
-                            &mut RewriteSpans {
-                                db,
-                                start_offset: x.span.start,
-                                def_id: DefId::new(db, DefIdData::Function(name)),
-                            },
-                        );
+            Diagnostic::warning(
+                start,
+                start + literal.len(),
+                format!("the literal `{literal}` can't be represented exactly; it is `{value}`"),
+            ),
+        );
+    }
+}
 
-                        eprintln!("{} {:#?}", name.text(db), data);
+/// Parses a single item, if it is a function definition. Spans in the
+/// function are relative to its start, which is relative to the item.
+#[salsa::tracked]
+pub fn parse_item(db: &dyn crate::Db, item: ItemText) -> Option<Function> {
+    let mut statements = grammar::ProgramParser::new().parse(item.text(db)).ok()?;
+    if statements.len() != 1 {
+        return None;
+    }
+    let statement = statements.pop()?;
+    let RawStatementData::Function {
+        name,
+        name_span,
+        args,
+        arg_spans,
+        body,
+        allow,
+    } = statement.data
+    else {
+        return None;
+    };
+    let start = statement.span.start;
+    let name = FunctionId::new(db, name);
+    // The spans are rewritten before the arena is interned.
+    let mut name_span = lower_span(db, name_span);
+    let mut rewrite = RewriteSpans {
+        db,
+        start_offset: start,
+        def_id: DefId::new(db, DefIdData::Function(item.file(db), name)),
+    };
+    name_span.traverse(db, &mut rewrite);
+    let mut arg_spans: Vec<_> = arg_spans
+        .into_iter()
+        .map(|span| lower_span(db, span))
+        .collect();
+    for span in &mut arg_spans {
+        span.traverse(db, &mut rewrite);
+    }
+    let mut arena = ExprArena::default();
+    let body = lower_expression(db, &mut arena, body);
+    arena.traverse(db, &mut rewrite);
+
+    let mut counter = CountExpressions::default();
+    arena.traverse(db, &mut counter);
+    db.log(LogEvent::parsed_function(name.text(db), counter.count));
 
-                        Some(Function::new(db, name, data))
-                    }
-                    _ => None,
+    let data = FunctionData::new(
+        name_span,
+        args.into_iter()
+            .map(|arg| VariableId::new(db, arg))
+            .collect(),
+        arg_spans,
+        InternedArena::new(db, arena),
+        body,
+        allow,
+    );
+
+    Some(Function::new(db, name, data, start))
+}
+
+fn lower_span(db: &dyn crate::Db, span: Range<usize>) -> Span {
+    Span::new(DefId::unknown(db), span.start, span.end)
+}
+
+/// Converts an expression from the grammar into the IR, adding it to
+/// `arena` after its operands and interning its names.
+fn lower_expression(
+    db: &dyn crate::Db,
+    arena: &mut ExprArena,
+    expression: RawExpression,
+) -> ExprId {
+    let mut lower = |expression| lower_expression(db, arena, expression);
+    let data = match expression.data {
+        RawExpressionData::Op(left, op, right) => {
+            ExpressionData::Op(lower(*left), op, lower(*right))
+        }
+        RawExpressionData::Neg(operand) => ExpressionData::Neg(lower(*operand)),
+        RawExpressionData::Abs(operand) => ExpressionData::Abs(lower(*operand)),
+        RawExpressionData::Number(n) => ExpressionData::Number(n.into()),
+        RawExpressionData::Bool(b) => ExpressionData::Bool(b),
+        RawExpressionData::Variable(v) => ExpressionData::Variable(VariableId::new(db, v)),
+        RawExpressionData::Call(f, args) => {
+            let f = FunctionId::new(db, f);
+            let args = args
+                .into_iter()
+                .map(|arg| CallArg {
+                    name: arg.name.map(|name| VariableId::new(db, name)),
+                    value: lower(arg.value),
                 })
-                .collect::<Vec<_>>(),
-        ),
-        Err(err) => {
+                .collect();
+            ExpressionData::Call(f, args)
+        }
+        RawExpressionData::Lambda(params, body, args) => {
+            let params = params
+                .into_iter()
+                .map(|param| VariableId::new(db, param))
+                .collect();
+            let body = lower(*body);
+            ExpressionData::Lambda(params, body, args.into_iter().map(lower).collect())
+        }
+        RawExpressionData::Let(name, value, body) => {
+            let name = VariableId::new(db, name);
+            ExpressionData::Let(name, lower(*value), lower(*body))
+        }
+        RawExpressionData::Print(operand) => ExpressionData::Print(lower(*operand)),
+        RawExpressionData::Error(message) => ExpressionData::Error(message),
+    };
+    arena.alloc(Expression::new(lower_span(db, expression.span), data))
+}
+
+/// Parses a program without a database, e.g. for tools which only need the
+/// syntax tree.
+pub fn quick_parse(source_text: &str) -> Result<Vec<RawStatement>, ParseError<'_>> {
+    grammar::ProgramParser::new().parse(source_text)
+}
+
+pub type ParseError<'input> = lalrpop_util::ParseError<usize, Token<'input>, &'static str>;
+
+pub fn parse_error_diagnostic(source_text: &str, err: ParseError<'_>) -> Diagnostic {
+    let (start, end) = match &err {
+        ParseError::InvalidToken { location } => {
+            // Cover the whole character, which may be more than one byte.
+            let len = source_text
+                .get(*location..)
+                .and_then(|rest| rest.chars().next())
+                .map_or(0, char::len_utf8);
+            (*location, *location + len)
+        }
+        ParseError::UnrecognizedEOF { location, expected } => {
+            if let Some(diagnostic) = unbalanced_paren(source_text, *location, expected) {
+                return diagnostic;
+            }
+            (*location, *location)
+        }
+        ParseError::UnrecognizedToken {
+            token: (start, _, end),
+            expected,
+        } => {
+            if let Some(diagnostic) = unbalanced_paren(source_text, *start, expected) {
+                return diagnostic;
+            }
+            // Expressions can start with `(`, so this is where an operand
+            // was missing, e.g. the second `+` in `1 + + 2`.
+            if expected.iter().any(|token| token == r#""(""#) {
+                return Diagnostic::new(*start, *end, "expected an operand here".to_string());
+            }
+            (*start, *end)
+        }
+        ParseError::ExtraToken {
+            token: (start, _, end),
+        } => {
+            if let Some(diagnostic) = unbalanced_paren(source_text, *start, &[]) {
+                return diagnostic;
+            }
+            (*start, *end)
+        }
+        ParseError::User { .. } => (0, 0),
+    };
+    Diagnostic::new(start, end, format!("{err}"))
+}
+
+/// Explains a parse error at `location` by an unbalanced parenthesis: a `)`
+/// at `location` without a `(` before it, or a `(` before `location` which
+/// is never closed, when a `)` was `expected`.
+fn unbalanced_paren(source_text: &str, location: usize, expected: &[String]) -> Option<Diagnostic> {
+    let tokens = tokenize(source_text);
+    let (before, after) = tokens.split_at(tokens.partition_point(|token| token.start < location));
+    let mut open = vec![];
+    for token in before {
+        match token.kind {
+            TokenKind::LParen => open.push(token),
+            TokenKind::RParen => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    if let Some(token) = after.first() {
+        if token.kind == TokenKind::RParen && open.is_empty() {
+            return Some(Diagnostic::new(
+                token.start,
+                token.end,
+                "unexpected ')'".to_string(),
+            ));
+        }
+    }
+    let innermost = open.last()?;
+    if !expected.iter().any(|token| token == r#"")""#) {
+        return None;
+    }
+    // The parenthesis may still be closed after the error, e.g. in
+    // `f(x y)`.
+    let mut depth = 1;
+    for token in after {
+        match token.kind {
+            TokenKind::LParen => depth += 1,
+            TokenKind::RParen if depth == 1 => return None,
+            TokenKind::RParen => depth -= 1,
+            _ => {}
+        }
+    }
+    Some(Diagnostic::new(
+        innermost.start,
+        innermost.end,
+        "unmatched '('".to_string(),
+    ))
+}
+
+/// The functions and includes of a file pulled in by `include`. Its other
+/// top-level statements are ignored.
+#[salsa::tracked(return_ref)]
+pub fn parse_included(db: &dyn crate::Db, source: SourceProgram) -> (Vec<Function>, Vec<Include>) {
+    let file = parse_file(db, source);
+    (file.functions, file.includes)
+}
+
+/// Adds the functions of the included files, and of the files they include,
+/// to `functions`. `stack` holds the files currently being included, to
+/// detect cycles, and `included` every file included so far, so that a file
+/// included twice only contributes its functions once.
+fn include_files(
+    db: &dyn crate::Db,
+    includes: &[Include],
+    stack: &mut Vec<SourceProgram>,
+    included: &mut Vec<SourceProgram>,
+    functions: &mut Vec<Function>,
+) {
+    let includer = *stack.last().unwrap();
+    for include in includes {
+        let span = include.span;
+        let path = &include.path;
+        let resolved = db
+            .files()
+            .includes(db)
+            .get(&(includer, path.clone()))
+            .cloned()
+            .unwrap_or_else(|| Err("the file hasn't been loaded".to_string()));
+        let source = match resolved {
+            Ok(source) => source,
+            Err(err) => {
+                Diagnostics::push(
+                    db,
+                    Diagnostic::new(
+                        span.start,
+                        span.end,
+                        format!("cannot include `{path}`: {err}"),
+                    ),
+                );
+                continue;
+            }
+        };
+        if stack.contains(&source) {
             Diagnostics::push(
                 db,
-                Diagnostic {
-                    start: 0,
-                    end: 0,
-                    message: format!("{err}"),
-                },
+                Diagnostic::new(
+                    span.start,
+                    span.end,
+                    format!("including `{path}` creates a cycle"),
+                ),
             );
-            Program::new(db, vec![])
+            continue;
+        }
+        if included.contains(&source) {
+            continue;
         }
+        included.push(source);
+        let (included_functions, nested_includes) = parse_included(db, source);
+        functions.extend(included_functions);
+        stack.push(source);
+        include_files(db, nested_includes, stack, included, functions);
+        stack.pop();
     }
 }
-// ANCHOR_END: parse_statements
+
+/// Parses `source_text` as a single bare expression ("expression mode"), if
+/// it is one. Programs containing statements (which always end with `;`)
+/// don't parse as an expression, so they are never treated as bare
+/// expressions.
+pub fn parse_bare_expression(db: &dyn crate::Db, source_text: &str) -> Option<(ExprArena, ExprId)> {
+    parse_expression(db, source_text).ok()
+}
+
+/// Parses `source_text` as a single expression, with spans relative to it.
+/// Returns the arena of its expressions and the root.
+pub fn parse_expression(
+    db: &dyn crate::Db,
+    source_text: &str,
+) -> Result<(ExprArena, ExprId), Diagnostic> {
+    let expression = grammar::ExprParser::new()
+        .parse(source_text)
+        .map_err(|err| parse_error_diagnostic(source_text, err))?;
+    let mut arena = ExprArena::default();
+    let root = lower_expression(db, &mut arena, expression);
+    Ok((arena, root))
+}
 
 // ANCHOR: parse_string
 /// Create a new database with the given source text and parse the result.
@@ -87,7 +577,56 @@ fn parse_string(source_text: &str) -> String {
     let accumulated = parse_statements::accumulated::<Diagnostics>(&db, source_program);
 
     // Format the result as a string and return it
-    format!("{:#?}", (statements.debug_all(&db), accumulated))
+    let program = DebugProgram {
+        db: &db,
+        program: statements,
+    };
+    format!("{:#?}", (program, accumulated))
+}
+
+/// Formats a program with its spans and names resolved, rather than as
+/// interned ids, so that snapshots don't change with the order things are
+/// interned in.
+#[cfg(test)]
+struct DebugProgram<'a> {
+    db: &'a dyn crate::Db,
+    program: Program,
+}
+
+#[cfg(test)]
+impl std::fmt::Debug for DebugProgram<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct DebugFunction<'a>(&'a dyn crate::Db, Function);
+
+        impl std::fmt::Debug for DebugFunction<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let DebugFunction(db, function) = *self;
+                f.debug_struct("Function")
+                    .field("name", function.name(db).text(db))
+                    .field("start", &function.start(db))
+                    .field("data", &function.data(db).debug(db))
+                    .finish()
+            }
+        }
+
+        let db = self.db;
+        let functions: Vec<_> = self
+            .program
+            .functions(db)
+            .iter()
+            .map(|&function| DebugFunction(db, function))
+            .collect();
+        let statements: Vec<_> = self
+            .program
+            .statements(db)
+            .iter()
+            .map(|statement| statement.debug(db))
+            .collect();
+        f.debug_struct("Program")
+            .field("functions", &functions)
+            .field("statements", &statements)
+            .finish()
+    }
 }
 // ANCHOR_END: parse_string
 
@@ -98,28 +637,16 @@ fn parse_print() {
     let expected = expect_test::expect![[r#"
         (
             Program {
-                [salsa id]: 0,
+                functions: [],
                 statements: [
                     Statement {
-                        span: Span(
-                            Id {
-                                value: 5,
-                            },
-                        ),
+                        span: Span(0..12),
                         data: Print(
                             Expression {
-                                span: Span(
-                                    Id {
-                                        value: 4,
-                                    },
-                                ),
+                                span: Span(6..11),
                                 data: Op(
                                     Expression {
-                                        span: Span(
-                                            Id {
-                                                value: 1,
-                                            },
-                                        ),
+                                        span: Span(6..7),
                                         data: Number(
                                             OrderedFloat(
                                                 1.0,
@@ -128,11 +655,7 @@ fn parse_print() {
                                     },
                                     Add,
                                     Expression {
-                                        span: Span(
-                                            Id {
-                                                value: 3,
-                                            },
-                                        ),
+                                        span: Span(10..11),
                                         data: Number(
                                             OrderedFloat(
                                                 2.0,
@@ -165,62 +688,92 @@ fn parse_example() {
     let expected = expect_test::expect![[r#"
         (
             Program {
-                [salsa id]: 0,
-                statements: [
-                    Statement {
-                        span: Span(
-                            Id {
-                                value: 10,
+                functions: [
+                    Function {
+                        name: "area_rectangle",
+                        start: 13,
+                        data: FunctionData {
+                            name_span: Span(area_rectangle, 3..17),
+                            args: [
+                                "w",
+                                "h",
+                            ],
+                            body: Expression {
+                                span: Span(area_rectangle, 26..31),
+                                data: Op(
+                                    Expression {
+                                        span: Span(area_rectangle, 26..27),
+                                        data: Variable(
+                                            "w",
+                                        ),
+                                    },
+                                    Multiply,
+                                    Expression {
+                                        span: Span(area_rectangle, 30..31),
+                                        data: Variable(
+                                            "h",
+                                        ),
+                                    },
+                                ),
                             },
-                        ),
-                        data: Function(
-                            Function(
-                                Id {
-                                    value: 1,
-                                },
-                            ),
-                        ),
+                            allow: [],
+                        },
                     },
-                    Statement {
-                        span: Span(
-                            Id {
-                                value: 22,
+                    Function {
+                        name: "area_circle",
+                        start: 58,
+                        data: FunctionData {
+                            name_span: Span(area_circle, 3..14),
+                            args: [
+                                "r",
+                            ],
+                            body: Expression {
+                                span: Span(area_circle, 20..32),
+                                data: Op(
+                                    Expression {
+                                        span: Span(area_circle, 20..28),
+                                        data: Op(
+                                            Expression {
+                                                span: Span(area_circle, 20..24),
+                                                data: Number(
+                                                    OrderedFloat(
+                                                        3.14,
+                                                    ),
+                                                ),
+                                            },
+                                            Multiply,
+                                            Expression {
+                                                span: Span(area_circle, 27..28),
+                                                data: Variable(
+                                                    "r",
+                                                ),
+                                            },
+                                        ),
+                                    },
+                                    Multiply,
+                                    Expression {
+                                        span: Span(area_circle, 31..32),
+                                        data: Variable(
+                                            "r",
+                                        ),
+                                    },
+                                ),
                             },
-                        ),
-                        data: Function(
-                            Function(
-                                Id {
-                                    value: 2,
-                                },
-                            ),
-                        ),
+                            allow: [],
+                        },
                     },
+                ],
+                statements: [
                     Statement {
-                        span: Span(
-                            Id {
-                                value: 29,
-                            },
-                        ),
+                        span: Span(104..131),
                         data: Print(
                             Expression {
-                                span: Span(
-                                    Id {
-                                        value: 28,
-                                    },
-                                ),
+                                span: Span(110..130),
                                 data: Call(
-                                    FunctionId(
-                                        Id {
-                                            value: 1,
-                                        },
-                                    ),
+                                    "area_rectangle",
                                     [
                                         Expression {
-                                            span: Span(
-                                                Id {
-                                                    value: 24,
-                                                },
-                                            ),
+                                            span: Span(125..126),
                                             data: Number(
                                                 OrderedFloat(
                                                     3.0,
@@ -228,11 +781,7 @@ fn parse_example() {
                                             ),
                                         },
                                         Expression {
-                                            span: Span(
-                                                Id {
-                                                    value: 26,
-                                                },
-                                            ),
+                                            span: Span(128..129),
                                             data: Number(
                                                 OrderedFloat(
                                                     4.0,
@@ -245,31 +794,15 @@ fn parse_example() {
                         ),
                     },
                     Statement {
-                        span: Span(
-                            Id {
-                                value: 34,
-                            },
-                        ),
+                        span: Span(144..165),
                         data: Print(
                             Expression {
-                                span: Span(
-                                    Id {
-                                        value: 33,
-                                    },
-                                ),
+                                span: Span(150..164),
                                 data: Call(
-                                    FunctionId(
-                                        Id {
-                                            value: 2,
-                                        },
-                                    ),
+                                    "area_circle",
                                     [
                                         Expression {
-                                            span: Span(
-                                                Id {
-                                                    value: 31,
-                                                },
-                                            ),
+                                            span: Span(162..163),
                                             data: Number(
                                                 OrderedFloat(
                                                     1.0,
@@ -282,25 +815,13 @@ fn parse_example() {
                         ),
                     },
                     Statement {
-                        span: Span(
-                            Id {
-                                value: 39,
-                            },
-                        ),
+                        span: Span(178..191),
                         data: Print(
                             Expression {
-                                span: Span(
-                                    Id {
-                                        value: 38,
-                                    },
-                                ),
+                                span: Span(184..190),
                                 data: Op(
                                     Expression {
-                                        span: Span(
-                                            Id {
-                                                value: 35,
-                                            },
-                                        ),
+                                        span: Span(184..186),
                                         data: Number(
                                             OrderedFloat(
                                                 11.0,
@@ -309,11 +830,7 @@ fn parse_example() {
                                     },
                                     Multiply,
                                     Expression {
-                                        span: Span(
-                                            Id {
-                                                value: 37,
-                                            },
-                                        ),
+                                        span: Span(189..190),
                                         data: Number(
                                             OrderedFloat(
                                                 2.0,
@@ -339,14 +856,16 @@ fn parse_error() {
     let expected = expect_test::expect![[r#"
         (
             Program {
-                [salsa id]: 0,
+                functions: [],
                 statements: [],
             },
             [
                 Diagnostic {
                     start: 10,
                     end: 11,
-                    message: "unexpected character",
+                    message: "expected an operand here",
+                    severity: Error,
+                    related: [],
                 },
             ],
         )"#]];
@@ -361,35 +880,19 @@ fn parse_precedence() {
     let expected = expect_test::expect![[r#"
         (
             Program {
-                [salsa id]: 0,
+                functions: [],
                 statements: [
                     Statement {
-                        span: Span(
-                            Id {
-                                value: 11,
-                            },
-                        ),
+                        span: Span(0..20),
                         data: Print(
                             Expression {
-                                span: Span(
-                                    Id {
-                                        value: 10,
-                                    },
-                                ),
+                                span: Span(6..19),
                                 data: Op(
                                     Expression {
-                                        span: Span(
-                                            Id {
-                                                value: 7,
-                                            },
-                                        ),
+                                        span: Span(6..15),
                                         data: Op(
                                             Expression {
-                                                span: Span(
-                                                    Id {
-                                                        value: 1,
-                                                    },
-                                                ),
+                                                span: Span(6..7),
                                                 data: Number(
                                                     OrderedFloat(
                                                         1.0,
@@ -398,18 +901,10 @@ fn parse_precedence() {
                                             },
                                             Add,
                                             Expression {
-                                                span: Span(
-                                                    Id {
-                                                        value: 6,
-                                                    },
-                                                ),
+                                                span: Span(10..15),
                                                 data: Op(
                                                     Expression {
-                                                        span: Span(
-                                                            Id {
-                                                                value: 3,
-                                                            },
-                                                        ),
+                                                        span: Span(10..11),
                                                         data: Number(
                                                             OrderedFloat(
                                                                 2.0,
@@ -418,11 +913,7 @@ fn parse_precedence() {
                                                     },
                                                     Multiply,
                                                     Expression {
-                                                        span: Span(
-                                                            Id {
-                                                                value: 5,
-                                                            },
-                                                        ),
+                                                        span: Span(14..15),
                                                         data: Number(
                                                             OrderedFloat(
                                                                 3.0,
@@ -435,11 +926,7 @@ fn parse_precedence() {
                                     },
                                     Add,
                                     Expression {
-                                        span: Span(
-                                            Id {
-                                                value: 9,
-                                            },
-                                        ),
+                                        span: Span(18..19),
                                         data: Number(
                                             OrderedFloat(
                                                 4.0,
@@ -456,3 +943,509 @@ fn parse_precedence() {
         )"#]];
     expected.assert_eq(&actual);
 }
+
+/// Renders the expression tree as an s-expression, to compare the shape of
+/// parses without spans.
+#[cfg(test)]
+fn sexp(db: &dyn crate::Db, arena: &ExprArena, id: ExprId) -> String {
+    let sexp = |id| sexp(db, arena, id);
+    match &arena[id].data {
+        ExpressionData::Op(l, op, r) => format!("({op:?} {} {})", sexp(*l), sexp(*r)),
+        ExpressionData::Neg(x) => format!("(Neg {})", sexp(*x)),
+        ExpressionData::Abs(x) => format!("(Abs {})", sexp(*x)),
+        ExpressionData::Number(n) => n.to_string(),
+        ExpressionData::Bool(b) => b.to_string(),
+        ExpressionData::Variable(v) => v.text(db).clone(),
+        ExpressionData::Call(f, args) => {
+            let args: Vec<_> = args
+                .iter()
+                .map(|arg| match arg.name {
+                    Some(name) => format!("(= {} {})", name.text(db), sexp(arg.value)),
+                    None => sexp(arg.value),
+                })
+                .collect();
+            format!("({} {})", f.text(db), args.join(" "))
+        }
+        ExpressionData::Lambda(params, body, args) => {
+            let params: Vec<_> = params.iter().map(|param| param.text(db).as_str()).collect();
+            let args: Vec<_> = args.iter().map(|&arg| sexp(arg)).collect();
+            format!(
+                "(Lambda ({}) {} {})",
+                params.join(" "),
+                sexp(*body),
+                args.join(" ")
+            )
+        }
+        ExpressionData::Let(name, value, body) => {
+            format!("(Let {} {} {})", name.text(db), sexp(*value), sexp(*body))
+        }
+        ExpressionData::Print(x) => format!("(Print {})", sexp(*x)),
+        ExpressionData::Error(message) => format!("(error {message:?})"),
+    }
+}
+
+#[cfg(test)]
+fn parse_expression_sexp(source_text: &str) -> String {
+    let db = crate::db::Database::default();
+    let (arena, expression) = parse_bare_expression(&db, source_text).expect("parse error");
+    sexp(&db, &arena, expression)
+}
+
+#[test]
+fn parse_unary_minus() {
+    assert_eq!(parse_expression_sexp("-3"), "(Neg 3)");
+    assert_eq!(parse_expression_sexp("3--2"), "(Subtract 3 (Neg 2))");
+    assert_eq!(parse_expression_sexp("3 - -2"), "(Subtract 3 (Neg 2))");
+    assert_eq!(
+        parse_expression_sexp("-3 * -x - 1"),
+        "(Subtract (Multiply (Neg 3) (Neg x)) 1)"
+    );
+}
+
+#[test]
+fn parse_abs() {
+    assert_eq!(parse_expression_sexp("|-3|"), "(Abs (Neg 3))");
+    assert_eq!(parse_expression_sexp("|a| + |b|"), "(Add (Abs a) (Abs b))");
+    assert_eq!(
+        parse_expression_sexp("||a| - 1| * -|f(b, |c|)|"),
+        "(Multiply (Abs (Subtract (Abs a) 1)) (Neg (Abs (f b (Abs c)))))"
+    );
+    assert_eq!(parse_diagnostics("print |1;").len(), 1);
+    assert_eq!(parse_diagnostics("print 1 | 2;").len(), 1);
+}
+
+#[test]
+fn parse_bool_literals() {
+    assert_eq!(parse_expression_sexp("true"), "true");
+    assert_eq!(parse_expression_sexp("false"), "false");
+    assert_eq!(
+        parse_expression_sexp("f(true, -false) + trueish"),
+        "(Add (f true (Neg false)) trueish)"
+    );
+    // Keywords, not identifiers.
+    assert_eq!(parse_diagnostics("fn true() = 1;").len(), 1);
+    assert_eq!(parse_diagnostics("fn f(false) = 1;").len(), 1);
+}
+
+#[test]
+fn parse_let() {
+    assert_eq!(
+        parse_expression_sexp("let x = 1 + 2 in let y = x in x * y"),
+        "(Let x (Add 1 2) (Let y x (Multiply x y)))"
+    );
+    assert_eq!(
+        parse_expression_sexp("2 * (let x = 1 in x) - 1"),
+        "(Subtract (Multiply 2 (Let x 1 x)) 1)"
+    );
+    // A `let` operand needs parentheses.
+    assert_eq!(parse_diagnostics("print 2 * let x = 1 in x;").len(), 1);
+}
+
+#[test]
+fn parse_method_call() {
+    let db = crate::db::Database::default();
+    let parse = |source_text| parse_bare_expression(&db, source_text).expect("parse error");
+    let (method, _) = parse("x.sqrt()");
+    let (call, _) = parse("sqrt(x)");
+    assert!(method.eq_ignoring_spans(&call));
+    assert_eq!(parse_expression_sexp("x.pow(2)"), "(pow x 2)");
+    assert_eq!(
+        parse_expression_sexp("-(1 + x).pow(2).sqrt() * 2.sqrt()"),
+        "(Multiply (Neg (sqrt (pow (Add 1 x) 2))) (sqrt 2))"
+    );
+    assert_eq!(parse_diagnostics("print x.sqrt;").len(), 1);
+}
+
+#[test]
+fn parse_named_arguments() {
+    assert_eq!(
+        parse_expression_sexp("area(w = 3, h = 2 * x)"),
+        "(area (= w 3) (= h (Multiply 2 x)))"
+    );
+    assert_eq!(parse_expression_sexp("x.pow(e = 2)"), "(pow x (= e 2))");
+    // Positional arguments after named ones parse, and are reported when
+    // the call is bound.
+    assert_eq!(parse_expression_sexp("f(a = 1, 2)"), "(f (= a 1) 2)");
+    assert_eq!(parse_diagnostics("print f(1 = 2);").len(), 1);
+    assert_eq!(parse_diagnostics("print f(a = );").len(), 1);
+}
+
+#[test]
+fn parse_nested_print() {
+    assert_eq!(parse_expression_sexp("(print 1) + 1"), "(Add (Print 1) 1)");
+    assert_eq!(
+        parse_expression_sexp("print print x * 2"),
+        "(Print (Print (Multiply x 2)))"
+    );
+    assert_eq!(parse_expression_sexp("f(print 1)"), "(f (Print 1))");
+    // The statement's expression takes in the rest of the statement.
+    assert!(parse_diagnostics("print (print 1) + 1;").is_empty());
+    assert_eq!(parse_diagnostics("print 2 * print 1;").len(), 1);
+}
+
+#[test]
+fn parse_lambda() {
+    assert_eq!(
+        parse_expression_sexp(r"(\(x, y) -> x * y)(2, 1 + 2)"),
+        "(Lambda (x y) (Multiply x y) 2 (Add 1 2))"
+    );
+    assert_eq!(
+        parse_expression_sexp(r"1 + (\(x) -> (\(y) -> -y)(x))(3)"),
+        "(Add 1 (Lambda (x) (Lambda (y) (Neg y) x) 3))"
+    );
+    // Lambdas can't be used except by calling them.
+    assert_eq!(parse_diagnostics(r"print \(x) -> x;").len(), 1);
+    assert_eq!(parse_diagnostics(r"print (\(x) -> x);").len(), 1);
+}
+
+#[test]
+fn parse_fractional_literal() {
+    assert_eq!(parse_expression_sexp("3.14 * r"), "(Multiply 3.14 r)");
+}
+
+#[test]
+fn parse_negative_print() {
+    let actual = parse_string("print -3; print 3 - -2;");
+    assert!(actual.ends_with("[],\n)"), "{actual}");
+}
+
+/// A temporary directory of files written by `write_fixture`, removed when
+/// it's dropped.
+#[cfg(test)]
+pub(crate) struct Fixture {
+    dir: std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl std::ops::Deref for Fixture {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.dir
+    }
+}
+
+#[cfg(test)]
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Writes `files` to a fresh temporary directory, replacing `$DIR` in their
+/// contents with the directory's path. File names may include
+/// subdirectories.
+#[cfg(test)]
+pub(crate) fn write_fixture(name: &str, files: &[(&str, &str)]) -> Fixture {
+    let dir = std::env::temp_dir().join(format!("banana-{name}-{}", std::process::id()));
+    for (file_name, contents) in files {
+        let path = dir.join(file_name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let contents = contents.replace("$DIR", dir.to_str().unwrap());
+        std::fs::write(path, contents).unwrap();
+    }
+    Fixture { dir }
+}
+
+/// Parses the program in `path`, returning its function names and the
+/// diagnostic messages, with the fixture directory replaced by `$DIR`.
+#[cfg(test)]
+fn parse_file_fixture(dir: &std::path::Path, path: &str) -> (Vec<String>, Vec<String>) {
+    let mut db = crate::db::Database::default();
+    let source_program = db.load_source(&dir.join(path)).unwrap();
+    let program = parse_statements(&db, source_program);
+    let functions = program
+        .functions(&db)
+        .iter()
+        .map(|function| function.name(&db).text(&db).clone())
+        .collect();
+    let diagnostics = parse_statements::accumulated::<Diagnostics>(&db, source_program)
+        .into_iter()
+        .map(|diagnostic| diagnostic.message.replace(dir.to_str().unwrap(), "$DIR"))
+        .collect();
+    (functions, diagnostics)
+}
+
+#[test]
+fn parse_include() {
+    let dir = write_fixture(
+        "include",
+        &[
+            (
+                "main.banana",
+                r#"include "lib.banana"; include "shapes/shapes.banana"; fn main() = area(2);"#,
+            ),
+            // Relative to the including file, not the current directory.
+            (
+                "shapes/shapes.banana",
+                r#"include "../lib.banana"; fn area(r) = pi() * square(r); print 1;"#,
+            ),
+            ("lib.banana", "fn pi() = 3.14; fn square(x) = x * x;"),
+        ],
+    );
+    let expected = expect_test::expect![[r#"
+        (
+            [
+                "main",
+                "pi",
+                "square",
+                "area",
+            ],
+            [],
+        )
+    "#]];
+    expected.assert_debug_eq(&parse_file_fixture(&dir, "main.banana"));
+}
+
+#[test]
+fn parse_include_errors() {
+    let dir = write_fixture(
+        "include-errors",
+        &[
+            (
+                "main.banana",
+                r#"include "a.banana"; include "$DIR/missing.banana";"#,
+            ),
+            ("a.banana", r#"include "b.banana"; fn a() = 1;"#),
+            ("b.banana", r#"include "a.banana"; fn b() = 2;"#),
+        ],
+    );
+    let expected = expect_test::expect![[r#"
+        (
+            [
+                "a",
+                "b",
+            ],
+            [
+                "including `a.banana` creates a cycle",
+                "cannot include `$DIR/missing.banana`: No such file or directory (os error 2)",
+            ],
+        )
+    "#]];
+    expected.assert_debug_eq(&parse_file_fixture(&dir, "main.banana"));
+}
+
+#[cfg(test)]
+fn parse_diagnostics(source_text: &str) -> Vec<Diagnostic> {
+    let db = crate::db::Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    parse_statements(&db, source_program);
+    parse_statements::accumulated::<Diagnostics>(&db, source_program)
+}
+
+#[test]
+fn parse_imprecise_literal() {
+    let expected = expect_test::expect![[r#"
+        [
+            Diagnostic {
+                start: 6,
+                end: 22,
+                message: "the literal `9007199254740993` can't be represented exactly; it is `9007199254740992`",
+                severity: Warning,
+                related: [],
+            },
+        ]
+    "#]];
+    expected.assert_debug_eq(&parse_diagnostics("print 9007199254740993;"));
+    assert!(parse_diagnostics("print 9007199254740992; print 0.1; print 007.50;").is_empty());
+}
+
+#[test]
+fn parse_missing_operand() {
+    let expected = expect_test::expect![[r#"
+        [
+            Diagnostic {
+                start: 10,
+                end: 11,
+                message: "expected an operand here",
+                severity: Error,
+                related: [],
+            },
+        ]
+    "#]];
+    expected.assert_debug_eq(&parse_diagnostics("print 1 + + 2;"));
+}
+
+#[test]
+fn split_items() {
+    let db = crate::db::Database::default();
+    let source_program = SourceProgram::new(
+        &db,
+        "fn f(x) = g(x;\n  y); // a; b\nprint \"a;b\"; print 1".to_string(),
+    );
+    let items: Vec<_> = split_into_items(&db, source_program)
+        .iter()
+        .map(|item| (item.text.text(&db).as_str(), item.start))
+        .collect();
+    assert_eq!(
+        items,
+        [
+            ("fn f(x) = g(x;\n  y);", 0),
+            ("print \"a;b\";", 29),
+            ("print 1", 42),
+        ]
+    );
+}
+
+#[test]
+fn parse_crlf() {
+    let db = crate::db::Database::default();
+    let parse =
+        |source_text: &str| parse_statements(&db, SourceProgram::new(&db, source_text.to_string()));
+    let lf = "fn f(x) =\n  x * 2; // double\nprint f(1);\nprint error(\"\"\"a\nb\"\"\");\n";
+    let crlf = lf.replace('\n', "\r\n");
+    assert!(crate::ir::program_eq_ignoring_spans(
+        &db,
+        parse(lf),
+        parse(&crlf)
+    ));
+
+    let crlf = "print 1;\r\n// comment\r\nprint 1 + + 2;\r\n";
+    let diagnostics = parse_diagnostics(crlf);
+    assert_eq!(crate::render::line_col(crlf, diagnostics[0].start), (3, 11));
+}
+
+#[cfg(test)]
+fn quick_parse_error(source_text: &str) -> Diagnostic {
+    let err = quick_parse(source_text).unwrap_err();
+    parse_error_diagnostic(source_text, err)
+}
+
+#[test]
+fn parse_unbalanced_parens() {
+    let error = |source_text| {
+        let diagnostic = quick_parse_error(source_text);
+        (diagnostic.message, diagnostic.start..diagnostic.end)
+    };
+    assert_eq!(error("print (1 + 2;"), ("unmatched '('".to_string(), 6..7));
+    assert_eq!(error("print (1 + 2"), ("unmatched '('".to_string(), 6..7));
+    assert_eq!(
+        error("print f((1), g(2;\nprint 3;"),
+        ("unmatched '('".to_string(), 14..15)
+    );
+    assert_eq!(
+        error("print 1 + 2);"),
+        ("unexpected ')'".to_string(), 11..12)
+    );
+    assert_eq!(
+        error("print (1) + 2);"),
+        ("unexpected ')'".to_string(), 13..14)
+    );
+    // Balanced parentheses keep the generic message.
+    assert_ne!(error("fn f(x y) = x;").0, "unmatched '('");
+    assert_ne!(error("print \"(\" + 1);").0, "unexpected ')'");
+}
+
+#[test]
+fn parse_error_builtin() {
+    let expected = expect_test::expect![[r#"(Add 1 (Neg (error "a b")))"#]];
+    expected.assert_eq(&parse_expression_sexp(r#"1 + -error("a b")"#));
+    let expected = expect_test::expect![[r#"
+        [
+            Diagnostic {
+                start: 0,
+                end: 0,
+                message: "only `error` takes a string argument",
+                severity: Error,
+                related: [],
+            },
+        ]
+    "#]];
+    expected.assert_debug_eq(&parse_diagnostics(r#"print f("a");"#));
+}
+
+#[test]
+fn parse_string_literals() {
+    let message = |source_text| match quick_parse(source_text).unwrap().remove(0).data {
+        RawStatementData::Print(RawExpression {
+            data: RawExpressionData::Error(message),
+            ..
+        }) => message,
+        data => panic!("unexpected statement {data:?}"),
+    };
+    assert_eq!(message(r#"print error("a\nb \"c\"");"#), "a\nb \"c\"");
+    assert_eq!(message(r#"print error(r"a\nb\");"#), r"a\nb\");
+    assert!(quick_parse("print error(\"a\nb\");").is_err());
+
+    let source_text = "print 1;\nprint error(\"\"\"first\n  \"second\"\n\"\"\");\nprint 2;";
+    let statements = quick_parse(source_text).unwrap();
+    let RawStatementData::Print(expression) = &statements[1].data else {
+        panic!("expected a print statement");
+    };
+    assert_eq!(
+        expression.data,
+        RawExpressionData::Error("first\n  \"second\"\n".to_string())
+    );
+    assert_eq!(
+        &source_text[expression.span.clone()],
+        "error(\"\"\"first\n  \"second\"\n\"\"\")"
+    );
+    assert_eq!(statements[2].span, 47..55);
+}
+
+#[test]
+fn parse_adversarial_input() {
+    let huge_number = "9".repeat(400);
+    let inputs = [
+        "-",
+        "print -;",
+        &huge_number,
+        &format!("print {huge_number}.{huge_number};"),
+        "(",
+        ")",
+        "print ((1);",
+        "print (1));",
+        "fn f(",
+        "fn f(x) = ;",
+        "fn (x) = 1;",
+        "#[allow(",
+        "#[deny(x)] fn f() = 1;",
+        "include",
+        "include \"",
+        "/*",
+        "é",
+        "print é;",
+        "\0",
+        "",
+    ];
+    for input in inputs {
+        // Nothing to check but that these don't panic.
+        parse_diagnostics(input);
+        crate::lexer::dump_tokens(input);
+    }
+    let expected = expect_test::expect![[r#"
+        [
+            Diagnostic {
+                start: 6,
+                end: 8,
+                message: "Invalid token at 6",
+                severity: Error,
+                related: [],
+            },
+        ]
+    "#]];
+    expected.assert_debug_eq(&parse_diagnostics("print é;"));
+}
+
+#[test]
+fn quick_parse_example() {
+    let statements = quick_parse(
+        "
+            fn area_rectangle(w, h) = w * h;
+            fn area_circle(r) = 3.14 * r * r;
+            print area_rectangle(3, 4);
+            print area_circle(1);
+            print 11 * 2;
+        ",
+    )
+    .unwrap();
+    let names: Vec<_> = statements
+        .iter()
+        .filter_map(|statement| match &statement.data {
+            RawStatementData::Function { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(names, ["area_rectangle", "area_circle"]);
+    assert_eq!(statements.len(), 5);
+}
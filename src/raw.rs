@@ -0,0 +1,120 @@
+//! The syntax tree produced by the grammar, before it is lowered into the
+//! IR. It doesn't depend on the database: names are owned strings and spans
+//! are plain offsets into the source text.
+
+use std::ops::Range;
+
+use crate::ir::Op;
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct RawStatement {
+    pub span: Range<usize>,
+    pub data: RawStatementData,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum RawStatementData {
+    /// Defines `fn <name>(<args>) = <body>`
+    Function {
+        name: String,
+        name_span: Range<usize>,
+        args: Vec<String>,
+        /// The span of each of `args`.
+        arg_spans: Vec<Range<usize>>,
+        body: RawExpression,
+        /// Lints suppressed with `#[allow(...)]`.
+        allow: Vec<String>,
+    },
+    /// Defines `print <expr>`
+    Print(RawExpression),
+    /// Defines `include "<path>"`
+    Include(String),
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct RawExpression {
+    pub span: Range<usize>,
+    pub data: RawExpressionData,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum RawExpressionData {
+    Op(Box<RawExpression>, Op, Box<RawExpression>),
+    Neg(Box<RawExpression>),
+    Abs(Box<RawExpression>),
+    Number(f64),
+    Bool(bool),
+    Variable(String),
+    Call(String, Vec<RawCallArg>),
+    /// `(\(<params>) -> <body>)(<args>)`, a lambda called immediately.
+    Lambda(Vec<String>, Box<RawExpression>, Vec<RawExpression>),
+    /// `let <name> = <value> in <body>`
+    Let(String, Box<RawExpression>, Box<RawExpression>),
+    /// `print <expr>` as an operand, e.g. `(print x) + 1`
+    Print(Box<RawExpression>),
+    /// `error("<message>")`
+    Error(String),
+}
+
+/// `<expr>`, or `<name> = <expr>` for an argument passed by name.
+#[derive(PartialEq, Clone, Debug)]
+pub struct RawCallArg {
+    pub name: Option<String>,
+    pub value: RawExpression,
+}
+
+/// The contents of a `"..."` string literal with its escapes (`\n`, `\r`,
+/// `\t`, `\"` and `\\`) replaced. Other backslashes are kept as they are.
+pub fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some(c @ ('"' | '\\')) => result.push(c),
+            Some(c) => {
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Writes `text` as the contents of a `"..."` string literal, the inverse of
+/// `unescape`.
+pub fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\n' => result += "\\n",
+            '\r' => result += "\\r",
+            '\t' => result += "\\t",
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[test]
+fn escape_round_trip() {
+    assert_eq!(unescape(r#"a\nb\t\"c\" \\ \d"#), "a\nb\t\"c\" \\ \\d");
+    for text in [
+        "plain",
+        "two\r\nlines",
+        "\"quoted\"",
+        r"C:\dir\new",
+        "trailing\\",
+    ] {
+        assert_eq!(unescape(&escape(text)), text);
+    }
+}
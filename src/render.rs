@@ -0,0 +1,347 @@
+use crate::ir::{DefIdData, Diagnostic, Severity, SourceProgram, Span};
+use crate::parser::parse_statements;
+
+/// Renders a diagnostic in `source_text` for the terminal, followed by its
+/// related locations as secondary notes.
+pub fn render_diagnostic(db: &dyn crate::Db, source_text: &str, diagnostic: &Diagnostic) -> String {
+    render_diagnostic_colored(db, source_text, diagnostic, false)
+}
+
+/// `render_diagnostic`, with the severities in color if `color` is set.
+pub fn render_diagnostic_colored(
+    db: &dyn crate::Db,
+    source_text: &str,
+    diagnostic: &Diagnostic,
+    color: bool,
+) -> String {
+    let (severity, code) = match diagnostic.severity {
+        Severity::Note => ("note", NOTE_COLOR),
+        Severity::Warning => ("warning", "33"),
+        Severity::Error => ("error", "31"),
+    };
+    let severity = paint(severity, code, color);
+    let (start, end) = normalize_span(diagnostic.start, diagnostic.end, source_text.len());
+    let mut output = format!("{severity}: {}\n  --> {start}..{end}\n", diagnostic.message);
+    for (span, message) in &diagnostic.related {
+        output += &format!(
+            "{}: {message}\n  --> {}\n",
+            paint("note", NOTE_COLOR, color),
+            render_span(db, *span)
+        );
+    }
+    output
+}
+
+/// The ANSI color code of notes: cyan.
+const NOTE_COLOR: &str = "36";
+
+/// `text` in bold, in the ANSI color `code`, if `color` is set.
+fn paint(text: &str, code: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[1;{code}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Whether to color diagnostics. `--no-color` turns color off; failing
+/// that, so does a non-empty `NO_COLOR` environment variable (see
+/// <https://no-color.org>); otherwise only output to a terminal is colored.
+pub fn use_color(no_color_flag: bool, no_color_env: Option<&str>, is_terminal: bool) -> bool {
+    if no_color_flag || no_color_env.is_some_and(|value| !value.is_empty()) {
+        return false;
+    }
+    is_terminal
+}
+
+/// Where a span is in the text of its file.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ResolvedSpan {
+    pub abs_start: usize,
+    pub abs_end: usize,
+    /// The line and column of the start, as given by `line_col`.
+    pub line: usize,
+    pub col: usize,
+    /// The source text covered by the span.
+    pub text: String,
+}
+
+/// Resolves a span of the program in `source` to its position in the
+/// source text. Spans in a function are relative to the function's start;
+/// for a function in an included file, the position is in that file.
+#[salsa::tracked(return_ref)]
+pub fn resolve_span(db: &dyn crate::Db, source: SourceProgram, span: Span) -> ResolvedSpan {
+    let program = parse_statements(db, source);
+    let (abs_start, abs_end) = span.absolute(db, program);
+    let file = match span.id.data(db) {
+        DefIdData::Function(file, _) | DefIdData::File(file) => file,
+        DefIdData::Unknown => source,
+    };
+    let source_text = file.text(db);
+    let (line, col) = line_col(source_text, abs_start);
+    ResolvedSpan {
+        abs_start,
+        abs_end,
+        line,
+        col,
+        text: source_text
+            .get(abs_start..abs_end)
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// The 1-based line and column of the byte `offset` in `source_text`, with
+/// the column counted in characters. Lines end with `\n` or `\r\n`; the
+/// `\r` of a `\r\n` counts as part of the line break, not as a column.
+pub fn line_col(source_text: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(source_text.len());
+    while !source_text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &source_text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].trim_end_matches('\r').chars().count() + 1;
+    (line, column)
+}
+
+/// Puts the ends of a span in order and clamps them to a text of `len`
+/// bytes. Such a span is a bug in the pass which reported it, so it's logged,
+/// but the diagnostic is still worth showing.
+fn normalize_span(start: usize, end: usize, len: usize) -> (usize, usize) {
+    if start <= end && end <= len {
+        return (start, end);
+    }
+    eprintln!(
+        "internal error: diagnostic span {start}..{end} is invalid for a text of {len} bytes"
+    );
+    let (start, end) = (start.min(end), start.max(end));
+    (start.min(len), end.min(len))
+}
+
+/// Caps the number of errors shown for a file (`--max-errors`). Salsa
+/// accumulates every diagnostic, so the cap is applied while rendering.
+/// Warnings are always shown.
+pub struct ErrorLimit {
+    max: Option<usize>,
+    shown: usize,
+    hidden: usize,
+}
+
+impl ErrorLimit {
+    pub fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            shown: 0,
+            hidden: 0,
+        }
+    }
+
+    /// Whether to show `diagnostic`, counting it if it's an error.
+    pub fn admit(&mut self, diagnostic: &Diagnostic) -> bool {
+        if diagnostic.severity != Severity::Error {
+            return true;
+        }
+        if self.max.is_some_and(|max| self.shown >= max) {
+            self.hidden += 1;
+            return false;
+        }
+        self.shown += 1;
+        true
+    }
+
+    /// A final note about the errors which weren't shown, if any.
+    pub fn summary(&self) -> Option<String> {
+        match self.hidden {
+            0 => None,
+            1 => Some("... and 1 more error\n".to_string()),
+            hidden => Some(format!("... and {hidden} more errors\n")),
+        }
+    }
+}
+
+/// A span as `file:start..end in `function``, leaving out the file if it
+/// wasn't loaded from disk and the function if the span isn't in one.
+fn render_span(db: &dyn crate::Db, span: Span) -> String {
+    let (file, name) = match span.id.data(db) {
+        DefIdData::File(file) => (Some(file), None),
+        DefIdData::Function(file, name) => (Some(file), Some(name)),
+        DefIdData::Unknown => (None, None),
+    };
+    let mut output = match file.and_then(|file| file_path(db, file)) {
+        Some(path) => format!("{path}:{}..{}", span.start, span.end),
+        None => format!("{}..{}", span.start, span.end),
+    };
+    if let Some(name) = name {
+        output += &format!(" in `{}`", name.text(db));
+    }
+    output
+}
+
+/// The path `file` was loaded from, relative to the current directory if
+/// it's in it.
+fn file_path(db: &dyn crate::Db, file: SourceProgram) -> Option<String> {
+    let (path, _) = db
+        .files()
+        .by_path(db)
+        .iter()
+        .find(|(_, source)| **source == file)?;
+    let current_dir = std::env::current_dir().ok();
+    let path = current_dir
+        .and_then(|dir| path.strip_prefix(dir).ok())
+        .unwrap_or(path);
+    Some(path.display().to_string())
+}
+
+#[test]
+fn render_related_as_notes() {
+    use crate::ir::{DefId, FunctionId, SourceProgram};
+
+    let db = crate::db::Database::default();
+    let source_text = "fn f() = 1; fn f() = 2;";
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let name = FunctionId::new(&db, "f".into());
+    let def_id = DefId::new(&db, DefIdData::Function(source_program, name));
+    let diagnostic = Diagnostic::new(3, 4, "the function `f` is defined more than once".into())
+        .with_related(Span::new(def_id, 3, 4), "first defined here".into());
+    let expected = expect_test::expect![[r#"
+        error: the function `f` is defined more than once
+          --> 3..4
+        note: first defined here
+          --> 3..4 in `f`
+    "#]];
+    expected.assert_eq(&render_diagnostic(&db, source_text, &diagnostic));
+}
+
+#[test]
+fn render_in_color() {
+    let db = crate::db::Database::default();
+    let diagnostic = Diagnostic::warning(0, 1, "careful".into());
+    assert_eq!(
+        render_diagnostic_colored(&db, "x", &diagnostic, true),
+        "\x1b[1;33mwarning\x1b[0m: careful\n  --> 0..1\n"
+    );
+    // The flag takes precedence over the environment, which takes
+    // precedence over whether stderr is a terminal.
+    assert!(use_color(false, None, true));
+    assert!(!use_color(false, None, false));
+    assert!(!use_color(true, None, true));
+    assert!(!use_color(false, Some("1"), true));
+    assert!(use_color(false, Some(""), true));
+}
+
+#[test]
+fn resolve_span_in_function() {
+    use crate::ir::ExpressionData;
+
+    let db = crate::db::Database::default();
+    let source_program = SourceProgram::new(
+        &db,
+        "fn f(x) = x + 1;\nfn g(y) = f(y * 2);\nprint g(3);".to_string(),
+    );
+    let program = parse_statements(&db, source_program);
+    let g = program.functions(&db)[1].data(&db);
+    let arena = g.arena(&db);
+    let ExpressionData::Call(_, args) = &arena[g.body].data else {
+        panic!("expected a call");
+    };
+    let expected = ResolvedSpan {
+        abs_start: 29,
+        abs_end: 34,
+        line: 2,
+        col: 13,
+        text: "y * 2".to_string(),
+    };
+    assert_eq!(
+        resolve_span(&db, source_program, arena[args[0].value].span),
+        &expected
+    );
+    let name = resolve_span(&db, source_program, g.name_span);
+    assert_eq!((name.line, name.col, name.text.as_str()), (2, 4, "g"));
+
+    // Statement spans are absolute.
+    let statement = program.statements(&db)[0].span;
+    let resolved = resolve_span(&db, source_program, statement);
+    assert_eq!((resolved.line, resolved.text.as_str()), (3, "print g(3);"));
+}
+
+#[test]
+fn line_col_with_crlf() {
+    let source_text = "print 1;\r\n\r\nprint é + x;\nprint 2;";
+    assert_eq!(line_col(source_text, 0), (1, 1));
+    assert_eq!(line_col(source_text, 8), (1, 9));
+    // Between the `\r` and the `\n`.
+    assert_eq!(line_col(source_text, 9), (1, 9));
+    assert_eq!(line_col(source_text, 10), (2, 1));
+    assert_eq!(line_col(source_text, 12), (3, 1));
+    // After the two-byte `é`.
+    assert_eq!(line_col(source_text, 23), (3, 11));
+    assert_eq!(line_col(source_text, source_text.len()), (4, 9));
+}
+
+#[test]
+fn render_clamps_out_of_bounds_span() {
+    let db = crate::db::Database::default();
+    let diagnostic = Diagnostic::new(3, 40, "unexpected end of file".into());
+    let expected = expect_test::expect![[r#"
+        error: unexpected end of file
+          --> 3..8
+    "#]];
+    expected.assert_eq(&render_diagnostic(&db, "print 1;", &diagnostic));
+}
+
+#[test]
+fn render_normalizes_inverted_span() {
+    let db = crate::db::Database::default();
+    let diagnostic = Diagnostic {
+        start: 5,
+        end: 2,
+        ..Diagnostic::new(0, 0, "inverted".into())
+    };
+    let expected = expect_test::expect![[r#"
+        error: inverted
+          --> 2..5
+    "#]];
+    expected.assert_eq(&render_diagnostic(&db, "print 1;", &diagnostic));
+}
+
+#[test]
+fn limit_errors() {
+    let diagnostics: Vec<_> = (0..10)
+        .map(|i| match i {
+            4 | 7 => Diagnostic::warning(i, i + 1, format!("warning {i}")),
+            _ => Diagnostic::new(i, i + 1, format!("error {i}")),
+        })
+        .collect();
+    let mut limit = ErrorLimit::new(Some(3));
+    let shown: Vec<_> = diagnostics
+        .iter()
+        .filter(|diagnostic| limit.admit(diagnostic))
+        .map(|diagnostic| diagnostic.message.as_str())
+        .collect();
+    assert_eq!(
+        shown,
+        ["error 0", "error 1", "error 2", "warning 4", "warning 7"]
+    );
+    assert_eq!(limit.summary().as_deref(), Some("... and 5 more errors\n"));
+
+    let mut unlimited = ErrorLimit::new(None);
+    assert!(diagnostics
+        .iter()
+        .all(|diagnostic| unlimited.admit(diagnostic)));
+    assert_eq!(unlimited.summary(), None);
+
+    let mut limit = ErrorLimit::new(Some(7));
+    assert_eq!(diagnostics.iter().filter(|d| limit.admit(d)).count(), 9);
+    assert_eq!(limit.summary().as_deref(), Some("... and 1 more error\n"));
+}
+
+#[test]
+fn normalize_spans() {
+    assert_eq!(normalize_span(1, 2, 3), (1, 2));
+    assert_eq!(normalize_span(3, 3, 3), (3, 3));
+    assert_eq!(normalize_span(2, 1, 3), (1, 2));
+    assert_eq!(normalize_span(2, 9, 3), (2, 3));
+    assert_eq!(normalize_span(8, 9, 3), (3, 3));
+}
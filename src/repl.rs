@@ -0,0 +1,276 @@
+//! The interactive mode: `--repl`, or running without any files.
+
+use std::io::{BufRead, Write};
+use std::ops::Range;
+
+use crate::db::Database;
+use crate::eval::{eval_source_expression, format_number};
+use crate::ir::{Diagnostic, Diagnostics, FunctionId, SourceProgram};
+use crate::parser::{parse_error_diagnostic, parse_statements, quick_parse};
+use crate::raw::RawStatementData;
+use crate::render::render_diagnostic;
+use crate::type_check::type_check_function;
+
+/// The state of a REPL session: the definitions entered so far.
+pub struct ReplSession {
+    db: Database,
+    /// The source text of each definition, with the name of the function it
+    /// defines. Redefining a function replaces its previous definition.
+    definitions: Vec<(Option<String>, String)>,
+    /// The definitions, as the source the program is parsed from.
+    source_program: SourceProgram,
+}
+
+impl Default for ReplSession {
+    fn default() -> Self {
+        let db = Database::default();
+        let source_program = SourceProgram::new(&db, String::new());
+        Self {
+            db,
+            definitions: vec![],
+            source_program,
+        }
+    }
+}
+
+impl ReplSession {
+    /// Handles a line of input and returns what to show for it. A line with
+    /// statements keeps its definitions for the following lines and prints
+    /// the values of its `print` statements. Otherwise the line is a bare
+    /// expression, whose value is printed. If the line has errors, only the
+    /// diagnostics are returned and the session is unchanged.
+    pub fn feed_line(&mut self, line: &str) -> String {
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            return String::new();
+        }
+        if !line.contains(';') {
+            return self.eval(line, 0..line.len());
+        }
+        let statements = match quick_parse(line) {
+            Ok(statements) => statements,
+            Err(err) => return render_in_line(&self.db, line, &parse_error_diagnostic(line, err)),
+        };
+        let previous = self.definitions.clone();
+        let mut defined = vec![];
+        // The text of each definition on this line, with where it starts.
+        let mut from_line = vec![];
+        let mut prints = vec![];
+        for statement in statements {
+            let text = line[statement.span.clone()].to_string();
+            from_line.push((text.clone(), statement.span.start));
+            match statement.data {
+                RawStatementData::Function { name, .. } => {
+                    self.definitions
+                        .retain(|(defined_name, _)| defined_name.as_ref() != Some(&name));
+                    defined.push(FunctionId::new(&self.db, name.clone()));
+                    self.definitions.push((Some(name), text));
+                }
+                RawStatementData::Include(_) => self.definitions.push((None, text)),
+                RawStatementData::Print(expression) => prints.push(expression.span),
+            }
+        }
+        let mut output = String::new();
+        if !defined.is_empty() || self.definitions.len() != previous.len() {
+            let diagnostics = self.update_definitions();
+            let errors = diagnostics.iter().any(Diagnostic::is_error);
+            for diagnostic in &diagnostics {
+                // Diagnostics in a definition on this line are shown against
+                // the line, and errors in an earlier one against that
+                // definition. Otherwise only the new definitions' warnings
+                // are shown.
+                let (text, diagnostic) = self.in_definition(diagnostic);
+                match from_line.iter().find(|(line_text, _)| *line_text == text) {
+                    Some((_, start)) => {
+                        let diagnostic = Diagnostic {
+                            start: diagnostic.start + start,
+                            end: diagnostic.end + start,
+                            ..diagnostic
+                        };
+                        output += &render_in_line(&self.db, line, &diagnostic);
+                    }
+                    None if errors => output += &render_in_line(&self.db, &text, &diagnostic),
+                    None => {}
+                }
+            }
+            if errors {
+                self.definitions = previous;
+                self.update_definitions();
+                return output;
+            }
+        }
+        for span in prints {
+            output += &self.eval(line, span);
+        }
+        output
+    }
+
+    /// The text of the definition `diagnostic` of the definitions' program
+    /// is in, and the diagnostic positioned in that text.
+    fn in_definition(&self, diagnostic: &Diagnostic) -> (String, Diagnostic) {
+        let mut start = 0;
+        for (index, (_, text)) in self.definitions.iter().enumerate() {
+            let end = start + text.len();
+            if diagnostic.start <= end || index + 1 == self.definitions.len() {
+                let diagnostic = Diagnostic {
+                    start: diagnostic.start.saturating_sub(start),
+                    end: diagnostic.end.saturating_sub(start),
+                    ..diagnostic.clone()
+                };
+                return (text.clone(), diagnostic);
+            }
+            // The definitions are joined with newlines.
+            start = end + 1;
+        }
+        (String::new(), diagnostic.clone())
+    }
+
+    /// Replaces the program with the current definitions, returning its
+    /// diagnostics, as `compile_diagnostics` but positioned in the whole
+    /// text.
+    fn update_definitions(&mut self) -> Vec<Diagnostic> {
+        let text: Vec<_> = self
+            .definitions
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect();
+        self.source_program
+            .set_text(&mut self.db)
+            .to(text.join("\n"));
+        let db = &self.db;
+        let program = parse_statements(db, self.source_program);
+        let mut diagnostics = parse_statements::accumulated::<Diagnostics>(db, self.source_program);
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            return diagnostics;
+        }
+        // Type errors are positioned relative to the function they're in.
+        for function in program.functions(db) {
+            let start = function.start(db);
+            for diagnostic in
+                type_check_function::accumulated::<Diagnostics>(db, *function, program)
+            {
+                diagnostics.push(Diagnostic {
+                    start: start + diagnostic.start,
+                    end: start + diagnostic.end,
+                    ..diagnostic
+                });
+            }
+        }
+        diagnostics
+    }
+
+    /// Evaluates the expression at `span` of `line`, returning its
+    /// diagnostics, shown against the line, and its value if it has one.
+    fn eval(&self, line: &str, span: Range<usize>) -> String {
+        let program = parse_statements(&self.db, self.source_program);
+        let source_text = line[span.clone()].to_string();
+        let value = eval_source_expression(&self.db, program, source_text.clone());
+        let diagnostics =
+            eval_source_expression::accumulated::<Diagnostics>(&self.db, program, source_text);
+        let mut output = String::new();
+        for diagnostic in diagnostics {
+            let diagnostic = Diagnostic {
+                start: diagnostic.start + span.start,
+                end: diagnostic.end + span.start,
+                ..diagnostic
+            };
+            output += &render_in_line(&self.db, line, &diagnostic);
+        }
+        if let Some(value) = value {
+            output += &format!("{}\n", format_number(value.into_inner()));
+        }
+        output
+    }
+}
+
+/// `render_diagnostic`, followed by `line` with the diagnostic's span
+/// underlined with carets.
+fn render_in_line(db: &dyn crate::Db, line: &str, diagnostic: &Diagnostic) -> String {
+    let mut output = render_diagnostic(db, line, diagnostic);
+    let start = diagnostic.start.min(line.len());
+    let end = diagnostic.end.clamp(start, line.len());
+    let (Some(before), Some(spanned)) = (line.get(..start), line.get(start..end)) else {
+        return output;
+    };
+    output += &format!(
+        "   | {line}\n   | {}{}\n",
+        " ".repeat(before.chars().count()),
+        "^".repeat(spanned.chars().count().max(1))
+    );
+    output
+}
+
+/// Runs a session on stdin until it ends.
+pub fn run() -> std::io::Result<()> {
+    let mut session = ReplSession::default();
+    let mut stdout = std::io::stdout();
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("> ");
+        stdout.flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            return Ok(());
+        };
+        print!("{}", session.feed_line(&line?));
+    }
+}
+
+#[test]
+fn repl_session() {
+    let mut session = ReplSession::default();
+    let mut feed = |line| session.feed_line(line);
+    let expected = expect_test::expect![[r#"
+        > fn double(x) = 2 * x;
+        > double(21)
+        42
+        > fn bad(x) = y;
+        error: the variable `y` is not declared
+          --> 12..13
+           | fn bad(x) = y;
+           |             ^
+        > bad(1)
+        error: the function `bad` is not declared
+          --> 0..6
+           | bad(1)
+           | ^^^^^^
+        > double(2) + + 1
+        error: expected an operand here
+          --> 12..13
+           | double(2) + + 1
+           |             ^
+        > print double(1); print 1 / 0;
+        2
+        warning: division by zero: `1 / 0` is `1 / 0`
+          --> 23..28
+           | print double(1); print 1 / 0;
+           |                        ^^^^^
+        inf
+        > fn double(x) = 3 * x; fn sqrt(x) = x; print double(sqrt(1));
+        warning: definition of `sqrt` shadows a built-in function
+          --> 25..29
+           | fn double(x) = 3 * x; fn sqrt(x) = x; print double(sqrt(1));
+           |                          ^^^^
+        3
+        > print * 2;
+        error: expected an operand here
+          --> 6..7
+           | print * 2;
+           |       ^
+    "#]];
+    let lines = [
+        "fn double(x) = 2 * x;",
+        "double(21)",
+        "fn bad(x) = y;",
+        "bad(1)",
+        "double(2) + + 1",
+        "print double(1); print 1 / 0;",
+        "fn double(x) = 3 * x; fn sqrt(x) = x; print double(sqrt(1));",
+        "print * 2;",
+    ];
+    let transcript: String = lines
+        .iter()
+        .map(|line| format!("> {line}\n{}", feed(line)))
+        .collect();
+    expected.assert_eq(&transcript);
+}
@@ -0,0 +1,180 @@
+//! A compiler which can be shared between threads, for programs embedding
+//! it, such as an editor integration. It owns the database, so callers
+//! don't deal with salsa directly.
+
+use std::{
+    collections::HashMap,
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    compile::{compile_full, CompileMode},
+    db::Database,
+    format::format_source,
+    ir::{DefIdData, Diagnostic, SourceProgram},
+    locate::{node_at_offset, NodeRef},
+    parser::parse_statements,
+    render::resolve_span,
+    type_check::expression_types,
+};
+
+/// A set of files kept in memory and compiled on demand. Edits wait for the
+/// queries running at the time to be cancelled; queries take a snapshot of
+/// the database, so they don't wait for each other.
+#[derive(Default)]
+pub struct CompilerService {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    db: Database,
+    /// The files set so far, by the path they were given with.
+    files: HashMap<PathBuf, SourceProgram>,
+}
+
+impl CompilerService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the text of the file at `path`, adding it if it's new. The file
+    /// isn't read from disk.
+    pub fn set_file(&self, path: impl Into<PathBuf>, text: String) {
+        let path = path.into();
+        let mut state = self.state.lock().unwrap();
+        let State { db, files } = &mut *state;
+        match files.get(&path) {
+            Some(source) => {
+                source.set_text(db).to(text);
+            }
+            None => {
+                let source = SourceProgram::new(db, text);
+                files.insert(path, source);
+            }
+        }
+    }
+
+    /// The diagnostics of checking the file with `compile_full`, as
+    /// `compile_diagnostics` orders them. An unknown file has none.
+    pub fn diagnostics(&self, path: &Path) -> Vec<Diagnostic> {
+        self.read(path, |db, source| {
+            let result = compile_full(db, source, CompileMode::Check);
+            result
+                .diagnostics
+                .into_iter()
+                .map(|(_, diagnostic)| diagnostic)
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// A description of what is at the byte `offset` of the file: the
+    /// signature of a function, or the text of an expression, with its type
+    /// if it's in a function. The types of the expressions of print
+    /// statements aren't recorded.
+    pub fn hover(&self, path: &Path, offset: usize) -> Option<String> {
+        self.read(path, |db, source| hover_text(db, source, offset))
+            .flatten()
+    }
+
+    /// The file in a canonical layout, or `None` if it doesn't parse.
+    pub fn format(&self, path: &Path) -> Option<String> {
+        self.read(path, format_source).flatten()
+    }
+
+    /// Runs `query` on a snapshot of the database, taken without blocking
+    /// other readers. If an edit cancels the query, it runs again on the
+    /// edited text. Returns `None` for an unknown file.
+    fn read<T>(
+        &self,
+        path: &Path,
+        query: impl Fn(&dyn crate::Db, SourceProgram) -> T,
+    ) -> Option<T> {
+        loop {
+            let (snapshot, source) = {
+                let state = self.state.lock().unwrap();
+                (state.db.snapshot(), *state.files.get(path)?)
+            };
+            // The snapshot is dropped before trying again, so that the edit
+            // which cancelled the query can go ahead.
+            if let Ok(result) =
+                salsa::Cancelled::catch(AssertUnwindSafe(|| query(&*snapshot, source)))
+            {
+                return Some(result);
+            }
+        }
+    }
+}
+
+fn hover_text(db: &dyn crate::Db, source: SourceProgram, offset: usize) -> Option<String> {
+    let program = parse_statements(db, source);
+    let span = match node_at_offset(db, source, offset)? {
+        NodeRef::Function(function) => {
+            return Some(crate::signature::signature(db, function).to_string());
+        }
+        NodeRef::Statement(_) => return None,
+        NodeRef::Expression(span) => span,
+    };
+    let text = resolve_span(db, source, span).text.clone();
+    // Only the types of expressions in functions are recorded.
+    let DefIdData::Function(_, name) = span.id.data(db) else {
+        return Some(format!("`{text}`"));
+    };
+    let function = crate::type_check::find_function(db, program, name)?;
+    let ty = expression_types(db, function, program)
+        .iter()
+        .find(|(expression, _)| *expression == span)
+        .map(|(_, ty)| ty);
+    Some(match ty {
+        Some(ty) => format!("`{text}`: {ty}"),
+        None => format!("`{text}`"),
+    })
+}
+
+#[test]
+fn hover_and_format() {
+    let service = CompilerService::new();
+    let path = Path::new("main.bn");
+    let text = "fn area(w,h)=w*h;\nprint area(2, 3);\n";
+    service.set_file(path, text.to_string());
+    let hover = |text_at: &str| service.hover(path, text.find(text_at).unwrap());
+    assert_eq!(hover("area(w").as_deref(), Some("fn area(w, h)"));
+    assert_eq!(hover("*h").as_deref(), Some("`w*h`: number"));
+    assert_eq!(hover("3)").as_deref(), Some("`3`"));
+    assert_eq!(
+        service.format(path).as_deref(),
+        Some("fn area(w, h) = w * h;\nprint area(2, 3);\n")
+    );
+    assert!(service.diagnostics(path).is_empty());
+    assert_eq!(service.hover(Path::new("other.bn"), 0), None);
+}
+
+#[test]
+fn edit_while_querying() {
+    let service = CompilerService::new();
+    let path = Path::new("main.bn");
+    // Every other version has an error.
+    let version = |i: usize| format!("fn f(x) = {} + {i}; print f(1);", ["x", "y"][i % 2]);
+    service.set_file(path, version(0));
+    std::thread::scope(|scope| {
+        let reader = scope.spawn(|| {
+            for _ in 0..50 {
+                let diagnostics = service.diagnostics(path);
+                // Each result is of one version of the file, not a mix.
+                assert!(diagnostics.len() <= 1);
+                service.hover(path, 10);
+            }
+        });
+        for i in 1..=20 {
+            service.set_file(path, version(i));
+        }
+        reader.join().unwrap();
+    });
+    // The last version has no errors.
+    assert!(service.diagnostics(path).is_empty());
+    service.set_file(path, version(21));
+    assert_eq!(service.diagnostics(path).len(), 1);
+}
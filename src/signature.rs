@@ -0,0 +1,156 @@
+//! Signatures of user and built-in functions, for the type checker and for
+//! tools such as completion.
+
+use std::fmt;
+
+use crate::builtins::{find_builtin, BUILTINS};
+use crate::ir::{Function, Program};
+
+/// What a caller needs to know about a function, as plain data.
+#[derive(Eq, PartialEq, Clone, Hash, Debug)]
+pub struct FunctionSignature {
+    pub name: String,
+    /// The names of the parameters, in order.
+    pub params: Vec<String>,
+    /// Whether any number of arguments is accepted, like for the builtin
+    /// `sum`. User functions never are.
+    pub variadic: bool,
+}
+
+impl FunctionSignature {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Whether the function can be called with `given` arguments.
+    pub fn accepts(&self, given: usize) -> bool {
+        self.variadic || given == self.arity()
+    }
+}
+
+/// `fn name(a, b)`, or `fn sum(...)` for a variadic function.
+impl fmt::Display for FunctionSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut params = self.params.clone();
+        if self.variadic {
+            params.push("...".to_string());
+        }
+        write!(f, "fn {}({})", self.name, params.join(", "))
+    }
+}
+
+/// The signature of `function`, which only changes when its name or
+/// parameters do.
+#[salsa::tracked(return_ref)]
+pub fn signature(db: &dyn crate::Db, function: Function) -> FunctionSignature {
+    FunctionSignature {
+        name: function.name(db).text(db).clone(),
+        params: function
+            .data(db)
+            .args
+            .iter()
+            .map(|arg| arg.text(db).clone())
+            .collect(),
+        variadic: false,
+    }
+}
+
+/// The signature of the built-in function `name`, if there is one.
+pub fn builtin_signature(name: &str) -> Option<FunctionSignature> {
+    let builtin = find_builtin(name)?;
+    Some(FunctionSignature {
+        name: builtin.name.to_string(),
+        params: builtin
+            .params
+            .iter()
+            .map(|param| param.to_string())
+            .collect(),
+        variadic: builtin.variadic,
+    })
+}
+
+/// The signatures of the functions a call in `program` can refer to, e.g.
+/// for completion: the program's functions, then the builtins which they
+/// don't shadow.
+pub fn callable_signatures(db: &dyn crate::Db, program: Program) -> Vec<FunctionSignature> {
+    let mut signatures: Vec<_> = program
+        .functions(db)
+        .iter()
+        .map(|function| signature(db, *function).clone())
+        .collect();
+    for builtin in BUILTINS {
+        if !signatures
+            .iter()
+            .any(|signature| signature.name == builtin.name)
+        {
+            signatures.extend(builtin_signature(builtin.name));
+        }
+    }
+    signatures
+}
+
+#[test]
+fn signature_of_area_rectangle() {
+    use crate::ir::SourceProgram;
+
+    let db = crate::db::Database::default();
+    let source_program = SourceProgram::new(
+        &db,
+        "fn area_rectangle(w, h) = w * h; fn pi() = 3.14;".to_string(),
+    );
+    let program = crate::parser::parse_statements(&db, source_program);
+    let signatures: Vec<_> = program
+        .functions(&db)
+        .iter()
+        .map(|function| signature(&db, *function))
+        .collect();
+    assert_eq!(
+        signatures[0],
+        &FunctionSignature {
+            name: "area_rectangle".to_string(),
+            params: vec!["w".to_string(), "h".to_string()],
+            variadic: false,
+        }
+    );
+    assert_eq!(signatures[0].arity(), 2);
+    assert_eq!(signatures[1].arity(), 0);
+}
+
+#[test]
+fn builtin_signatures() {
+    use crate::ir::SourceProgram;
+
+    let sqrt = builtin_signature("sqrt").unwrap();
+    assert_eq!(
+        (sqrt.to_string(), sqrt.arity()),
+        ("fn sqrt(x)".to_string(), 1)
+    );
+    assert!(!sqrt.accepts(2));
+    let sum = builtin_signature("sum").unwrap();
+    assert_eq!(sum.to_string(), "fn sum(...)");
+    assert!(sum.accepts(0) && sum.accepts(3));
+    assert_eq!(builtin_signature("area"), None);
+
+    let db = crate::db::Database::default();
+    let source_program = SourceProgram::new(&db, "fn min(x) = x;".to_string());
+    let program = crate::parser::parse_statements(&db, source_program);
+    let signatures: Vec<_> = callable_signatures(&db, program)
+        .iter()
+        .map(|signature| signature.to_string())
+        .collect();
+    // The user's `min` shadows the builtin.
+    assert_eq!(
+        signatures,
+        [
+            "fn min(x)",
+            "fn sqrt(x)",
+            "fn abs(x)",
+            "fn max(a, b)",
+            "fn pow(base, exponent)",
+            "fn log(x)",
+            "fn sin(x)",
+            "fn cos(x)",
+            "fn sum(...)",
+        ]
+    );
+}
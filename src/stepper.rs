@@ -0,0 +1,449 @@
+//! An evaluator which runs a program one expression at a time, e.g. for a
+//! debugger. It evaluates like `eval::eval_program`, but keeps the work left
+//! on an explicit stack instead of recursing, so that it can stop after any
+//! expression. The `memoize` and `cse` options are ignored.
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::eval::{Callee, EvalOptions, OutputLine, Runtime};
+use crate::ir::{
+    Diagnostic, ExprArena, ExprId, ExpressionData, FunctionId, Program, Span, StatementData,
+    VariableId,
+};
+use crate::type_check::{argument_order, bind_arguments, builtin_arguments};
+use crate::value::Value;
+
+/// The values of the variables in scope, shared by the expressions of a
+/// scope.
+type Env = Rc<HashMap<VariableId, Value>>;
+
+/// What a step of a `Stepper` did.
+#[derive(Clone, Debug)]
+pub enum StepResult {
+    /// The expression at `span` evaluated to `value`.
+    Evaluated { span: Span, value: Value },
+    /// Evaluating an expression failed, which stops its statement. The next
+    /// step starts the next statement, unless a math event failed with
+    /// `strict_math`.
+    Failed(Diagnostic),
+    /// Every statement has run.
+    Done,
+}
+
+/// Work left to do for the statement being run.
+enum Task<'db> {
+    /// Evaluates an expression: right away if it has no operands, otherwise
+    /// by scheduling its operands, then a `Reduce` or `Call`.
+    Eval {
+        arena: &'db ExprArena,
+        id: ExprId,
+        env: Env,
+    },
+    /// Computes the value of an expression from those of its operands, the
+    /// last ones on the value stack.
+    Reduce {
+        arena: &'db ExprArena,
+        id: ExprId,
+        env: Env,
+    },
+    /// Calls `callee` at `span` with the last values, the arguments as
+    /// written, passed in `order` (see `argument_order`).
+    Call {
+        callee: Callee,
+        f: FunctionId,
+        order: Vec<usize>,
+        span: Span,
+    },
+    /// Ends the expression at `span`, whose value is the last one evaluated:
+    /// a call's is that of the function's body, and a `let`'s or lambda's
+    /// that of its body. A `call` also leaves the user function.
+    Finish { span: Span, call: bool },
+    /// Prints the value of the `print` statement at `span`.
+    PrintStatement(Span),
+}
+
+/// Runs the top-level statements of a program in order, one expression per
+/// `step`. Diagnostics are collected rather than accumulated, so a stepper
+/// can be used outside a query.
+pub struct Stepper<'db> {
+    runtime: Runtime<'db>,
+    program: Program,
+    /// The index of the next statement to start.
+    next_statement: usize,
+    /// The work left for the current statement, innermost last.
+    tasks: Vec<Task<'db>>,
+    /// The values of the expressions evaluated and not yet used by the
+    /// expression they're an operand of.
+    values: Vec<Value>,
+}
+
+impl<'db> Stepper<'db> {
+    pub fn new(db: &'db dyn crate::Db, program: Program, options: EvalOptions) -> Self {
+        let mut runtime = Runtime::new(db, Some(program), options);
+        runtime.collected = Some(vec![]);
+        Self {
+            runtime,
+            program,
+            next_statement: 0,
+            tasks: vec![],
+            values: vec![],
+        }
+    }
+
+    /// Evaluates until the next expression has a value, starting the next
+    /// statement if the current one is done.
+    pub fn step(&mut self) -> StepResult {
+        let db = self.runtime.db;
+        loop {
+            let Some(task) = self.tasks.pop() else {
+                let statements = self.program.statements(db);
+                let Some(statement) = statements.get(self.next_statement) else {
+                    return StepResult::Done;
+                };
+                self.next_statement += 1;
+                self.runtime.stats.statements += 1;
+                match &statement.data {
+                    StatementData::Print(expression) => {
+                        self.tasks.push(Task::PrintStatement(statement.span));
+                        self.tasks.push(Task::Eval {
+                            arena: &statement.arena,
+                            id: *expression,
+                            env: Env::default(),
+                        });
+                    }
+                }
+                continue;
+            };
+            match self.run(task) {
+                Ok(None) => {}
+                Ok(Some(span)) => {
+                    let value = self.values.last().cloned().expect("no value");
+                    // A statement prints in the step evaluating it.
+                    if let Some(&Task::PrintStatement(span)) = self.tasks.last() {
+                        self.tasks.pop();
+                        let value = self.values.pop().expect("no value");
+                        self.runtime.print(value, span);
+                    }
+                    return StepResult::Evaluated { span, value };
+                }
+                Err(diagnostic) => {
+                    self.values.clear();
+                    for task in self.tasks.drain(..) {
+                        if let Task::Finish { call: true, .. } = task {
+                            self.runtime.exit_call();
+                        }
+                    }
+                    self.runtime.report(Some(diagnostic.clone()));
+                    if self.runtime.math_failed {
+                        self.next_statement = self.program.statements(db).len();
+                    }
+                    return StepResult::Failed(diagnostic);
+                }
+            }
+        }
+    }
+
+    /// What the program printed so far.
+    pub fn output(&self) -> &[OutputLine] {
+        &self.runtime.output
+    }
+
+    /// The diagnostics reported so far, including the math warnings of
+    /// printed values.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.runtime.collected.as_deref().unwrap_or_default()
+    }
+
+    /// The user function calls being evaluated, as the function and the
+    /// span of the call, innermost last.
+    pub fn call_stack(&self) -> &[(FunctionId, Span)] {
+        self.runtime.call_stack()
+    }
+
+    /// Runs one task. Returns the span of the expression it finished, if
+    /// any, whose value is then the last on the value stack.
+    fn run(&mut self, task: Task<'db>) -> Result<Option<Span>, Diagnostic> {
+        let db = self.runtime.db;
+        let (span, value) = match task {
+            Task::Eval { arena, id, env } => {
+                let span = arena[id].span;
+                let value = match &arena[id].data {
+                    ExpressionData::Number(n) => Value::Number(*n),
+                    ExpressionData::Bool(b) => Value::Bool(*b),
+                    ExpressionData::Variable(v) => match env.get(v) {
+                        Some(value) => value.clone(),
+                        None => return Err(self.runtime.undeclared_variable(*v, span)),
+                    },
+                    ExpressionData::Error(message) => {
+                        return Err(self.runtime.raise(message, span))
+                    }
+                    ExpressionData::Call(f, args) => {
+                        let callee = self.runtime.resolve_call(*f, args.len(), span)?;
+                        let bound = match &callee {
+                            Callee::User(function) => {
+                                bind_arguments(db, *f, &function.data(db).args, arena, args)
+                            }
+                            Callee::Builtin(_) => builtin_arguments(db, *f, arena, args),
+                        }
+                        .map_err(|(span, message)| self.runtime.raise(&message, span))?;
+                        self.tasks.push(Task::Call {
+                            callee,
+                            f: *f,
+                            order: argument_order(&bound, args),
+                            span,
+                        });
+                        // Arguments are evaluated in the order they're
+                        // written.
+                        let args: Vec<_> = args.iter().map(|arg| arg.value).collect();
+                        self.schedule(arena, &args, &env);
+                        return Ok(None);
+                    }
+                    // A lambda's body, and a `let`'s, is evaluated once the
+                    // arguments, or the value, are.
+                    ExpressionData::Lambda(_, _, args) => {
+                        self.tasks.push(Task::Reduce {
+                            arena,
+                            id,
+                            env: env.clone(),
+                        });
+                        self.schedule(arena, args, &env);
+                        return Ok(None);
+                    }
+                    ExpressionData::Let(_, value, _) => {
+                        self.tasks.push(Task::Reduce {
+                            arena,
+                            id,
+                            env: env.clone(),
+                        });
+                        self.schedule(arena, &[*value], &env);
+                        return Ok(None);
+                    }
+                    data => {
+                        self.tasks.push(Task::Reduce {
+                            arena,
+                            id,
+                            env: env.clone(),
+                        });
+                        self.schedule(arena, &data.operands(), &env);
+                        return Ok(None);
+                    }
+                };
+                (span, value)
+            }
+            Task::Reduce { arena, id, env } => {
+                let span = arena[id].span;
+                let value = match &arena[id].data {
+                    ExpressionData::Op(_, op, _) => {
+                        let right = self.pop();
+                        let left = self.pop();
+                        self.runtime
+                            .apply_op(*op, &left, &right, span, Some((arena, id)))?
+                    }
+                    ExpressionData::Neg(_) => {
+                        let operand = self.pop();
+                        Value::from(-self.runtime.expect_number(&operand, span)?)
+                    }
+                    ExpressionData::Abs(_) => {
+                        let operand = self.pop();
+                        Value::from(self.runtime.expect_number(&operand, span)?.abs())
+                    }
+                    ExpressionData::Print(_) => {
+                        let value = self.pop();
+                        self.runtime.print(value.clone(), span);
+                        value
+                    }
+                    ExpressionData::Lambda(params, body, args) => {
+                        let args = self.values.split_off(self.values.len() - args.len());
+                        self.runtime
+                            .check_lambda_arity(params.len(), args.len(), span)?;
+                        let env = params.iter().copied().zip(args).collect();
+                        self.enter(span, false, arena, *body, Rc::new(env));
+                        return Ok(None);
+                    }
+                    ExpressionData::Let(name, _, body) => {
+                        let value = self.pop();
+                        let mut env = (*env).clone();
+                        env.insert(*name, value);
+                        self.enter(span, false, arena, *body, Rc::new(env));
+                        return Ok(None);
+                    }
+                    _ => unreachable!("an expression without operands"),
+                };
+                (span, value)
+            }
+            Task::Call {
+                callee,
+                f,
+                order,
+                span,
+            } => {
+                let values = self.values.split_off(self.values.len() - order.len());
+                let args: Vec<_> = order.iter().map(|&index| values[index].clone()).collect();
+                match callee {
+                    Callee::User(function) => {
+                        let data = function.data(db);
+                        let env = data.args.iter().copied().zip(args).collect();
+                        self.runtime.enter_call(f, span)?;
+                        self.enter(span, true, data.arena(db), data.body, Rc::new(env));
+                        return Ok(None);
+                    }
+                    Callee::Builtin(builtin) => {
+                        (span, self.runtime.call_builtin(builtin, f, &args, span)?)
+                    }
+                }
+            }
+            Task::Finish { span, call } => {
+                if call {
+                    self.runtime.exit_call();
+                }
+                return Ok(Some(span));
+            }
+            Task::PrintStatement(span) => {
+                let value = self.pop();
+                self.runtime.print(value, span);
+                return Ok(None);
+            }
+        };
+        self.values.push(value);
+        Ok(Some(span))
+    }
+
+    /// Schedules the evaluation of `ids`, in order.
+    fn schedule(&mut self, arena: &'db ExprArena, ids: &[ExprId], env: &Env) {
+        for &id in ids.iter().rev() {
+            self.tasks.push(Task::Eval {
+                arena,
+                id,
+                env: env.clone(),
+            });
+        }
+    }
+
+    /// Schedules the evaluation of `body`, whose value is that of the
+    /// expression at `span`.
+    fn enter(&mut self, span: Span, call: bool, arena: &'db ExprArena, body: ExprId, env: Env) {
+        self.tasks.push(Task::Finish { span, call });
+        self.tasks.push(Task::Eval {
+            arena,
+            id: body,
+            env,
+        });
+    }
+
+    fn pop(&mut self) -> Value {
+        self.values.pop().expect("no value")
+    }
+}
+
+/// Steps through `source_text` to the end, recording each step as the text
+/// of the expression and its value, or the error's message.
+#[cfg(test)]
+fn step_string(source_text: &str) -> (Vec<String>, Vec<String>) {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = parse_statements(&db, source_program);
+    let mut stepper = Stepper::new(&db, program, EvalOptions::default());
+    let mut steps = vec![];
+    loop {
+        match stepper.step() {
+            StepResult::Evaluated { span, value } => {
+                let (start, end) = span.absolute(&db, program);
+                steps.push(format!("{}: {value}", &source_text[start..end]));
+            }
+            StepResult::Failed(diagnostic) => steps.push(format!("error: {}", diagnostic.message)),
+            StepResult::Done => break,
+        }
+    }
+    assert!(stepper.call_stack().is_empty());
+    let output = stepper
+        .output()
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    (steps, output)
+}
+
+#[test]
+fn step_print() {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, "print 1 + 2;".to_string());
+    let program = parse_statements(&db, source_program);
+    let mut stepper = Stepper::new(&db, program, EvalOptions::default());
+    let mut spans = vec![];
+    while let StepResult::Evaluated { span, .. } = stepper.step() {
+        spans.push(span.absolute(&db, program));
+    }
+    // The operands, then the operation, which the statement prints.
+    assert_eq!(spans, [(6, 7), (10, 11), (6, 11)]);
+    assert_eq!(stepper.output()[0].text, "3");
+    assert!(matches!(stepper.step(), StepResult::Done));
+}
+
+#[test]
+fn step_calls_and_errors() {
+    let (steps, output) =
+        step_string("fn f(x) = x * 2;\nprint f(1) + 1;\nprint y;\nprint let z = 3 in z;");
+    let expected = expect_test::expect![[r#"
+        [
+            "1: 1",
+            "x: 1",
+            "2: 2",
+            "x * 2: 2",
+            "f(1): 2",
+            "1: 1",
+            "f(1) + 1: 3",
+            "error: the variable `y` is not declared",
+            "3: 3",
+            "z: 3",
+            "let z = 3 in z: 3",
+        ]
+    "#]];
+    expected.assert_debug_eq(&steps);
+    assert_eq!(output.len(), 2);
+}
+
+#[test]
+fn stepper_matches_evaluator() {
+    use crate::{
+        db::Database,
+        eval::eval_program,
+        ir::{Diagnostics, SourceProgram},
+        parser::parse_statements,
+    };
+
+    let db = Database::default();
+    for source_text in [
+        r"fn div(x, y) = x / y; print div(y = 2, x = 1); print 1 / 0; print sum(1, 2, 3);",
+        r"print (\(a, b) -> a * b)(2, 3); print (\(a) -> a)(1, 2); print |-2| + -1;",
+        "fn f(x) = print x * 2; print (print 1) + f(2);",
+        "fn f(x, y) = x - y; print f(y = print 1, x = print 2);",
+        "fn f(x) = f(x) + 1; print f(1); print error(\"stop\"); print 2;",
+    ] {
+        let source_program = SourceProgram::new(&db, source_text.to_string());
+        let program = parse_statements(&db, source_program);
+        let options = EvalOptions {
+            max_depth: 10,
+            ..EvalOptions::default()
+        };
+        let mut stepper = Stepper::new(&db, program, options);
+        while !matches!(stepper.step(), StepResult::Done) {}
+        let expected = eval_program(&db, program, options);
+        assert_eq!(stepper.output(), expected.lines, "{source_text}");
+        let diagnostics = eval_program::accumulated::<Diagnostics>(&db, program, options);
+        let messages = |diagnostics: &[Diagnostic]| {
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.message.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages(stepper.diagnostics()),
+            messages(&diagnostics),
+            "{source_text}"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Timing of the compiler's phases (`--time`).
+
+use std::fmt::Write;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+/// A phase of compiling a file, and what it took.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Phase {
+    pub name: &'static str,
+    pub time: Duration,
+    /// The queries executed, rather than reused, during the phase.
+    pub queries: usize,
+}
+
+/// Measures the phases run with `time`. The time comes from a clock which
+/// can be replaced in tests, and the queries executed are counted from the
+/// database's events (see `event_counter`).
+pub struct Timer {
+    /// The time since some fixed point.
+    clock: Box<dyn Fn() -> Duration>,
+    executed: Arc<AtomicUsize>,
+    phases: Vec<Phase>,
+}
+
+impl Timer {
+    pub fn new(clock: impl Fn() -> Duration + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+            executed: Default::default(),
+            phases: vec![],
+        }
+    }
+
+    /// A timer measuring wall-clock time.
+    pub fn system() -> Self {
+        let start = Instant::now();
+        Self::new(move || start.elapsed())
+    }
+
+    /// A callback for `DatabaseBuilder::on_event` counting the events which
+    /// pass the database's log filter. With the default filter, these are
+    /// the queries executed.
+    pub fn event_counter(&self) -> impl Fn(&salsa::Event) + Send + Sync + 'static {
+        let executed = self.executed.clone();
+        move |_| {
+            executed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Runs `f` as the phase `name`.
+    pub fn time<T>(&mut self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let executed = self.executed.load(Ordering::Relaxed);
+        let start = (self.clock)();
+        let result = f();
+        self.phases.push(Phase {
+            name,
+            time: (self.clock)() - start,
+            queries: self.executed.load(Ordering::Relaxed) - executed,
+        });
+        result
+    }
+
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    /// Renders the phases timed so far as a table, and starts over.
+    pub fn take_report(&mut self) -> String {
+        let mut report = format!("{:<12} {:>10} {:>8}\n", "phase", "time", "queries");
+        for phase in self.phases.drain(..) {
+            let millis = phase.time.as_secs_f64() * 1000.0;
+            let time = format!("{millis:.3}ms");
+            writeln!(report, "{:<12} {time:>10} {:>8}", phase.name, phase.queries).unwrap();
+        }
+        report
+    }
+}
+
+#[test]
+fn report_phases() {
+    use crate::db::Database;
+    use crate::ir::SourceProgram;
+    use std::cell::Cell;
+
+    // Each reading of the clock is 1.5ms after the previous one.
+    let now = Cell::new(Duration::ZERO);
+    let mut timer = Timer::new(move || {
+        now.set(now.get() + Duration::from_micros(1500));
+        now.get()
+    });
+    let db = Database::builder().on_event(timer.event_counter()).build();
+    let source_program = SourceProgram::new(&db, "fn f(x) = x * 2; print f(1);".to_string());
+    let program = timer.time("parse", || {
+        crate::parser::parse_statements(&db, source_program)
+    });
+    timer.time("type check", || {
+        crate::compile::compile_diagnostics(&db, source_program)
+    });
+    timer.time("compile", || {
+        crate::bytecode::compile_program_bytecode(&db, program)
+    });
+    let names: Vec<_> = timer.phases().iter().map(|phase| phase.name).collect();
+    assert_eq!(names, ["parse", "type check", "compile"]);
+    assert!(timer.phases().iter().all(|phase| phase.queries > 0));
+
+    let report = timer.take_report();
+    let rows: Vec<_> = report
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .collect();
+    assert_eq!(rows[0], ["phase", "time", "queries"]);
+    assert_eq!(rows[1][..2], ["parse", "1.500ms"]);
+    assert_eq!(rows[2][..3], ["type", "check", "1.500ms"]);
+    assert_eq!(rows[3][..2], ["compile", "1.500ms"]);
+    assert!(timer.phases().is_empty());
+}
@@ -1,24 +1,153 @@
+use crate::builtins::find_builtin;
 use crate::ir::{
-    Diagnostic, Diagnostics, Expression, Function, FunctionId, Program, Span, StatementData,
-    VariableId,
+    CallArg, DefIdData, Diagnostic, Diagnostics, ExprArena, ExprId, ExpressionData, Function,
+    FunctionId, ItemText, Op, Program, Span, StatementData, VariableId,
 };
+use crate::parser::{parse_item_statements, statement_items};
+use crate::signature::builtin_signature;
 use derive_new::new;
 #[cfg(test)]
 use expect_test::expect;
+use std::fmt;
 
 // ANCHOR: parse_statements
 #[salsa::tracked]
 pub fn type_check_program(db: &dyn crate::Db, program: Program) {
+    check_duplicate_definitions(db, program);
     for function in program.functions(db) {
         type_check_function(db, *function, program)
-        //            StatementData::Print(e) => CheckExpression::new(db, program, &[]).check(e),
     }
+    // The statements are checked item by item, in each file they come from.
+    let mut files = vec![];
+    for statement in program.statements(db) {
+        if let DefIdData::File(file) = statement.span.id.data(db) {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    for file in files {
+        for item in statement_items(db, file) {
+            type_check_statements(db, program, item.text);
+        }
+    }
+}
+
+/// Type-checks the top-level statements of `item`, a part of a file of the
+/// program which isn't a function. As for `parse_item_statements`, its
+/// diagnostics are relative to the item, so that this only runs again when
+/// the item's text changes, rather than on every edit of the file.
+#[salsa::tracked]
+pub fn type_check_statements(db: &dyn crate::Db, program: Program, item: ItemText) {
+    let Some((statements, _)) = parse_item_statements(db, item) else {
+        return;
+    };
+    for statement in statements {
+        match statement.data {
+            StatementData::Print(expression) => {
+                CheckExpression::new(db, program, &statement.arena, &[]).check(expression);
+            }
+        }
+    }
+}
+
+/// Reports functions defined more than once. These are the only diagnostics
+/// of the type checker which don't come from a single function.
+#[salsa::tracked]
+pub fn check_duplicate_definitions(db: &dyn crate::Db, program: Program) {
+    for (function, first) in redefinitions(db, program) {
+        let span = function.data(db).name_span;
+        Diagnostics::push(
+            db,
+            Diagnostic::new(
+                span.start,
+                span.end,
+                format!(
+                    "the function `{}` is defined more than once",
+                    function.name(db).text(db)
+                ),
+            )
+            .with_related(first.data(db).name_span, "first defined here".to_string()),
+        );
+    }
+}
+
+/// The functions with the same name as an earlier one, each with the first
+/// definition of the name, in the order `check_duplicate_definitions`
+/// reports them.
+pub(crate) fn redefinitions(db: &dyn crate::Db, program: Program) -> Vec<(Function, Function)> {
+    let functions = program.functions(db);
+    functions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, function)| {
+            let name = function.name(db);
+            let first = functions[..i].iter().find(|f| f.name(db) == name)?;
+            Some((*function, *first))
+        })
+        .collect()
 }
 
 #[salsa::tracked]
 pub fn type_check_function(db: &dyn crate::Db, function: Function, program: Program) {
-    eprintln!("type-checking {:?}", function.name(db).text(db));
-    CheckExpression::new(db, program, &function.data(db).args).check(&function.data(db).body)
+    let data = function.data(db);
+    let name = function.name(db).text(db);
+    if find_builtin(name).is_some() && !data.allows("shadow_builtin") {
+        Diagnostics::push(
+            db,
+            Diagnostic::warning(
+                data.name_span.start,
+                data.name_span.end,
+                format!("definition of `{name}` shadows a built-in function"),
+            ),
+        );
+    }
+    for (arg, span) in data.args.iter().zip(&data.arg_spans) {
+        let arg = arg.text(db);
+        if find_builtin(arg).is_some() && !data.allows("shadow_builtin") {
+            Diagnostics::push(
+                db,
+                Diagnostic::warning(
+                    span.start,
+                    span.end,
+                    format!("parameter `{arg}` of `{name}` shadows a built-in function"),
+                ),
+            );
+        }
+    }
+    expression_types(db, function, program);
+}
+
+/// The inferred type of every expression in the function body, in source
+/// order. Inference reports its diagnostics as a side effect, so this is
+/// also what type-checks the body.
+#[salsa::tracked(return_ref)]
+pub fn expression_types(
+    db: &dyn crate::Db,
+    function: Function,
+    program: Program,
+) -> Vec<(Span, Type)> {
+    let data = function.data(db);
+    let mut check = CheckExpression::new(db, program, data.arena(db), &data.args);
+    check.check(data.body);
+    let mut types = check.types;
+    // Expressions are recorded after their children; put enclosing
+    // expressions first.
+    types.sort_by_key(|(span, _)| (span.start, std::cmp::Reverse(span.end)));
+    types
+}
+
+/// Type-checks the expression `id` of `arena` outside of any function, which
+/// may call the program's functions. Returns whether there were no errors.
+pub fn check_expression(
+    db: &dyn crate::Db,
+    program: Program,
+    arena: &ExprArena,
+    id: ExprId,
+) -> bool {
+    let mut check = CheckExpression::new(db, program, arena, &[]);
+    check.check(id);
+    !check.failed
 }
 
 #[salsa::tracked]
@@ -30,40 +159,224 @@ pub fn find_function(db: &dyn crate::Db, program: Program, name: FunctionId) ->
         .next()
 }
 
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub enum Type {
+    Number,
+    Bool,
+    /// The type of `error(...)`, which never produces a value. It's
+    /// accepted wherever a value is expected.
+    Never,
+    /// The type of an expression which failed to check. Operations on it
+    /// aren't reported, to avoid cascading errors.
+    Unknown,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::Bool => write!(f, "bool"),
+            Type::Never => write!(f, "never"),
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct OpTypeError {
+    pub op: Op,
+    pub lhs: Type,
+    pub rhs: Type,
+}
+
+/// The operand types each operator accepts, and the resulting type.
+const OP_TYPES: &[(Op, Type, Type, Type)] = &[
+    (Op::Add, Type::Number, Type::Number, Type::Number),
+    (Op::Subtract, Type::Number, Type::Number, Type::Number),
+    (Op::Multiply, Type::Number, Type::Number, Type::Number),
+    (Op::Divide, Type::Number, Type::Number, Type::Number),
+];
+
+pub fn check_op(op: Op, lhs: Type, rhs: Type) -> Result<Type, OpTypeError> {
+    if lhs == Type::Unknown || rhs == Type::Unknown {
+        return Ok(Type::Unknown);
+    }
+    if lhs == Type::Never || rhs == Type::Never {
+        return Ok(Type::Never);
+    }
+    OP_TYPES
+        .iter()
+        .find(|&&(o, l, r, _)| (o, l, r) == (op, lhs, rhs))
+        .map(|&(_, _, _, result)| result)
+        .ok_or(OpTypeError { op, lhs, rhs })
+}
+
 #[derive(new)]
 struct CheckExpression<'w> {
     db: &'w dyn crate::Db,
     program: Program,
+    arena: &'w ExprArena,
     names_in_scope: &'w [VariableId],
+    /// The variables bound by the enclosing `let`s, innermost last, with the
+    /// types of their values. They shadow `names_in_scope`.
+    #[new(default)]
+    let_bindings: Vec<(VariableId, Type)>,
+    #[new(default)]
+    types: Vec<(Span, Type)>,
+    /// Whether an error was reported.
+    #[new(default)]
+    failed: bool,
 }
 
 impl CheckExpression<'_> {
-    fn check(&self, expression: &Expression) {
+    fn check(&mut self, id: ExprId) -> Type {
+        let ty = self.infer(id);
+        self.types.push((self.arena[id].span, ty));
+        ty
+    }
+
+    fn infer(&mut self, id: ExprId) -> Type {
+        let arena = self.arena;
+        let expression = &arena[id];
         match &expression.data {
-            crate::ir::ExpressionData::Op(left, _, right) => {
-                self.check(left);
-                self.check(right);
+            ExpressionData::Op(left, op, right) => {
+                let lhs = self.check(*left);
+                let rhs = self.check(*right);
+                match check_op(*op, lhs, rhs) {
+                    Ok(ty) => ty,
+                    Err(error) => {
+                        let span = expression.span;
+                        self.failed = true;
+                        Diagnostics::push(
+                            self.db,
+                            Diagnostic::new(
+                                span.start,
+                                span.end,
+                                format!(
+                                    "cannot apply `{}` to {} and {}",
+                                    error.op, error.lhs, error.rhs
+                                ),
+                            )
+                            .with_related(arena[*left].span, format!("this is a {}", error.lhs))
+                            .with_related(arena[*right].span, format!("this is a {}", error.rhs)),
+                        );
+                        Type::Unknown
+                    }
+                }
+            }
+            ExpressionData::Neg(operand) | ExpressionData::Abs(operand) => {
+                match self.check(*operand) {
+                    Type::Number => Type::Number,
+                    Type::Bool => {
+                        self.report_error(
+                            expression.span,
+                            "expected a number, found a bool".to_string(),
+                        );
+                        Type::Unknown
+                    }
+                    Type::Never => Type::Never,
+                    Type::Unknown => Type::Unknown,
+                }
             }
-            crate::ir::ExpressionData::Number(_) => {}
-            crate::ir::ExpressionData::Variable(v) => {
-                if !self.names_in_scope.contains(v) {
+            ExpressionData::Number(_) => Type::Number,
+            ExpressionData::Bool(_) => Type::Bool,
+            ExpressionData::Variable(v) => {
+                if let Some((_, ty)) = self.let_bindings.iter().rev().find(|(name, _)| name == v) {
+                    *ty
+                } else if self.names_in_scope.contains(v) {
+                    Type::Number
+                } else if self.is_function_name(v.text(self.db)) {
+                    self.report_error(
+                        expression.span,
+                        format!(
+                            "'{}' is a function; did you mean to call it?",
+                            v.text(self.db)
+                        ),
+                    );
+                    Type::Unknown
+                } else {
                     self.report_error(
                         expression.span,
                         format!("the variable `{}` is not declared", v.text(self.db)),
                     );
+                    Type::Unknown
                 }
             }
-            crate::ir::ExpressionData::Call(f, args) => {
-                if self.find_function(*f).is_none() {
+            ExpressionData::Call(f, args) => {
+                let mut ty = Type::Number;
+                // User definitions take precedence over builtins of the same name.
+                if let Some(function) = self.find_function(*f) {
+                    let data = function.data(self.db);
+                    if data.args.len() != args.len() {
+                        let span = expression.span;
+                        self.failed = true;
+                        Diagnostics::push(
+                            self.db,
+                            Diagnostic::new(
+                                span.start,
+                                span.end,
+                                arity_mismatch(self.db, *f, data.args.len(), args.len()),
+                            )
+                            .with_related(
+                                data.name_span,
+                                format!("`{}` is defined here", f.text(self.db)),
+                            ),
+                        );
+                    } else if let Err((span, message)) =
+                        bind_arguments(self.db, *f, &data.args, arena, args)
+                    {
+                        self.report_error(span, message);
+                    }
+                } else if let Some(signature) = builtin_signature(f.text(self.db)) {
+                    if !signature.accepts(args.len()) {
+                        self.report_error(
+                            expression.span,
+                            arity_mismatch(self.db, *f, signature.arity(), args.len()),
+                        );
+                    }
+                    if let Err((span, message)) = builtin_arguments(self.db, *f, arena, args) {
+                        self.report_error(span, message);
+                    }
+                } else {
                     self.report_error(
                         expression.span,
                         format!("the function `{}` is not declared", f.text(self.db)),
                     );
+                    ty = Type::Unknown;
                 }
+                // Arguments are checked even if the callee doesn't resolve.
                 for arg in args {
-                    self.check(arg);
+                    self.check(arg.value);
                 }
+                ty
             }
+            ExpressionData::Lambda(params, body, args) => {
+                if params.len() != args.len() {
+                    self.report_error(
+                        expression.span,
+                        lambda_arity_mismatch(params.len(), args.len()),
+                    );
+                }
+                for arg in args {
+                    self.check(*arg);
+                }
+                // The body is checked with only the parameters in scope.
+                let mut body_check = CheckExpression::new(self.db, self.program, arena, params);
+                let ty = body_check.check(*body);
+                self.types.extend(body_check.types);
+                self.failed |= body_check.failed;
+                ty
+            }
+            ExpressionData::Let(name, value, body) => {
+                // The value is checked before the name is bound.
+                let value_ty = self.check(*value);
+                self.let_bindings.push((*name, value_ty));
+                let ty = self.check(*body);
+                self.let_bindings.pop();
+                ty
+            }
+            ExpressionData::Print(operand) => self.check(*operand),
+            ExpressionData::Error(_) => Type::Never,
         }
     }
 
@@ -71,11 +384,150 @@ impl CheckExpression<'_> {
         find_function(self.db, self.program, f)
     }
 
-    fn report_error(&self, span: Span, message: String) {
+    /// Whether the program defines a function named `name`, for a variable
+    /// which is really a function used without calling it.
+    fn is_function_name(&self, name: &str) -> bool {
+        self.program
+            .functions(self.db)
+            .iter()
+            .any(|function| function.name(self.db).text(self.db) == name)
+    }
+
+    fn report_error(&mut self, span: Span, message: String) {
+        self.failed = true;
         Diagnostics::push(self.db, Diagnostic::new(span.start, span.end, message));
     }
 }
 
+/// Binds the arguments of a call of `f` to its parameters `params`, of
+/// which there are as many as arguments: positional arguments to the first
+/// parameters, and named ones to the parameter of that name. Returns the
+/// argument of each parameter, or the span and message of the first
+/// argument which can't be bound.
+pub fn bind_arguments(
+    db: &dyn crate::Db,
+    f: FunctionId,
+    params: &[VariableId],
+    arena: &ExprArena,
+    args: &[CallArg],
+) -> Result<Vec<ExprId>, (Span, String)> {
+    let mut bound = vec![None; params.len()];
+    let mut named = false;
+    for (index, arg) in args.iter().enumerate() {
+        let span = arena[arg.value].span;
+        let param = match arg.name {
+            None if named => {
+                let message = "a positional argument can't follow a named one".to_string();
+                return Err((span, message));
+            }
+            None => index,
+            Some(name) => {
+                named = true;
+                params
+                    .iter()
+                    .position(|param| *param == name)
+                    .ok_or_else(|| {
+                        let message = format!(
+                            "the function `{}` has no parameter named `{}`",
+                            f.text(db),
+                            name.text(db)
+                        );
+                        (span, message)
+                    })?
+            }
+        };
+        if bound[param].replace(arg.value).is_some() {
+            let message = format!(
+                "the argument `{}` is given more than once",
+                params[param].text(db)
+            );
+            return Err((span, message));
+        }
+    }
+    // With as many arguments as parameters, none bound twice, every
+    // parameter is bound.
+    Ok(bound.into_iter().map(Option::unwrap).collect())
+}
+
+/// For each parameter, the index in `args` of the argument `bound` to it by
+/// `bind_arguments`. Arguments are evaluated in the order they're written,
+/// then passed in the order of the parameters.
+pub fn argument_order(bound: &[ExprId], args: &[CallArg]) -> Vec<usize> {
+    bound
+        .iter()
+        .map(|&id| args.iter().position(|arg| arg.value == id).unwrap())
+        .collect()
+}
+
+/// The arguments of a call of the builtin `f`, which only takes positional
+/// ones, or the span and message of the first named one.
+pub fn builtin_arguments(
+    db: &dyn crate::Db,
+    f: FunctionId,
+    arena: &ExprArena,
+    args: &[CallArg],
+) -> Result<Vec<ExprId>, (Span, String)> {
+    match args.iter().find(|arg| arg.name.is_some()) {
+        Some(arg) => Err((
+            arena[arg.value].span,
+            format!(
+                "the built-in function `{}` has no named parameters",
+                f.text(db)
+            ),
+        )),
+        None => Ok(args.iter().map(|arg| arg.value).collect()),
+    }
+}
+
+/// The arguments of a call of `f` in `program`, in the order of the
+/// callee's parameters, for the backends. A call which doesn't resolve, or
+/// has the wrong number of arguments, keeps the order they're written in.
+pub fn call_arguments(
+    db: &dyn crate::Db,
+    program: Program,
+    f: FunctionId,
+    arena: &ExprArena,
+    args: &[CallArg],
+) -> Result<Vec<ExprId>, (Span, String)> {
+    match find_function(db, program, f) {
+        Some(function) if function.data(db).args.len() == args.len() => {
+            bind_arguments(db, f, &function.data(db).args, arena, args)
+        }
+        None if find_builtin(f.text(db)).is_some() => builtin_arguments(db, f, arena, args),
+        _ => Ok(args.iter().map(|arg| arg.value).collect()),
+    }
+}
+
+pub fn arity_mismatch(db: &dyn crate::Db, f: FunctionId, arity: usize, given: usize) -> String {
+    format!(
+        "the function `{}` expects {}",
+        f.text(db),
+        expected_arguments(arity, given)
+    )
+}
+
+pub fn lambda_arity_mismatch(arity: usize, given: usize) -> String {
+    format!("the lambda expects {}", expected_arguments(arity, given))
+}
+
+fn expected_arguments(arity: usize, given: usize) -> String {
+    format!(
+        "{}, but {} {} given",
+        plural(arity, "argument"),
+        given,
+        if given == 1 { "was" } else { "were" },
+    )
+}
+
+/// Formats `count` followed by `noun`, pluralized if needed.
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{count} {noun}")
+    } else {
+        format!("{count} {noun}s")
+    }
+}
+
 /// Create a new database with the given source text and parse the result.
 /// Returns the statements and the diagnostics generated.
 #[cfg(test)]
@@ -85,6 +537,7 @@ fn check_string(
     edits: &[(&str, expect_test::Expect, expect_test::Expect)],
 ) {
     use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+    use salsa::DebugWithDb;
 
     // Create the database
     let mut db = Database::default().enable_logging();
@@ -96,9 +549,8 @@ fn check_string(
     let program = parse_statements(&db, source_program);
 
     // Read out any diagnostics
-    expected_diagnostics.assert_debug_eq(&type_check_program::accumulated::<Diagnostics>(
-        &db, program,
-    ));
+    let diagnostics = type_check_program::accumulated::<Diagnostics>(&db, program);
+    expected_diagnostics.assert_debug_eq(&diagnostics.debug(&db as &dyn crate::Db));
 
     // Clear logs
     db.take_logs();
@@ -109,10 +561,9 @@ fn check_string(
             .set_text(&mut db)
             .to(new_source_text.to_string());
         let program = parse_statements(&db, source_program);
-        expected_diagnostics.assert_debug_eq(&type_check_program::accumulated::<Diagnostics>(
-            &db, program,
-        ));
-        expected_logs.assert_debug_eq(&db.take_logs());
+        let diagnostics = type_check_program::accumulated::<Diagnostics>(&db, program);
+        expected_diagnostics.assert_debug_eq(&diagnostics.debug(&db as &dyn crate::Db));
+        expected_logs.assert_debug_eq(&db.take_logs().executed_queries());
     }
 }
 
@@ -137,11 +588,53 @@ fn check_bad_variable_in_program() {
                     start: 6,
                     end: 8,
                     message: "the variable `a` is not declared",
+                    severity: Error,
+                    related: [],
                 },
                 Diagnostic {
                     start: 10,
                     end: 11,
                     message: "the variable `b` is not declared",
+                    severity: Error,
+                    related: [],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_function_used_as_variable() {
+    check_string(
+        "fn area_rectangle(w, h) = w * h;\nprint area_rectangle + 1;",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 6,
+                    end: 20,
+                    message: "'area_rectangle' is a function; did you mean to call it?",
+                    severity: Error,
+                    related: [],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_function_used_as_variable_in_function() {
+    check_string(
+        "fn area_rectangle(w, h) = w * h;\nfn f() = area_rectangle + 1;",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 9,
+                    end: 23,
+                    message: "'area_rectangle' is a function; did you mean to call it?",
+                    severity: Error,
+                    related: [],
                 },
             ]
         "#]],
@@ -159,6 +652,8 @@ fn check_bad_function_in_program() {
                     start: 6,
                     end: 11,
                     message: "the function `a` is not declared",
+                    severity: Error,
+                    related: [],
                 },
             ]
         "#]],
@@ -179,6 +674,8 @@ fn check_bad_variable_in_function() {
                     start: 33,
                     end: 47,
                     message: "the variable `b` is not declared",
+                    severity: Error,
+                    related: [],
                 },
             ]
         "#]],
@@ -199,11 +696,15 @@ fn check_bad_function_in_function() {
                     start: 29,
                     end: 39,
                     message: "the function `add_two` is not declared",
+                    severity: Error,
+                    related: [],
                 },
                 Diagnostic {
                     start: 42,
                     end: 56,
                     message: "the variable `b` is not declared",
+                    severity: Error,
+                    related: [],
                 },
             ]
         "#]],
@@ -225,6 +726,8 @@ fn fix_bad_variable_in_function() {
                     start: 32,
                     end: 46,
                     message: "the variable `b` is not declared",
+                    severity: Error,
+                    related: [],
                 },
             ]
         "#]],
@@ -239,10 +742,342 @@ fn fix_bad_variable_in_function() {
             "#]],
             expect![[r#"
                 [
-                    "Event: Event { runtime_id: RuntimeId { counter: 0 }, kind: WillExecute { database_key: parse_statements(0) } }",
-                    "Event: Event { runtime_id: RuntimeId { counter: 0 }, kind: WillExecute { database_key: type_check_function(0) } }",
+                    "parse_statements(0)",
+                    "type_check_function(0)",
+                    "expression_types(0)",
                 ]
             "#]],
         )],
     );
 }
+
+#[test]
+fn check_arguments_of_unknown_function() {
+    check_string(
+        "fn f() = nosuch(undefined_var + 1);",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 9,
+                    end: 34,
+                    message: "the function `nosuch` is not declared",
+                    severity: Error,
+                    related: [],
+                },
+                Diagnostic {
+                    start: 16,
+                    end: 29,
+                    message: "the variable `undefined_var` is not declared",
+                    severity: Error,
+                    related: [],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_arity_mismatch() {
+    check_string(
+        "fn f(a) = a; fn g() = f(1, 2);",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 9,
+                    end: 16,
+                    message: "the function `f` expects 1 argument, but 2 were given",
+                    severity: Error,
+                    related: [
+                        (
+                            Span(f, 3..4),
+                            "`f` is defined here",
+                        ),
+                    ],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_named_arguments() {
+    check_string(
+        "fn f(a, b) = a - b; fn g(x) = f(b = x, a = 1) + f(x, b = 2) + f(a = x, a = 1) + sqrt(x = x);",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 55,
+                    end: 56,
+                    message: "the argument `a` is given more than once",
+                    severity: Error,
+                    related: [],
+                },
+                Diagnostic {
+                    start: 69,
+                    end: 70,
+                    message: "the built-in function `sqrt` has no named parameters",
+                    severity: Error,
+                    related: [],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_duplicate_function() {
+    check_string(
+        "fn f() = 1; fn f() = 2;",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 3,
+                    end: 4,
+                    message: "the function `f` is defined more than once",
+                    severity: Error,
+                    related: [
+                        (
+                            Span(f, 3..4),
+                            "first defined here",
+                        ),
+                    ],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_shadowed_builtin() {
+    check_string(
+        "fn sqrt(x) = x; fn f() = sqrt(1);",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 3,
+                    end: 7,
+                    message: "definition of `sqrt` shadows a built-in function",
+                    severity: Warning,
+                    related: [],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_parameter_shadowing_builtin() {
+    check_string(
+        "fn f(a, sqrt) = sqrt + a; fn g(root) = root + 1;",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 8,
+                    end: 12,
+                    message: "parameter `sqrt` of `f` shadows a built-in function",
+                    severity: Warning,
+                    related: [],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_shadowed_builtin_uses_user_arity() {
+    check_string(
+        "fn sqrt(x, y) = x; fn f() = sqrt(1, 2) + sqrt(1);",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 3,
+                    end: 7,
+                    message: "definition of `sqrt` shadows a built-in function",
+                    severity: Warning,
+                    related: [],
+                },
+                Diagnostic {
+                    start: 22,
+                    end: 29,
+                    message: "the function `sqrt` expects 2 arguments, but 1 was given",
+                    severity: Error,
+                    related: [
+                        (
+                            Span(sqrt, 3..7),
+                            "`sqrt` is defined here",
+                        ),
+                    ],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_allowed_builtin_shadowing() {
+    check_string(
+        "#[allow(shadow_builtin)] fn sqrt(x) = x; fn f(x) = sqrt(x) + abs(x);",
+        expect![[r#"
+            []
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_builtin_call() {
+    check_string(
+        "fn f(x) = sqrt(x) + min(x) + sum() + sum(x, x, x);",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 20,
+                    end: 26,
+                    message: "the function `min` expects 2 arguments, but 1 was given",
+                    severity: Error,
+                    related: [],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_builtin_arity() {
+    check_string(
+        "fn f(x) = sqrt(1, 2) + min(1);",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 10,
+                    end: 20,
+                    message: "the function `sqrt` expects 1 argument, but 2 were given",
+                    severity: Error,
+                    related: [],
+                },
+                Diagnostic {
+                    start: 23,
+                    end: 29,
+                    message: "the function `min` expects 2 arguments, but 1 was given",
+                    severity: Error,
+                    related: [],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_op_table() {
+    for op in [Op::Add, Op::Subtract, Op::Multiply, Op::Divide] {
+        assert_eq!(check_op(op, Type::Number, Type::Number), Ok(Type::Number));
+        assert_eq!(check_op(op, Type::Unknown, Type::Number), Ok(Type::Unknown));
+        assert_eq!(check_op(op, Type::Number, Type::Unknown), Ok(Type::Unknown));
+    }
+}
+
+#[test]
+fn check_arithmetic() {
+    check_string(
+        "fn f(a, b) = (a + b) * 2 / sqrt(a) - max(a, b);",
+        expect![[r#"
+            []
+        "#]],
+        &[],
+    );
+}
+
+#[test]
+fn check_unknown_operand_does_not_cascade() {
+    check_string(
+        "fn f(a) = a + (b * 2);",
+        expect![[r#"
+            [
+                Diagnostic {
+                    start: 15,
+                    end: 16,
+                    message: "the variable `b` is not declared",
+                    severity: Error,
+                    related: [],
+                },
+            ]
+        "#]],
+        &[],
+    );
+}
+
+#[cfg(test)]
+fn types_string(source_text: &str) -> String {
+    use crate::{db::Database, ir::SourceProgram, parser::parse_statements};
+
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let program = parse_statements(&db, source_program);
+    let mut output = String::new();
+    for function in program.functions(&db) {
+        for (span, ty) in expression_types(&db, *function, program) {
+            output += &format!("{}..{}: {}\n", span.start, span.end, ty);
+        }
+    }
+    output
+}
+
+#[test]
+fn expression_types_in_source_order() {
+    let expected = expect![[r#"
+        10..31: unknown
+        10..21: number
+        10..11: number
+        14..21: number
+        19..20: number
+        24..31: unknown
+        25..26: unknown
+        29..30: number
+    "#]];
+    expected.assert_eq(&types_string("fn f(a) = a + sqrt(a) + (b * 2);"));
+}
+
+#[test]
+fn error_is_accepted_as_any_value() {
+    let expected = expect![[r#"
+        10..24: never
+        10..11: number
+        14..24: never
+        10..25: never
+        10..21: never
+        11..21: never
+        24..25: number
+    "#]];
+    expected.assert_eq(&types_string(
+        r#"fn f(a) = a + error("x"); fn g(a) = -error("y") * a;"#,
+    ));
+}
+
+#[test]
+fn bools_are_not_numbers() {
+    let expected = expect![[r#"
+        9..13: bool
+        10..20: unknown
+        10..16: unknown
+        11..16: bool
+        19..20: number
+    "#]];
+    expected.assert_eq(&types_string("fn f() = true; fn g(a) = -false + a;"));
+    assert_eq!(
+        check_op(Op::Add, Type::Bool, Type::Number),
+        Err(OpTypeError {
+            op: Op::Add,
+            lhs: Type::Bool,
+            rhs: Type::Number
+        })
+    );
+}
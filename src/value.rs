@@ -0,0 +1,116 @@
+//! The values programs compute at runtime.
+
+use std::fmt;
+
+use ordered_float::OrderedFloat;
+
+use crate::eval::format_number;
+use crate::ir::Op;
+
+/// A runtime value. The evaluator and the VM are written against this type,
+/// so that adding a kind of value only takes new cases here and where values
+/// are inspected.
+#[derive(Eq, PartialEq, Clone, Hash, Debug)]
+pub enum Value {
+    Number(OrderedFloat<f64>),
+    Bool(bool),
+}
+
+impl Value {
+    /// The name of the value's type in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(n.into_inner()),
+            Value::Bool(_) => None,
+        }
+    }
+}
+
+/// A value as the key of a cache of results. `Value`'s own equality treats
+/// `0` and `-0` as the same number, but a function can tell them apart
+/// (`1 / -0` is `-inf`), so here numbers are only equal if their bits are.
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub enum ValueKey {
+    Number(u64),
+    Bool(bool),
+}
+
+impl Value {
+    pub fn key(&self) -> ValueKey {
+        match self {
+            Value::Number(n) => ValueKey::Number(n.into_inner().to_bits()),
+            Value::Bool(b) => ValueKey::Bool(*b),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n.into())
+    }
+}
+
+/// Formats the value as `print` does.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", format_number(n.into_inner())),
+            Value::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+/// The operands of a binary operator, by the kinds of values it applies to.
+#[derive(PartialEq, Debug)]
+pub enum Operands {
+    Numbers(f64, f64),
+}
+
+/// Finds how `op` applies to a pair of values, or explains why it doesn't,
+/// e.g. "cannot apply `+` to number and string".
+pub fn dispatch_op(op: Op, left: &Value, right: &Value) -> Result<Operands, String> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => {
+            Ok(Operands::Numbers(l.into_inner(), r.into_inner()))
+        }
+        _ => Err(format!(
+            "cannot apply `{op}` to {} and {}",
+            left.type_name(),
+            right.type_name()
+        )),
+    }
+}
+
+#[test]
+fn dispatch_ops() {
+    for op in [Op::Add, Op::Subtract, Op::Multiply, Op::Divide] {
+        assert_eq!(
+            dispatch_op(op, &Value::from(3.0), &Value::from(-0.5)),
+            Ok(Operands::Numbers(3.0, -0.5))
+        );
+    }
+    let nan = Value::from(f64::NAN);
+    assert_eq!(dispatch_op(Op::Add, &nan, &nan).map(|_| ()), Ok(()));
+    assert_eq!(
+        dispatch_op(Op::Add, &Value::Bool(true), &Value::from(1.0)),
+        Err("cannot apply `+` to bool and number".to_string())
+    );
+}
+
+#[test]
+fn display_values() {
+    let values = [1.0, 2.5, -0.0, f64::NAN, f64::INFINITY].map(|n| Value::from(n).to_string());
+    assert_eq!(values, ["1", "2.5", "0", "nan", "inf"]);
+    assert_eq!(Value::from(1.0).type_name(), "number");
+    assert_eq!(Value::from(1.5).as_number(), Some(1.5));
+    assert_eq!(Value::Bool(false).to_string(), "false");
+    assert_eq!(Value::Bool(true).type_name(), "bool");
+    assert_eq!(Value::Bool(true).as_number(), None);
+}
@@ -0,0 +1,235 @@
+//! Several files compiled together as one program, so that a statement in
+//! one file can call a function defined in another.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::{
+    compile::{statement_diagnostics, Pass},
+    ir::{Diagnostic, Diagnostics, Function, Program, SourceProgram},
+    parser::parse_statements,
+    type_check::{check_duplicate_definitions, redefinitions, type_check_function},
+};
+
+/// The files compiled together, by path, in the order they were given.
+#[salsa::input]
+pub struct Workspace {
+    #[return_ref]
+    pub files: Vec<(PathBuf, SourceProgram)>,
+}
+
+/// The functions and statements of every file in the workspace, in order.
+/// A function with the name of one from an earlier file is left out, and
+/// reported by `workspace_diagnostics`.
+#[salsa::tracked]
+pub fn workspace_program(db: &dyn crate::Db, workspace: Workspace) -> Program {
+    let mut statements = vec![];
+    for (_, source) in workspace.files(db) {
+        statements.extend(parse_statements(db, *source).statements(db).iter().cloned());
+    }
+    Program::new(db, merge_functions(db, workspace).functions, statements)
+}
+
+struct MergedFunctions {
+    functions: Vec<Function>,
+    /// The index of the file each function, left out or not, comes from. A
+    /// function of an included file comes from the file including it.
+    files: HashMap<Function, usize>,
+    /// Functions left out because an earlier file has one of the same name,
+    /// with that one.
+    redefinitions: Vec<(Function, Function)>,
+}
+
+fn merge_functions(db: &dyn crate::Db, workspace: Workspace) -> MergedFunctions {
+    let mut merged = MergedFunctions {
+        functions: vec![],
+        files: HashMap::new(),
+        redefinitions: vec![],
+    };
+    for (index, (_, source)) in workspace.files(db).iter().enumerate() {
+        let earlier_files = merged.functions.len();
+        for function in parse_statements(db, *source).functions(db) {
+            merged.files.insert(*function, index);
+            let name = function.name(db);
+            let earlier = merged.functions[..earlier_files]
+                .iter()
+                .find(|earlier| earlier.name(db) == name);
+            match earlier {
+                // The same definition, from a file which the earlier one
+                // includes or is included by.
+                Some(earlier) if same_definition(db, *earlier, *function) => {}
+                Some(earlier) => merged.redefinitions.push((*function, *earlier)),
+                None => merged.functions.push(*function),
+            }
+        }
+    }
+    merged
+}
+
+fn same_definition(db: &dyn crate::Db, a: Function, b: Function) -> bool {
+    a.data(db).name_span.id == b.data(db).name_span.id
+}
+
+/// The diagnostics of compiling the workspace, with the file each is in, in
+/// the order of the files. Within a file they're in the order of
+/// `compile_diagnostics`, and as there, a workspace with parse errors isn't
+/// type checked.
+pub fn workspace_diagnostics(
+    db: &dyn crate::Db,
+    workspace: Workspace,
+) -> Vec<(SourceProgram, Diagnostic)> {
+    workspace_diagnostics_with(db, workspace, |function, program| {
+        type_check_function::accumulated::<Diagnostics>(db, function, program)
+    })
+}
+
+/// `workspace_diagnostics`, with the type errors of each function given by
+/// `check_function`, as for `compile_diagnostics_with`.
+pub fn workspace_diagnostics_with(
+    db: &dyn crate::Db,
+    workspace: Workspace,
+    mut check_function: impl FnMut(Function, Program) -> Vec<Diagnostic>,
+) -> Vec<(SourceProgram, Diagnostic)> {
+    let files = workspace.files(db);
+    let mut keyed = vec![];
+    for (index, (_, source)) in files.iter().enumerate() {
+        for diagnostic in parse_statements::accumulated::<Diagnostics>(db, *source) {
+            keyed.push(((index, Pass::Parse, diagnostic.start), diagnostic));
+        }
+    }
+    if !keyed.iter().any(|(_, diagnostic)| diagnostic.is_error()) {
+        let program = workspace_program(db, workspace);
+        let merged = merge_functions(db, workspace);
+        // Type errors are positioned relative to the function they're in,
+        // and are moved to where it is in its file.
+        let mut push = |function: Function, diagnostic: Diagnostic| {
+            let start = function.start(db);
+            let diagnostic = Diagnostic {
+                start: start + diagnostic.start,
+                end: start + diagnostic.end,
+                ..diagnostic
+            };
+            let key = (merged.files[&function], Pass::TypeCheck, diagnostic.start);
+            keyed.push((key, diagnostic));
+        };
+        for (function, earlier) in &merged.redefinitions {
+            let path = |function| files[merged.files[function]].0.display();
+            let span = function.data(db).name_span;
+            let message = format!(
+                "the function `{}` is defined in both `{}` and `{}`",
+                function.name(db).text(db),
+                path(earlier),
+                path(function),
+            );
+            let diagnostic = Diagnostic::new(span.start, span.end, message)
+                .with_related(earlier.data(db).name_span, "first defined here".to_string());
+            push(*function, diagnostic);
+        }
+        let duplicates = check_duplicate_definitions::accumulated::<Diagnostics>(db, program);
+        let redefined = redefinitions(db, program).into_iter().map(|(f, _)| f);
+        for (function, diagnostic) in redefined.zip(duplicates) {
+            push(function, diagnostic);
+        }
+        for function in program.functions(db) {
+            for diagnostic in check_function(*function, program) {
+                push(*function, diagnostic);
+            }
+        }
+        for (index, (_, source)) in files.iter().enumerate() {
+            for diagnostic in statement_diagnostics(db, program, *source) {
+                keyed.push(((index, Pass::TypeCheck, diagnostic.start), diagnostic));
+            }
+        }
+    }
+    keyed.sort_by_key(|(key, _)| *key);
+    keyed
+        .into_iter()
+        .map(|((index, ..), diagnostic)| (files[index].1, diagnostic))
+        .collect()
+}
+
+#[cfg(test)]
+fn two_files(db: &crate::db::Database, main: &str, lib: &str) -> Workspace {
+    let files = [("main.bn", main), ("lib.bn", lib)].map(|(path, text)| {
+        (
+            PathBuf::from(path),
+            SourceProgram::new(db, text.to_string()),
+        )
+    });
+    Workspace::new(db, files.to_vec())
+}
+
+#[cfg(test)]
+fn run_workspace(db: &crate::db::Database, workspace: Workspace) -> Vec<String> {
+    use crate::eval::{eval_program, EvalOptions};
+
+    let program = workspace_program(db, workspace);
+    eval_program(db, program, EvalOptions::default())
+        .lines
+        .iter()
+        .map(|line| line.text.clone())
+        .collect()
+}
+
+#[test]
+fn call_across_files() {
+    let db = crate::db::Database::default();
+    let workspace = two_files(&db, "print double(21);", "fn double(x) = x * 2;");
+    assert!(workspace_diagnostics(&db, workspace).is_empty());
+    assert_eq!(run_workspace(&db, workspace), ["42"]);
+}
+
+#[test]
+fn duplicate_across_files() {
+    let db = crate::db::Database::default();
+    let workspace = two_files(&db, "fn f() = 1; print f();", "fn f() = 2;");
+    let diagnostics: Vec<_> = workspace_diagnostics(&db, workspace)
+        .into_iter()
+        .map(|(source, diagnostic)| {
+            let span = diagnostic.start..diagnostic.end;
+            (source.text(&db).clone(), diagnostic.message, span)
+        })
+        .collect();
+    let expected = expect_test::expect![[r#"
+        [
+            (
+                "fn f() = 2;",
+                "the function `f` is defined in both `main.bn` and `lib.bn`",
+                3..4,
+            ),
+        ]
+    "#]];
+    expected.assert_debug_eq(&diagnostics);
+    // The first definition is the one called.
+    assert_eq!(run_workspace(&db, workspace), ["1"]);
+}
+
+#[test]
+fn statement_errors_in_their_file() {
+    let db = crate::db::Database::default();
+    let workspace = two_files(&db, "fn f() = 1;", "fn g() = 2;\nprint f() + x;");
+    let diagnostics = workspace_diagnostics(&db, workspace);
+    assert_eq!(diagnostics.len(), 1);
+    let (source, diagnostic) = &diagnostics[0];
+    assert_eq!(source.text(&db), "fn g() = 2;\nprint f() + x;");
+    assert_eq!(diagnostic.start..diagnostic.end, 24..25);
+}
+
+#[test]
+fn resolve_span_in_other_file() {
+    use crate::eval::{eval_program, EvalOptions};
+    use crate::render::resolve_span;
+
+    let db = crate::db::Database::default();
+    let workspace = two_files(&db, "print 1;", "fn f(x) = x;\nprint f(2) + 1;");
+    let program = workspace_program(&db, workspace);
+    let run = eval_program(&db, program, EvalOptions::default());
+    // Both statements start at different offsets of their own files.
+    let main = workspace.files(&db)[0].1;
+    let snippets: Vec<_> = run
+        .lines
+        .iter()
+        .map(|line| resolve_span(&db, main, line.span).text.clone())
+        .collect();
+    assert_eq!(snippets, ["print 1;", "print f(2) + 1;"]);
+}
@@ -0,0 +1,290 @@
+//! Runs the `banana` binary on files.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A path for a temporary file of the test `name`.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("banana-cli-{name}-{}.banana", std::process::id()))
+}
+
+/// A temporary file written by `write_file`, removed when dropped. It
+/// derefs to its path, as an argument.
+struct TempFile {
+    path: String,
+}
+
+impl std::ops::Deref for TempFile {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Writes `source_text` to a temporary file of the test `name`.
+fn write_file(name: &str, source_text: &str) -> TempFile {
+    let path = temp_path(name);
+    std::fs::write(&path, source_text).unwrap();
+    TempFile {
+        path: path.to_str().unwrap().to_string(),
+    }
+}
+
+/// Runs `banana` with `args`, writing `stdin` to its standard input.
+/// Returns what it printed to stdout and stderr, and its exit code.
+fn run(args: &[&str], stdin: &str) -> (String, String, i32) {
+    run_with_env(args, stdin, &[])
+}
+
+/// `run`, with the environment variables `env` set.
+fn run_with_env(args: &[&str], stdin: &str, env: &[(&str, &str)]) -> (String, String, i32) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_banana"))
+        .args(args)
+        .envs(env.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+        output.status.code().unwrap(),
+    )
+}
+
+#[test]
+fn min_severity_hides_warnings() {
+    let path = write_file("warnings", "fn sqrt(x) = x; print sqrt(4);");
+    let (_, stderr, _) = run(&[&path], "");
+    assert!(stderr.contains("shadows a built-in function"));
+
+    let (stdout, stderr, _) = run(&["--min-severity", "error", &path], "");
+    assert_eq!(stdout, "4\n");
+    assert!(!stderr.contains("shadows"));
+
+    // Only `--check` sets the exit code from the diagnostics: the hidden
+    // warnings don't fail it, and errors are shown even so.
+    let (_, stderr, code) = run(&["--check", "--min-severity", "error", &path], "");
+    assert_eq!((stderr.as_str(), code), ("", 0));
+    let path = write_file("warning-and-error", "fn sqrt(x) = y; print sqrt(4);");
+    let (_, stderr, code) = run(&["--check", "--min-severity", "error", &path], "");
+    assert!(!stderr.contains("shadows"), "{stderr}");
+    assert!(
+        stderr.contains("the variable `y` is not declared"),
+        "{stderr}"
+    );
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn file_over_size_limit() {
+    let path = write_file("max-file-size", "print 1; print 2;");
+    let (stdout, stderr, _) = run(&["--max-file-size", "8", &path], "");
+    assert!(
+        stderr.contains("the file is larger than the limit of 8 bytes"),
+        "{stderr}"
+    );
+    assert!(stdout.is_empty());
+}
+
+#[test]
+fn unreadable_file_among_others() {
+    let checked = write_file("checked", "fn f(x) = y; print 1;");
+    let missing = temp_path("missing");
+    let (stdout, stderr, _) = run(&[missing.to_str().unwrap(), &checked], "");
+    // The other file is still type checked, but nothing runs.
+    assert!(stderr.contains("cannot read"), "{stderr}");
+    assert!(
+        stderr.contains("the variable `y` is not declared"),
+        "{stderr}"
+    );
+    assert!(stdout.is_empty());
+}
+
+#[test]
+fn read_stdin() {
+    let (stdout, _, code) = run(&["-"], "print 1+2;");
+    assert_eq!((stdout.as_str(), code), ("3\n", 0));
+    // Without files, a program is read from stdin when it isn't a terminal.
+    assert_eq!(run(&[], "print 2;").0, "2\n");
+}
+
+#[test]
+fn stdin_among_files() {
+    let path = write_file("with-stdin", "fn double(x) = x * 2;");
+    let (stdout, _, code) = run(&[&path, "-"], "print double(4);");
+    assert_eq!((stdout.as_str(), code), ("8\n", 0));
+
+    // Diagnostics name standard input `<stdin>`.
+    let (_, stderr, _) = run(&[&path, "-"], "fn double(x) = x;");
+    assert!(stderr.contains("in both"), "{stderr}");
+    assert!(stderr.contains("`<stdin>`"), "{stderr}");
+}
+
+#[test]
+fn no_color() {
+    let path = write_file("no-color", "fn f(x) = y; print 1;");
+    let (_, stderr, _) = run_with_env(&[&path], "", &[("NO_COLOR", "1")]);
+    assert!(stderr.contains("error: the variable `y`"), "{stderr}");
+    assert!(!stderr.contains('\x1b'), "{stderr}");
+    let (_, stderr, _) = run(&["--no-color", &path], "");
+    assert!(!stderr.contains('\x1b'), "{stderr}");
+}
+
+#[test]
+fn fmt_in_place() {
+    let path = write_file("fmt", "fn f(x)=x*(1+2) ;\n// last\nprint f(1);");
+    let (_, _, code) = run(&["fmt", "--check", &path], "");
+    assert_eq!(code, 1);
+    let (_, _, code) = run(&["fmt", &path], "");
+    assert_eq!(code, 0);
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "fn f(x) = x * (1 + 2);\n// last\nprint f(1);\n"
+    );
+    let (stdout, _, code) = run(&["fmt", "--check", &path], "");
+    assert_eq!((stdout.as_str(), code), ("", 0));
+}
+
+#[test]
+fn fmt_stdin() {
+    let (stdout, _, code) = run(&["fmt"], "print  1+2 ;");
+    assert_eq!((stdout.as_str(), code), ("print 1 + 2;\n", 0));
+    // A file which doesn't parse is left alone.
+    let path = write_file("fmt-broken", "print 1 +;");
+    let (_, stderr, code) = run(&["fmt", &path], "");
+    assert!(stderr.contains("cannot format"), "{stderr}");
+    assert_eq!(code, 1);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "print 1 +;");
+}
+
+#[test]
+fn check_without_running() {
+    let path = write_file("check", "fn f(x) = f(x) + 1;\nprint f(1);");
+    let (stdout, stderr, code) = run(&["--check", &path], "");
+    assert_eq!((stdout.as_str(), code), ("", 0));
+    assert!(!stderr.contains("error"), "{stderr}");
+
+    let path = write_file("check-error", "fn f(x) = y;\nprint f(1);");
+    let (_, stderr, code) = run(&["--check", &path], "");
+    assert!(
+        stderr.contains("the variable `y` is not declared"),
+        "{stderr}"
+    );
+    assert_eq!(code, 1);
+
+    // Errors in top-level statements are reported too.
+    let path = write_file(
+        "check-statements",
+        "fn area_rectangle(w, h) = w * h;\nprint x;\nprint area_rectangle + 1;",
+    );
+    let (stdout, stderr, code) = run(&["--check", &path], "");
+    assert!(
+        stderr.contains("the variable `x` is not declared"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("'area_rectangle' is a function"),
+        "{stderr}"
+    );
+    assert_eq!((stdout.as_str(), code), ("", 1));
+}
+
+#[test]
+fn type_errors_at_their_position_in_the_file() {
+    let path = write_file("positions", "fn g(a) = a;\nfn f(x) = x + y;\nprint f(1);");
+    let (_, stderr, code) = run(&["--check", &path], "");
+    assert!(
+        stderr.contains("the variable `y` is not declared\n  --> 27..28"),
+        "{stderr}"
+    );
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn time_with_check() {
+    let path = write_file("time-check", "fn f(x) = x;\nprint f(1);");
+    let (stdout, stderr, code) = run(&["--time", "--check", &path], "");
+    // The names of the phases are padded to 12 columns.
+    let phases: Vec<_> = stderr.lines().map(|line| line[..12].trim_end()).collect();
+    assert_eq!(phases, ["phase", "parse", "type check"], "{stderr}");
+    assert_eq!((stdout.as_str(), code), ("", 0));
+}
+
+#[test]
+fn recursion_at_default_max_depth() {
+    // Deep enough to overflow the stack of the main thread in a debug
+    // build, before the limit of 1000 calls is reached.
+    let path = write_file(
+        "max-depth",
+        "fn f(x) = 1 + (2 * (3 - f(x))); print f(1); print 2;",
+    );
+    let (stdout, stderr, code) = run(&[&path], "");
+    assert!(
+        stderr.contains("maximum recursion depth exceeded while calling `f`"),
+        "{stderr}"
+    );
+    assert_eq!((stdout.as_str(), code), ("2\n", 0));
+}
+
+#[test]
+fn emit_code_only_without_errors() {
+    let path = write_file("emit-error", "fn f(x) = y;\nprint f(1);");
+    for target in ["rust", "wat"] {
+        let (stdout, stderr, code) = run(&["--emit", target, &path], "");
+        assert!(
+            stderr.contains("the variable `y` is not declared"),
+            "{stderr}"
+        );
+        assert_eq!((stdout.as_str(), code), ("", 1));
+    }
+
+    let path = write_file("emit-rust", "print -0 * 1;");
+    let (stdout, _, code) = run(&["--emit", "rust", &path], "");
+    assert!(stdout.contains("Show::show(-0.0 * 1.0)"), "{stdout}");
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn related_note_names_its_file() {
+    let main = write_file("related-main", "fn double(x) = 2 * x; print double(1);");
+    let lib = write_file("related-lib", "fn double(x) = x + x;");
+    let (_, stderr, _) = run(&[&main, &lib], "");
+    let main_name = format!("banana-cli-related-main-{}.banana", std::process::id());
+    assert!(
+        stderr.contains(&format!("{main_name}:3..9 in `double`")),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn log_only_when_verbose() {
+    let path = write_file("verbose", "fn f(x) = x; print f(1);");
+    let (stdout, stderr, _) = run(&[&path], "");
+    assert_eq!((stdout.as_str(), stderr.as_str()), ("1\n", ""));
+
+    let (_, stderr, _) = run(&["--verbose", &path], "");
+    assert!(stderr.contains("WillExecute"), "{stderr}");
+}
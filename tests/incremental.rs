@@ -0,0 +1,153 @@
+//! Checks which queries re-run after an edit, to lock in the incremental
+//! behaviour the span representation is designed for.
+
+use std::collections::HashMap;
+
+use banana::{compile::compile_diagnostics, db::Database, ir::SourceProgram};
+
+const PROGRAM: &str = "\
+fn area_rectangle(w, h) = w * h;
+// circles
+fn area_circle(r) = 3.14 * r * r;
+print area_rectangle(3, 4);
+print area_circle(1);
+";
+
+/// A compiled program which can be edited and recompiled.
+struct Harness {
+    db: Database,
+    source_program: SourceProgram,
+    /// Readable names for the keys of queries run once per function, e.g.
+    /// `type_check_function(area_circle)` for `type_check_function(1)`.
+    names: HashMap<String, String>,
+}
+
+impl Harness {
+    fn new(source_text: &str) -> Self {
+        let mut db = Database::builder().logging(true).build();
+        let source_program = SourceProgram::new(&db, source_text.to_string());
+        compile_diagnostics(&db, source_program);
+        let executed = db.take_logs().executed_queries();
+
+        // The functions are checked in order, so the first compile runs the
+        // queries for each function in that order.
+        let program = banana::parser::parse_statements(&db, source_program);
+        let functions: Vec<_> = program
+            .functions(&db)
+            .iter()
+            .map(|function| function.name(&db).text(&db).clone())
+            .collect();
+        let mut by_query: HashMap<&str, Vec<&String>> = HashMap::new();
+        for key in &executed {
+            by_query.entry(query_name(key)).or_default().push(key);
+        }
+        let mut names = HashMap::new();
+        for (query, keys) in by_query {
+            // Items are keyed by their text, and there may be as many other
+            // items as functions.
+            let by_item = query.starts_with("parse_item") || query == "type_check_statements";
+            if keys.len() == functions.len() && !by_item {
+                for (key, function) in keys.into_iter().zip(&functions) {
+                    names.insert(key.clone(), format!("{query}({function})"));
+                }
+            }
+        }
+        Self {
+            db,
+            source_program,
+            names,
+        }
+    }
+
+    fn edit(&mut self, source_text: &str) {
+        self.source_program
+            .set_text(&mut self.db)
+            .to(source_text.to_string());
+    }
+}
+
+fn query_name(key: &str) -> &str {
+    key.split('(').next().unwrap_or(key)
+}
+
+/// Recompiles and asserts that exactly the `expected` queries re-ran, in any
+/// order.
+fn expect_reexecuted(harness: &mut Harness, expected: &[&str]) {
+    compile_diagnostics(&harness.db, harness.source_program);
+    let mut executed: Vec<_> = harness
+        .db
+        .take_logs()
+        .executed_queries()
+        .into_iter()
+        .map(|key| harness.names.get(&key).cloned().unwrap_or(key))
+        .collect();
+    executed.sort();
+    let mut expected = expected.to_vec();
+    expected.sort();
+    assert_eq!(executed, expected);
+}
+
+#[test]
+fn recompiling_without_edits_runs_nothing() {
+    let mut harness = Harness::new(PROGRAM);
+    expect_reexecuted(&mut harness, &[]);
+}
+
+#[test]
+fn editing_a_body_checks_only_that_function() {
+    let mut harness = Harness::new(PROGRAM);
+    harness.edit(&PROGRAM.replace("3.14 * r * r", "3.14159 * r * r"));
+    expect_reexecuted(
+        &mut harness,
+        &[
+            "split_into_items(0)",
+            "parse_statements(0)",
+            // The edited function is the only new item, interned after the
+            // four items of the original program.
+            "parse_item(4)",
+            "type_check_function(area_circle)",
+            "expression_types(area_circle)",
+            // The statement calling it, which checks the call against its
+            // parameters.
+            "type_check_statements(1)",
+        ],
+    );
+}
+
+#[test]
+fn whitespace_before_a_function_only_reparses() {
+    let mut harness = Harness::new(PROGRAM);
+    harness.edit(&PROGRAM.replace("fn area_circle", "\n\n    fn area_circle"));
+    expect_reexecuted(
+        &mut harness,
+        &["split_into_items(0)", "parse_statements(0)"],
+    );
+}
+
+#[test]
+fn editing_a_comment_only_reparses() {
+    let mut harness = Harness::new(PROGRAM);
+    harness.edit(&PROGRAM.replace("// circles", "// area of a circle of radius r"));
+    expect_reexecuted(
+        &mut harness,
+        &["split_into_items(0)", "parse_statements(0)"],
+    );
+}
+
+#[test]
+fn editing_a_statement_parses_only_that_statement() {
+    let mut harness = Harness::new(PROGRAM);
+    harness.edit(&PROGRAM.replace("area_circle(1)", "area_circle(2)"));
+    expect_reexecuted(
+        &mut harness,
+        &[
+            "split_into_items(0)",
+            "parse_statements(0)",
+            // Tried as a function first.
+            "parse_item(4)",
+            "parse_item_statements(4)",
+            // Only the edited statement is checked again.
+            "type_check_statements(2)",
+        ],
+    );
+}
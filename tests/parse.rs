@@ -0,0 +1,44 @@
+//! Parsing and checking programs through the library's public API.
+
+use banana::{
+    compile::compile_diagnostics,
+    db::Database,
+    ir::{program_eq_ignoring_spans, Diagnostics, SourceProgram},
+    parser::parse_statements,
+};
+
+#[test]
+fn parse_program() {
+    let db = Database::default();
+    let source = SourceProgram::new(&db, "fn double(x) = 2 * x;\nprint double(3);".to_string());
+    let program = parse_statements(&db, source);
+    assert_eq!(program.functions(&db).len(), 1);
+    assert_eq!(program.statements(&db).len(), 1);
+    assert!(parse_statements::accumulated::<Diagnostics>(&db, source).is_empty());
+
+    let reformatted = SourceProgram::new(
+        &db,
+        "fn double(x) =\n    2 * x;\n\nprint double( 3 );".to_string(),
+    );
+    assert!(program_eq_ignoring_spans(
+        &db,
+        program,
+        parse_statements(&db, reformatted)
+    ));
+}
+
+#[test]
+fn report_diagnostics() {
+    let db = Database::default();
+    let source = SourceProgram::new(&db, "print 1 +;".to_string());
+    let diagnostics = compile_diagnostics(&db, source);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!((diagnostics[0].start, diagnostics[0].end), (9, 10));
+
+    let source = SourceProgram::new(&db, "fn f(x) = y;".to_string());
+    let messages: Vec<_> = compile_diagnostics(&db, source)
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect();
+    assert_eq!(messages, ["the variable `y` is not declared"]);
+}
@@ -0,0 +1,98 @@
+//! The scoping rules of `let`: a binding is in scope in its body only, it
+//! shadows variables of the same name, and its value can't refer to it.
+
+use banana::{
+    compile::{compile_full, Backend, CompileMode},
+    db::Database,
+    eval::EvalOptions,
+    ir::SourceProgram,
+};
+
+/// Compiles the program and runs it if it compiled. Returns the messages of
+/// the compile errors and the printed lines.
+fn run(source_text: &str) -> (Vec<String>, Vec<String>) {
+    let db = Database::default();
+    let source_program = SourceProgram::new(&db, source_text.to_string());
+    let result = compile_full(
+        &db,
+        source_program,
+        CompileMode::Run(EvalOptions::default(), Backend::Ast),
+    );
+    let messages = result
+        .diagnostics
+        .into_iter()
+        .map(|(_, diagnostic)| diagnostic.message)
+        .collect();
+    let output = result
+        .output
+        .unwrap_or_default()
+        .into_iter()
+        .map(|line| line.text)
+        .collect();
+    (messages, output)
+}
+
+fn assert_undeclared(source_text: &str, variable: &str) {
+    let (messages, output) = run(source_text);
+    assert_eq!(
+        messages,
+        [format!("the variable `{variable}` is not declared")],
+        "in {source_text:?}"
+    );
+    assert!(output.is_empty());
+}
+
+#[test]
+fn binding_is_in_scope_in_body() {
+    let (messages, output) = run("
+        fn f(a) = let b = a + 1 in a * b;
+        print f(2);
+        print let x = 2 in let y = x + 1 in x * y;
+        ");
+    assert!(messages.is_empty(), "{messages:?}");
+    assert_eq!(output, ["6", "6"]);
+}
+
+#[test]
+fn binding_does_not_leak() {
+    assert_undeclared("fn f(a) = (let b = a + 1 in b) * b; print f(1);", "b");
+    assert_undeclared("print let x = 1 in x; print x;", "x");
+    assert_undeclared("fn f() = let x = 1 in x; fn g() = x; print g();", "x");
+    assert_undeclared("fn f(y) = (let x = 1 in x) + x; print f(1);", "x");
+}
+
+#[test]
+fn binding_shadows() {
+    let (messages, output) = run("
+        fn f(x) = let x = x * 10 in let x = x + 1 in x;
+        print f(2);
+        print let y = 1 in (let y = 2 in y) + y;
+        print let b = true in let b = 3 in b + 1;
+        ");
+    assert!(messages.is_empty(), "{messages:?}");
+    assert_eq!(output, ["21", "3", "4"]);
+}
+
+#[test]
+fn use_before_definition() {
+    assert_undeclared("print let x = x + 1 in x;", "x");
+    assert_undeclared("print let y = x in let x = 1 in y;", "x");
+    assert_undeclared("fn f() = let x = x + 1 in x; print f();", "x");
+    assert_undeclared("fn f() = let y = x in let x = 1 in y; print f();", "x");
+}
+
+#[test]
+fn lambdas_and_functions_do_not_see_bindings() {
+    assert_undeclared(r"print let x = 1 in (\(y) -> x + y)(2);", "x");
+    assert_undeclared("fn f() = x; print let x = 1 in f();", "x");
+    assert_undeclared(r"fn f() = let x = 1 in (\(y) -> x + y)(2); print f();", "x");
+    assert_undeclared("fn f() = x; fn g() = let x = 1 in f(); print g();", "x");
+}
+
+#[test]
+fn binding_keeps_the_type_of_its_value() {
+    let (messages, _) = run("fn f() = let b = true in b + 1; print f();");
+    assert_eq!(messages, ["cannot apply `+` to bool and number"]);
+    let (messages, _) = run("print let b = true in b + 1;");
+    assert_eq!(messages, ["cannot apply `+` to bool and number"]);
+}